@@ -0,0 +1,153 @@
+// criterion benchmarks for the matrix and network hot paths.
+//   cargo bench -- --save-baseline before   (on the old code)
+//   cargo bench -- --baseline before        (on the new code, prints the change)
+// `cargo bench -- gemm` etc. runs a single group
+use std::hint::black_box;
+use std::path::Path;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use kek::nn::{self, NN};
+use kek::tools::activations;
+use kek::tools::matrix::*;
+
+// (rows of a, shared size, columns of b): square sizes plus the dense layer
+// products of the mnist net for a single sample and for batches of 16 and 64
+const GEMM_SHAPES: [(usize, usize, usize); 6] = [
+    (32, 32, 32),
+    (128, 128, 128),
+    (256, 256, 256),
+    (32, 784, 1),
+    (32, 784, 16),
+    (32, 784, 64),
+];
+
+fn gemm(c: &mut Criterion) {
+    let mut group = c.benchmark_group("gemm");
+    for (n, k, m) in GEMM_SHAPES {
+        let a = Mat::rand_mat(n, k, -1.0, 1.0);
+        let b = Mat::rand_mat(k, m, -1.0, 1.0);
+        group.throughput(Throughput::Elements((2 * n * k * m) as u64));
+        group.bench_function(
+            BenchmarkId::from_parameter(format!("{n}x{k}x{m}")),
+            |bench| bench.iter(|| black_box(&a) * black_box(&b)),
+        );
+    }
+    group.finish();
+}
+
+fn matmul_block_size(c: &mut Criterion) {
+    let mut group = c.benchmark_group("matmul_block_size");
+    let a = Mat::rand_mat(256, 256, -1.0, 1.0);
+    let b = Mat::rand_mat(256, 256, -1.0, 1.0);
+    group.throughput(Throughput::Elements(2 * 256 * 256 * 256));
+    for block_size in [8, 16, 32, 64, 128, 256] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(block_size),
+            &block_size,
+            |bench, &block_size| bench.iter(|| black_box(&a).mul_blocked(&b, block_size)),
+        );
+    }
+    group.finish();
+}
+
+fn element_wise(c: &mut Criterion) {
+    let mut group = c.benchmark_group("element_wise");
+    // a batch of 64 mnist images
+    let a = Mat::rand_mat(784, 64, -1.0, 1.0);
+    let b = Mat::rand_mat(784, 64, -1.0, 1.0);
+    let column = Mat::rand_mat(784, 1, -1.0, 1.0);
+    group.throughput(Throughput::Elements(784 * 64));
+    group.bench_function("add", |bench| bench.iter(|| black_box(&a) + black_box(&b)));
+    group.bench_function("sub", |bench| bench.iter(|| black_box(&a) - black_box(&b)));
+    group.bench_function("ele_mul", |bench| bench.iter(|| black_box(&a).ele_mul(&b)));
+    group.bench_function("scaler_mul", |bench| {
+        bench.iter(|| black_box(&a).scaler_mul(0.5))
+    });
+    group.bench_function("map_sigmoid", |bench| {
+        bench.iter(|| black_box(&a).map(activations::sigmoid))
+    });
+    group.bench_function("add_column", |bench| {
+        bench.iter(|| black_box(&a).add_column(&column))
+    });
+    group.bench_function("sum_columns", |bench| {
+        bench.iter(|| black_box(&a).sum_columns())
+    });
+    group.finish();
+}
+
+fn transpose(c: &mut Criterion) {
+    let mut group = c.benchmark_group("transpose");
+    for (row, col) in [(784, 64), (256, 256), (32, 784)] {
+        let a = Mat::rand_mat(row, col, -1.0, 1.0);
+        group.throughput(Throughput::Elements((row * col) as u64));
+        group.bench_function(
+            BenchmarkId::from_parameter(format!("{row}x{col}")),
+            |bench| bench.iter(|| black_box(&a).transpose()),
+        );
+    }
+    group.finish();
+}
+
+// one hot label per sample
+fn batch(n: usize) -> (Vec<Mat>, Vec<Mat>) {
+    let x = (0..n).map(|_| Mat::rand_mat(784, 1, 0.0, 1.0)).collect();
+    let y = (0..n)
+        .map(|i| {
+            let mut y = Mat::new(10, 1);
+            y[(i % 10, 0)] = 1.0;
+            y
+        })
+        .collect();
+    (x, y)
+}
+
+// forward alone and forward + backward + update (train_batch) of the default mnist net
+fn network(c: &mut Criterion) {
+    let mut group = c.benchmark_group("network");
+    let mut nn = NN::new(784, 32, 10);
+    for n in [1, 16, 64] {
+        let (x, y) = batch(n);
+        let stacked = Mat::hstack(&x);
+        group.throughput(Throughput::Elements(n as u64));
+        group.bench_function(BenchmarkId::new("forward", n), |bench| {
+            bench.iter(|| nn.forward(black_box(&stacked)))
+        });
+        group.bench_function(BenchmarkId::new("train_batch", n), |bench| {
+            bench.iter(|| nn.train_batch(black_box(&x), black_box(&y)))
+        });
+    }
+    group.finish();
+}
+
+// one epoch over mnist_train.csv in the working directory with batches of 16,
+// skipped when the file isn't there
+fn mnist_epoch(c: &mut Criterion) {
+    let path = "mnist_train.csv";
+    if !Path::new(path).exists() {
+        eprintln!("mnist_epoch: {path} not found, skipping");
+        return;
+    }
+    let (x, y) = nn::parse_mnist(path);
+    let mut group = c.benchmark_group("mnist_epoch");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(x.len() as u64));
+    group.bench_function("dense_32", |bench| {
+        bench.iter(|| {
+            let mut nn = NN::new(784, 32, 10);
+            nn.train(&x, &y, None, 1, 16, &mut [])
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    gemm,
+    matmul_block_size,
+    element_wise,
+    transpose,
+    network,
+    mnist_epoch
+);
+criterion_main!(benches);
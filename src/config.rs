@@ -0,0 +1,570 @@
+use std::fs;
+use std::path::Path;
+
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+
+use crate::data::augment::Augmenter;
+use crate::data::loader::DataLoader;
+use crate::data::split;
+use crate::data::transform::{Pipeline, Transform};
+use crate::layers::activation::{Activation, Prelu};
+use crate::layers::batch_norm::BatchNorm1d;
+use crate::layers::conv::{Conv2d, ImageShape};
+use crate::layers::dense::Dense;
+use crate::layers::dropout::Dropout;
+use crate::layers::layer_norm::LayerNorm;
+use crate::layers::pool::{AvgPool2d, Flatten, GlobalAvgPool, MaxPool2d};
+use crate::layers::Layer;
+use crate::metrics;
+use crate::nn::callbacks::{Callback, LrScheduler, Schedule};
+use crate::nn::early_stopping::EarlyStopping;
+use crate::nn::loss::Loss;
+use crate::nn::optim::{Optimizer, OptimizerKind};
+use crate::nn::regularization::{GradientClipping, Regularization};
+use crate::nn::{self, EpochMetrics, NN};
+use crate::tools::matrix::*;
+
+// A model and how to train it, read from a .toml or .json file. everything but the
+// architecture has a default, the resolved config (defaults filled in) is kept by the
+// built NN and ends up in its checkpoints. enums use serde's default representation:
+//   [model]
+//   input = 784
+//   loss = "SoftmaxCrossEntropy"
+//   layers = [{ Dense = { units = 32, init = "He" } }, { Activation = "Relu" }, { Dense = { units = 10 } }]
+//   [training]
+//   optimizer = { Adam = { beta1 = 0.9, beta2 = 0.999, eps = 1e-8 } }
+//   lr = 0.001
+//   [data]
+//   train = "mnist_train.csv"
+// see configs/ for complete files
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Config {
+    pub model: ModelConfig,
+    #[serde(default)]
+    pub training: TrainingConfig,
+    // only needed by Config::train
+    #[serde(default)]
+    pub data: Option<DataConfig>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ModelConfig {
+    pub input: usize,
+    // (channels, height, width) of the input for convolution and pooling layers
+    #[serde(default)]
+    pub image: Option<ImageShape>,
+    pub layers: Vec<LayerConfig>,
+    #[serde(default = "default_loss")]
+    pub loss: Loss,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Init {
+    // Dense::new
+    #[default]
+    Uniform,
+    // Dense::scaled
+    Scaled,
+    // Dense::xavier
+    Xavier,
+    // Dense::he
+    He,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ActivationConfig {
+    Relu,
+    Sigmoid,
+    Tanh,
+    LeakyRelu { slope: f64 },
+    Elu { alpha: f64 },
+    Selu,
+    Gelu,
+    Swish,
+    Mish,
+    Softplus,
+    Softsign,
+    HardSigmoid,
+}
+
+// one entry of the layer list, sizes follow from the layer before
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LayerConfig {
+    Dense {
+        units: usize,
+        #[serde(default)]
+        init: Init,
+    },
+    Activation(ActivationConfig),
+    Prelu {
+        #[serde(default = "default_prelu_slope")]
+        slope: f64,
+    },
+    // inverted dropout with drop probability p
+    Dropout {
+        p: f64,
+    },
+    BatchNorm,
+    LayerNorm,
+    Conv2d {
+        filters: usize,
+        kernel: usize,
+        #[serde(default = "default_stride")]
+        stride: usize,
+        #[serde(default)]
+        padding: usize,
+    },
+    // stride defaults to the kernel size
+    MaxPool2d {
+        kernel: usize,
+        #[serde(default)]
+        stride: Option<usize>,
+    },
+    AvgPool2d {
+        kernel: usize,
+        #[serde(default)]
+        stride: Option<usize>,
+    },
+    GlobalAvgPool,
+    Flatten,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrainingConfig {
+    pub optimizer: OptimizerKind,
+    pub lr: f64,
+    pub schedule: Option<Schedule>,
+    pub epochs: i32,
+    pub batch_size: usize,
+    pub seed: u64,
+    pub regularization: Option<Regularization>,
+    pub clipping: Option<GradientClipping>,
+    pub early_stopping: Option<EarlyStopping>,
+}
+
+impl Default for TrainingConfig {
+    fn default() -> Self {
+        TrainingConfig {
+            optimizer: OptimizerKind::Sgd,
+            lr: 0.1,
+            schedule: None,
+            epochs: 10,
+            batch_size: 16,
+            seed: 42,
+            regularization: None,
+            clipping: None,
+            early_stopping: None,
+        }
+    }
+}
+
+// a mnist style csv (label, then the pixels) like nn::parse_mnist reads
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DataConfig {
+    pub train: String,
+    // labeled csv scored after training
+    #[serde(default)]
+    pub test: Option<String>,
+    // share of train held out for validation, same class balance in both parts
+    #[serde(default = "default_val_split")]
+    pub val_split: f64,
+    // random shifts and rotations, needs model.image
+    #[serde(default)]
+    pub augment: bool,
+    // fitted on the training part
+    #[serde(default = "default_transform")]
+    pub transform: Vec<Transform>,
+}
+
+fn default_loss() -> Loss {
+    Loss::SigmoidCrossEntropy
+}
+fn default_prelu_slope() -> f64 {
+    0.25
+}
+fn default_stride() -> usize {
+    1
+}
+fn default_val_split() -> f64 {
+    0.1
+}
+fn default_transform() -> Vec<Transform> {
+    vec![Transform::min_max(0.0, 0.9)]
+}
+
+// what Config::train returns
+pub struct Trained {
+    pub nn: NN,
+    // fitted on the training data, apply it to anything fed to nn
+    pub transform: Pipeline,
+    pub history: Vec<EpochMetrics>,
+    // metrics on data.test
+    pub test: Option<metrics::Report>,
+}
+
+#[derive(Clone, Copy)]
+enum Shape {
+    Flat(usize),
+    Image(ImageShape),
+}
+
+impl Shape {
+    fn size(&self) -> usize {
+        match *self {
+            Shape::Flat(size) => size,
+            Shape::Image((c, h, w)) => c * h * w,
+        }
+    }
+    fn image(&self, layer: &str) -> ImageShape {
+        match *self {
+            Shape::Image(image) => image,
+            Shape::Flat(_) => panic!("{layer} needs an image input, set model.image ;("),
+        }
+    }
+}
+
+impl ModelConfig {
+    pub fn build(&self) -> NN {
+        let mut shape = match self.image {
+            Some((c, h, w)) => {
+                assert_eq!(
+                    c * h * w,
+                    self.input,
+                    "model.image doesn't match model.input"
+                );
+                Shape::Image((c, h, w))
+            }
+            None => Shape::Flat(self.input),
+        };
+        let mut layers: Vec<Box<dyn Layer>> = vec![];
+        for config in &self.layers {
+            let (layer, next): (Box<dyn Layer>, Shape) = match *config {
+                LayerConfig::Dense { units, init } => {
+                    let size = shape.size();
+                    let dense = match init {
+                        Init::Uniform => Dense::new(size, units),
+                        Init::Scaled => Dense::scaled(size, units),
+                        Init::Xavier => Dense::xavier(size, units),
+                        Init::He => Dense::he(size, units),
+                    };
+                    (Box::new(dense), Shape::Flat(units))
+                }
+                LayerConfig::Activation(function) => (Box::new(activation(function)), shape),
+                LayerConfig::Prelu { slope } => (Box::new(Prelu::new(slope)), shape),
+                LayerConfig::Dropout { p } => (Box::new(Dropout::new(p)), shape),
+                LayerConfig::BatchNorm => (Box::new(BatchNorm1d::new(shape.size())), shape),
+                LayerConfig::LayerNorm => (Box::new(LayerNorm::new(shape.size())), shape),
+                LayerConfig::Conv2d {
+                    filters,
+                    kernel,
+                    stride,
+                    padding,
+                } => {
+                    let conv = Conv2d::new(shape.image("Conv2d"), filters, kernel)
+                        .stride(stride)
+                        .padding(padding);
+                    let output = conv.output();
+                    (Box::new(conv), Shape::Image(output))
+                }
+                LayerConfig::MaxPool2d { kernel, stride } => {
+                    let mut pool = MaxPool2d::new(shape.image("MaxPool2d"), kernel);
+                    if let Some(stride) = stride {
+                        pool = pool.stride(stride);
+                    }
+                    let output = pool.output();
+                    (Box::new(pool), Shape::Image(output))
+                }
+                LayerConfig::AvgPool2d { kernel, stride } => {
+                    let mut pool = AvgPool2d::new(shape.image("AvgPool2d"), kernel);
+                    if let Some(stride) = stride {
+                        pool = pool.stride(stride);
+                    }
+                    let output = pool.output();
+                    (Box::new(pool), Shape::Image(output))
+                }
+                LayerConfig::GlobalAvgPool => {
+                    let image = shape.image("GlobalAvgPool");
+                    (Box::new(GlobalAvgPool::new(image)), Shape::Flat(image.0))
+                }
+                LayerConfig::Flatten => (Box::new(Flatten::new()), Shape::Flat(shape.size())),
+            };
+            layers.push(layer);
+            shape = next;
+        }
+        NN::from_layers(self.input, layers, self.loss)
+    }
+}
+
+fn activation(config: ActivationConfig) -> Activation {
+    match config {
+        ActivationConfig::Relu => Activation::relu(),
+        ActivationConfig::Sigmoid => Activation::sigmoid(),
+        ActivationConfig::Tanh => Activation::tanh(),
+        ActivationConfig::LeakyRelu { slope } => Activation::leaky_relu(slope),
+        ActivationConfig::Elu { alpha } => Activation::elu(alpha),
+        ActivationConfig::Selu => Activation::selu(),
+        ActivationConfig::Gelu => Activation::gelu(),
+        ActivationConfig::Swish => Activation::swish(),
+        ActivationConfig::Mish => Activation::mish(),
+        ActivationConfig::Softplus => Activation::softplus(),
+        ActivationConfig::Softsign => Activation::softsign(),
+        ActivationConfig::HardSigmoid => Activation::hard_sigmoid(),
+    }
+}
+
+fn is_toml(path: &str) -> bool {
+    Path::new(path).extension().is_some_and(|ext| ext == "toml")
+}
+
+impl Config {
+    // .toml files are read as toml, anything else as json
+    pub fn load(path: &str) -> Config {
+        let text = fs::read_to_string(path).expect("couldn't read config file ;(");
+        if is_toml(path) {
+            toml::from_str(&text).unwrap_or_else(|e| panic!("couldn't parse {path} ;( {e}"))
+        } else {
+            serde_json::from_str(&text).unwrap_or_else(|e| panic!("couldn't parse {path} ;( {e}"))
+        }
+    }
+    pub fn save(&self, path: &str) {
+        let text = if is_toml(path) {
+            toml::to_string_pretty(self).expect("couldn't write config as toml ;(")
+        } else {
+            serde_json::to_string_pretty(self).expect("couldn't write config as json ;(")
+        };
+        fs::write(path, text).expect("couldn't write config file ;(");
+    }
+    // the model with optimizer, lr, seed, regularization, clipping and early stopping set,
+    // the schedule is a callback and only used by train. the seed also draws the initial weights
+    pub fn build(&self) -> NN {
+        let training = &self.training;
+        let mut nn = Mat::seeded(training.seed, || self.model.build());
+        nn.set_seed(training.seed);
+        nn.set_lr(training.lr);
+        nn.set_optimizer(Optimizer::new(training.optimizer.clone()));
+        nn.set_regularization(training.regularization.clone());
+        nn.set_gradient_clipping(training.clipping);
+        nn.set_early_stopping(training.early_stopping.clone());
+        nn.set_config(Some(self.clone()));
+        nn
+    }
+    // builds the model and trains it on data.train, callbacks run besides the lr schedule.
+    // the returned model is in eval mode
+    pub fn train(&self, callbacks: Vec<Box<dyn Callback>>) -> Trained {
+        let data = self.data.as_ref().expect("config has no data section ;(");
+        let (x_all, y_all) = nn::parse_mnist(&data.train);
+        let mut trained = self.train_on(&x_all, &y_all, callbacks);
+        trained.test = data.test.as_ref().map(|path| {
+            let (x_test, y_test) = nn::parse_mnist(path);
+            let x_test = trained.transform.apply_all(&x_test);
+            trained.nn.evaluate(&x_test, &y_test)
+        });
+        trained
+    }
+    // like train but on already loaded samples instead of data.train, data.test is ignored.
+    // used to train many configs on the same data without parsing it again
+    pub fn train_on(
+        &self,
+        x_all: &[Mat],
+        y_all: &[Mat],
+        mut callbacks: Vec<Box<dyn Callback>>,
+    ) -> Trained {
+        let data = self.data.as_ref().expect("config has no data section ;(");
+        let training = &self.training;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(training.seed);
+        let holdout = split::stratified_holdout(y_all, data.val_split, &mut rng);
+        let (x_train, y_train) = (
+            split::select(x_all, &holdout.train),
+            split::select(y_all, &holdout.train),
+        );
+        let (x_val, y_val) = (
+            split::select(x_all, &holdout.test),
+            split::select(y_all, &holdout.test),
+        );
+        let mut transform = Pipeline::new(data.transform.clone());
+        let x_train = transform.fit_apply(&x_train);
+        let x_val = transform.apply_all(&x_val);
+
+        let mut nn = self.build();
+        let mut loader =
+            DataLoader::new(&x_train, &y_train, training.batch_size).seed(training.seed);
+        if data.augment {
+            let (_, h, w) = self
+                .model
+                .image
+                .expect("augment needs model.image to know the image size ;(");
+            loader = loader.augment(Augmenter::new(w, h).affine(2.0, 10.0, (0.9, 1.1), 10.0));
+        }
+        if let Some(schedule) = &training.schedule {
+            callbacks.push(Box::new(LrScheduler::new(schedule.clone())));
+        }
+        let validation = (!x_val.is_empty()).then_some((&x_val[..], &y_val[..]));
+        let history = nn.train_loader(&mut loader, validation, training.epochs, &mut callbacks);
+        nn.eval_mode();
+        Trained {
+            nn,
+            transform,
+            history,
+            test: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{ActivationConfig, Config, LayerConfig, ModelConfig, TrainingConfig};
+    use crate::data::loader::DataLoader;
+    use crate::nn::checkpoint::{Checkpoint, CheckpointConfig};
+    use crate::nn::loss::Loss;
+    use crate::tools::matrix::*;
+
+    fn mlp(seed: u64) -> Config {
+        Config {
+            model: ModelConfig {
+                input: 3,
+                image: None,
+                layers: vec![
+                    LayerConfig::Dense {
+                        units: 4,
+                        init: Default::default(),
+                    },
+                    LayerConfig::Activation(ActivationConfig::Tanh),
+                    LayerConfig::Dense {
+                        units: 2,
+                        init: Default::default(),
+                    },
+                ],
+                loss: Loss::MeanSquaredError,
+            },
+            training: TrainingConfig {
+                seed,
+                ..Default::default()
+            },
+            data: None,
+        }
+    }
+
+    fn image_model(layers: Vec<LayerConfig>) -> ModelConfig {
+        ModelConfig {
+            input: 64,
+            image: Some((1, 8, 8)),
+            layers,
+            loss: Loss::SoftmaxCrossEntropy,
+        }
+    }
+
+    #[test]
+    fn toml_and_json_round_trip() {
+        let config = Config::load("configs/mnist_mlp.toml");
+        let json = serde_json::to_string(&config).unwrap();
+        for ext in ["toml", "json"] {
+            let path =
+                std::env::temp_dir().join(format!("kek_config_{}.{ext}", std::process::id()));
+            let path = path.to_str().unwrap();
+            config.save(path);
+            let loaded = Config::load(path);
+            std::fs::remove_file(path).unwrap();
+            assert_eq!(loaded.model, config.model);
+            // Config itself isn't PartialEq, compare what it serializes to
+            assert_eq!(serde_json::to_string(&loaded).unwrap(), json);
+        }
+    }
+
+    #[test]
+    fn shapes_follow_through_conv_pool_and_flatten() {
+        // 1x8x8 -> conv 4x8x8 -> pool 4x4x4 -> 64 -> 5
+        let mut nn = image_model(vec![
+            LayerConfig::Conv2d {
+                filters: 4,
+                kernel: 3,
+                stride: 1,
+                padding: 1,
+            },
+            LayerConfig::MaxPool2d {
+                kernel: 2,
+                stride: None,
+            },
+            LayerConfig::Flatten,
+            LayerConfig::Dense {
+                units: 5,
+                init: Default::default(),
+            },
+        ])
+        .build();
+        assert_eq!(nn.output_shape(), 5);
+        assert_eq!(nn.forward(&Mat::new(64, 3)).shape(), (5, 3));
+
+        // 1x8x8 -> conv 3x3x3 -> 3 -> 2
+        let mut nn = image_model(vec![
+            LayerConfig::Conv2d {
+                filters: 3,
+                kernel: 3,
+                stride: 2,
+                padding: 0,
+            },
+            LayerConfig::GlobalAvgPool,
+            LayerConfig::Dense {
+                units: 2,
+                init: Default::default(),
+            },
+        ])
+        .build();
+        assert_eq!(nn.output_shape(), 2);
+        assert_eq!(nn.forward(&Mat::new(64, 2)).shape(), (2, 2));
+    }
+
+    #[test]
+    #[should_panic(expected = "model.image doesn't match model.input")]
+    fn image_has_to_match_the_input() {
+        let mut model = image_model(vec![LayerConfig::Flatten]);
+        model.input = 63;
+        model.build();
+    }
+
+    #[test]
+    fn seed_draws_the_initial_weights() {
+        let weights = |seed| mlp(seed).build().weights();
+        let same = weights(7);
+        assert_eq!(same.len(), 4);
+        for (a, b) in same.iter().zip(&weights(7)) {
+            assert_eq!(a.as_slice(), b.as_slice());
+        }
+        assert_ne!(same[0].as_slice(), weights(8)[0].as_slice());
+    }
+
+    #[test]
+    fn checkpoints_and_model_files_carry_the_config() {
+        let config = mlp(3);
+        let json = serde_json::to_string(&config).unwrap();
+        let x: Vec<Mat> = (0..8)
+            .map(|i| Mat::from_vec(vec![i as f64 / 8.0, 1.0, -0.5], 3, 1))
+            .collect();
+        let y: Vec<Mat> = x.iter().map(|xi| xi.rows(0, 2)).collect();
+
+        let mut nn = config.build();
+        let path =
+            std::env::temp_dir().join(format!("kek_config_ckpt_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        nn.set_checkpoint(Some(CheckpointConfig::new(path, 1)));
+        let mut loader = DataLoader::new(&x, &y, 4);
+        nn.train_loader(&mut loader, None, 1, &mut []);
+        let checkpoint = Checkpoint::load(path);
+        std::fs::remove_file(path).unwrap();
+        let saved = checkpoint.config.expect("checkpoint lost the config");
+        assert_eq!(serde_json::to_string(&saved).unwrap(), json);
+
+        // a model file is the config next to the weights, rebuilding gives the same network
+        let file = serde_json::to_string(&(&config, nn.weights())).unwrap();
+        let (loaded, weights): (Config, Vec<Mat>) = serde_json::from_str(&file).unwrap();
+        assert_eq!(serde_json::to_string(&loaded).unwrap(), json);
+        let mut rebuilt = loaded.build();
+        rebuilt.set_weights(weights);
+        let batch = Mat::hstack(&x);
+        assert_eq!(
+            rebuilt.forward(&batch).as_slice(),
+            nn.forward(&batch).as_slice()
+        );
+    }
+}
@@ -0,0 +1,7 @@
+pub mod augment;
+pub mod image_folder;
+pub mod loader;
+pub mod sequence;
+pub mod split;
+pub mod text;
+pub mod transform;
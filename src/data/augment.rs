@@ -0,0 +1,392 @@
+use rand::Rng;
+
+use crate::tools::matrix::*;
+
+// A single random image transform. Inputs are (channels * height * width, 1) columns laid out
+// like data::image_folder::ImageFolderDataset, so plain 28x28 mnist columns work as they are.
+// angles are in degrees, shifts are in pixels.
+#[derive(Clone, Debug)]
+pub enum Augmentation {
+    // rotate, scale and shear around the image center, then shift
+    Affine {
+        max_shift: f64,
+        max_rotation: f64,
+        scale: (f64, f64),
+        max_shear: f64,
+    },
+    // Simard et al. elastic distortion: a random displacement field smoothed by a gaussian
+    // of width sigma and scaled by alpha pixels
+    Elastic {
+        alpha: f64,
+        sigma: f64,
+    },
+    // add N(0, std^2) to every pixel
+    GaussianNoise {
+        std: f64,
+    },
+    // with the given probability fill a random rectangle covering `area` of the image with `value`
+    RandomErasing {
+        probability: f64,
+        area: (f64, f64),
+        value: f64,
+    },
+}
+
+// Ordered list of augmentations applied one after another to images of a fixed size.
+// all randomness comes from the rng passed in, so a seeded rng gives reproducible output.
+#[derive(Clone, Debug)]
+pub struct Augmenter {
+    width: usize,
+    height: usize,
+    steps: Vec<Augmentation>,
+}
+
+impl Augmenter {
+    pub fn new(width: usize, height: usize) -> Augmenter {
+        Augmenter {
+            width,
+            height,
+            steps: vec![],
+        }
+    }
+    pub fn push(mut self, step: Augmentation) -> Augmenter {
+        self.steps.push(step);
+        self
+    }
+    pub fn affine(
+        self,
+        max_shift: f64,
+        max_rotation: f64,
+        scale: (f64, f64),
+        max_shear: f64,
+    ) -> Augmenter {
+        assert!(scale.0 > 0.0 && scale.0 <= scale.1);
+        self.push(Augmentation::Affine {
+            max_shift,
+            max_rotation,
+            scale,
+            max_shear,
+        })
+    }
+    pub fn elastic(self, alpha: f64, sigma: f64) -> Augmenter {
+        assert!(sigma > 0.0);
+        self.push(Augmentation::Elastic { alpha, sigma })
+    }
+    pub fn gaussian_noise(self, std: f64) -> Augmenter {
+        self.push(Augmentation::GaussianNoise { std })
+    }
+    pub fn random_erasing(self, probability: f64, area: (f64, f64), value: f64) -> Augmenter {
+        assert!(area.0 > 0.0 && area.0 <= area.1 && area.1 <= 1.0);
+        self.push(Augmentation::RandomErasing {
+            probability,
+            area,
+            value,
+        })
+    }
+    pub fn steps(&self) -> &Vec<Augmentation> {
+        &self.steps
+    }
+    pub fn apply<R: Rng>(&self, x: &Mat, rng: &mut R) -> Mat {
+        let plane = self.width * self.height;
+        let len = x.as_slice().len();
+        assert_eq!(
+            len % plane,
+            0,
+            "input doesn't match the augmenter image size"
+        );
+        let mut out = x.clone();
+        for step in &self.steps {
+            out = match step {
+                Augmentation::Affine {
+                    max_shift,
+                    max_rotation,
+                    scale,
+                    max_shear,
+                } => {
+                    let angle = uniform(rng, *max_rotation).to_radians();
+                    let shear = uniform(rng, *max_shear).to_radians();
+                    let s = if scale.0 < scale.1 {
+                        rng.gen_range(scale.0..scale.1)
+                    } else {
+                        scale.0
+                    };
+                    let tx = uniform(rng, *max_shift);
+                    let ty = uniform(rng, *max_shift);
+                    self.affine_warp(&out, angle, shear, s, (tx, ty))
+                }
+                Augmentation::Elastic { alpha, sigma } => {
+                    self.elastic_warp(&out, *alpha, *sigma, rng)
+                }
+                Augmentation::GaussianNoise { std } => {
+                    for ele in out.as_mut_slice().iter_mut() {
+                        *ele += std * gaussian(rng);
+                    }
+                    out
+                }
+                Augmentation::RandomErasing {
+                    probability,
+                    area,
+                    value,
+                } => {
+                    if rng.gen::<f64>() < *probability {
+                        self.erase(&mut out, *area, *value, rng);
+                    }
+                    out
+                }
+            };
+        }
+        out
+    }
+    pub fn apply_all<R: Rng>(&self, x: &[Mat], rng: &mut R) -> Vec<Mat> {
+        x.iter().map(|sample| self.apply(sample, rng)).collect()
+    }
+
+    // inverse mapping: every output pixel looks up where it came from in the input
+    fn affine_warp(&self, x: &Mat, angle: f64, shear: f64, scale: f64, shift: (f64, f64)) -> Mat {
+        // forward transform is rotation * shear * scale, invert the 2x2 once
+        let (sin, cos) = libm::sincos(angle);
+        let tan = libm::tan(shear);
+        let a = [
+            [cos * scale, (cos * tan - sin) * scale],
+            [sin * scale, (sin * tan + cos) * scale],
+        ];
+        let det = a[0][0] * a[1][1] - a[0][1] * a[1][0];
+        let inv = [
+            [a[1][1] / det, -a[0][1] / det],
+            [-a[1][0] / det, a[0][0] / det],
+        ];
+
+        let cx = (self.width as f64 - 1.0) / 2.0;
+        let cy = (self.height as f64 - 1.0) / 2.0;
+        self.remap(x, |px, py| {
+            let dx = px - cx - shift.0;
+            let dy = py - cy - shift.1;
+            (
+                inv[0][0] * dx + inv[0][1] * dy + cx,
+                inv[1][0] * dx + inv[1][1] * dy + cy,
+            )
+        })
+    }
+    fn elastic_warp<R: Rng>(&self, x: &Mat, alpha: f64, sigma: f64, rng: &mut R) -> Mat {
+        let plane = self.width * self.height;
+        let mut dx: Vec<f64> = (0..plane).map(|_| rng.gen_range(-1.0..1.0)).collect();
+        let mut dy: Vec<f64> = (0..plane).map(|_| rng.gen_range(-1.0..1.0)).collect();
+        dx = self.gaussian_blur(&dx, sigma);
+        dy = self.gaussian_blur(&dy, sigma);
+        let width = self.width;
+        self.remap(x, |px, py| {
+            let index = Mat::map_2_to_1(px as usize, py as usize, width);
+            (px + alpha * dx[index], py + alpha * dy[index])
+        })
+    }
+    fn erase<R: Rng>(&self, x: &mut Mat, area: (f64, f64), value: f64, rng: &mut R) {
+        let plane = self.width * self.height;
+        let target = if area.0 < area.1 {
+            rng.gen_range(area.0..area.1)
+        } else {
+            area.0
+        } * plane as f64;
+        // aspect ratio sampled in log space between 0.3 and 1/0.3
+        let ratio = libm::exp(rng.gen_range(libm::log(0.3)..libm::log(1.0 / 0.3)));
+        let h = (libm::sqrt(target * ratio).round() as usize).clamp(1, self.height);
+        let w = (libm::sqrt(target / ratio).round() as usize).clamp(1, self.width);
+        let top = rng.gen_range(0..=self.height - h);
+        let left = rng.gen_range(0..=self.width - w);
+
+        let channels = x.as_slice().len() / plane;
+        let buffer = x.as_mut_slice();
+        for c in 0..channels {
+            for py in top..top + h {
+                for px in left..left + w {
+                    buffer[c * plane + Mat::map_2_to_1(px, py, self.width)] = value;
+                }
+            }
+        }
+    }
+    // build the output by bilinear sampling the input at source(px, py) for every channel,
+    // samples falling outside the image read as 0
+    fn remap<F: Fn(f64, f64) -> (f64, f64)>(&self, x: &Mat, source: F) -> Mat {
+        let plane = self.width * self.height;
+        let channels = x.as_slice().len() / plane;
+        let input = x.as_slice();
+        let mut out = Mat::zeroes_like(x);
+        let buffer = out.as_mut_slice();
+        for py in 0..self.height {
+            for px in 0..self.width {
+                let (sx, sy) = source(px as f64, py as f64);
+                let index = Mat::map_2_to_1(px, py, self.width);
+                for c in 0..channels {
+                    buffer[c * plane + index] =
+                        self.bilinear(&input[c * plane..(c + 1) * plane], sx, sy);
+                }
+            }
+        }
+        out
+    }
+    fn bilinear(&self, channel: &[f64], x: f64, y: f64) -> f64 {
+        let x0 = libm::floor(x);
+        let y0 = libm::floor(y);
+        let fx = x - x0;
+        let fy = y - y0;
+        let pixel = |px: f64, py: f64| -> f64 {
+            if px < 0.0 || py < 0.0 || px >= self.width as f64 || py >= self.height as f64 {
+                return 0.0;
+            }
+            channel[Mat::map_2_to_1(px as usize, py as usize, self.width)]
+        };
+        pixel(x0, y0) * (1.0 - fx) * (1.0 - fy)
+            + pixel(x0 + 1.0, y0) * fx * (1.0 - fy)
+            + pixel(x0, y0 + 1.0) * (1.0 - fx) * fy
+            + pixel(x0 + 1.0, y0 + 1.0) * fx * fy
+    }
+    // separable gaussian blur of a single plane, borders are clamped
+    fn gaussian_blur(&self, plane: &[f64], sigma: f64) -> Vec<f64> {
+        let radius = libm::ceil(3.0 * sigma) as i64;
+        let mut kernel: Vec<f64> = (-radius..=radius)
+            .map(|i| libm::exp(-((i * i) as f64) / (2.0 * sigma * sigma)))
+            .collect();
+        let total: f64 = kernel.iter().sum();
+        for k in kernel.iter_mut() {
+            *k /= total;
+        }
+
+        let (w, h) = (self.width as i64, self.height as i64);
+        let mut tmp = vec![0.0; plane.len()];
+        for y in 0..h {
+            for x in 0..w {
+                let mut sum = 0.0;
+                for (k, weight) in kernel.iter().enumerate() {
+                    let sx = (x + k as i64 - radius).clamp(0, w - 1);
+                    sum += weight * plane[(y * w + sx) as usize];
+                }
+                tmp[(y * w + x) as usize] = sum;
+            }
+        }
+        let mut out = vec![0.0; plane.len()];
+        for y in 0..h {
+            for x in 0..w {
+                let mut sum = 0.0;
+                for (k, weight) in kernel.iter().enumerate() {
+                    let sy = (y + k as i64 - radius).clamp(0, h - 1);
+                    sum += weight * tmp[(sy * w + x) as usize];
+                }
+                out[(y * w + x) as usize] = sum;
+            }
+        }
+        out
+    }
+}
+
+// uniform in [-|max|, |max|]
+fn uniform<R: Rng>(rng: &mut R, max: f64) -> f64 {
+    let max = max.abs();
+    if max == 0.0 {
+        return 0.0;
+    }
+    rng.gen_range(-max..=max)
+}
+
+// standard normal sample using the Box-Muller transform
+pub fn gaussian<R: Rng>(rng: &mut R) -> f64 {
+    let u1: f64 = 1.0 - rng.gen::<f64>();
+    let u2: f64 = rng.gen();
+    libm::sqrt(-2.0 * libm::log(u1)) * libm::cos(2.0 * std::f64::consts::PI * u2)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::data::augment::*;
+
+    fn image(channels: usize, rng: &mut ChaCha8Rng) -> Mat {
+        Mat::from_vec(
+            (0..channels * 30).map(|_| rng.gen()).collect(),
+            channels * 30,
+            1,
+        )
+    }
+
+    fn augmenter() -> Augmenter {
+        Augmenter::new(6, 5)
+            .affine(1.5, 20.0, (0.9, 1.1), 10.0)
+            .elastic(2.0, 1.0)
+            .gaussian_noise(0.1)
+            .random_erasing(0.5, (0.1, 0.3), 0.0)
+    }
+
+    #[test]
+    fn seeded_rng_reproduces_the_output() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let x = vec![image(1, &mut rng), image(3, &mut rng)];
+        let a = augmenter().apply_all(&x, &mut ChaCha8Rng::seed_from_u64(7));
+        let b = augmenter().apply_all(&x, &mut ChaCha8Rng::seed_from_u64(7));
+        let c = augmenter().apply_all(&x, &mut ChaCha8Rng::seed_from_u64(8));
+        for i in 0..x.len() {
+            assert_eq!(a[i].as_slice(), b[i].as_slice());
+            assert_ne!(a[i].as_slice(), c[i].as_slice());
+            assert_eq!(a[i].shape(), x[i].shape());
+        }
+    }
+
+    #[test]
+    fn identity_steps_keep_the_image() {
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+        let x = image(3, &mut rng);
+        let out = Augmenter::new(6, 5)
+            .affine(0.0, 0.0, (1.0, 1.0), 0.0)
+            .gaussian_noise(0.0)
+            .random_erasing(0.0, (0.5, 0.5), 0.0)
+            .apply(&x, &mut rng);
+        for (a, b) in out.as_slice().iter().zip(x.as_slice()) {
+            assert!((a - b).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn negative_ranges_are_symmetric() {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        for _ in 0..100 {
+            assert!(uniform(&mut rng, -2.0).abs() <= 2.0);
+        }
+        let x = image(1, &mut rng);
+        let out = Augmenter::new(6, 5)
+            .affine(-1.0, -15.0, (1.0, 1.0), -5.0)
+            .apply(&x, &mut rng);
+        assert_eq!(out.shape(), x.shape());
+    }
+
+    #[test]
+    #[should_panic(expected = "input doesn't match the augmenter image size")]
+    fn wrong_image_size_panics() {
+        let mut rng = ChaCha8Rng::seed_from_u64(4);
+        augmenter().apply(&Mat::new(31, 1), &mut rng);
+    }
+
+    fn close(a: &Mat, b: &[f64]) -> bool {
+        a.as_slice()
+            .iter()
+            .zip(b)
+            .all(|(a, b)| (a - b).abs() < 1e-9)
+    }
+
+    #[test]
+    fn integer_shift_moves_a_pixel() {
+        // 5x4 image, one lit pixel at x 1, y 1
+        let mut x = Mat::new(20, 1);
+        x[(Mat::map_2_to_1(1, 1, 5), 0)] = 1.0;
+        let out = Augmenter::new(5, 4).affine_warp(&x, 0.0, 0.0, 1.0, (2.0, 1.0));
+        let mut expected = vec![0.0; 20];
+        expected[Mat::map_2_to_1(3, 2, 5)] = 1.0;
+        assert!(close(&out, &expected));
+    }
+
+    #[test]
+    fn quarter_turn_rotates_the_pattern() {
+        // rows top to bottom, a positive angle turns clockwise since y points down
+        let x = Mat::from_vec((1..=9).map(f64::from).collect(), 9, 1);
+        let out = Augmenter::new(3, 3).affine_warp(&x, 90f64.to_radians(), 0.0, 1.0, (0.0, 0.0));
+        assert!(close(&out, &[7.0, 4.0, 1.0, 8.0, 5.0, 2.0, 9.0, 6.0, 3.0]));
+    }
+}
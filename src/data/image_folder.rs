@@ -0,0 +1,188 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use image::imageops::FilterType;
+
+use crate::tools::matrix::*;
+
+const EXTENSIONS: [&str; 3] = ["png", "jpg", "jpeg"];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ColorMode {
+    Gray,
+    Rgb,
+}
+
+impl ColorMode {
+    pub fn channels(&self) -> usize {
+        match self {
+            ColorMode::Gray => 1,
+            ColorMode::Rgb => 3,
+        }
+    }
+}
+
+// Dataset laid out as root/<class_name>/*.png|jpg, one sub directory per class.
+// every image becomes a (channels * height * width, 1) column with values in [0, 1],
+// channels are stored one after another (all red, then all green, then all blue)
+// and pixels inside a channel are row major like parse_mnist.
+pub struct ImageFolderDataset {
+    pub x: Vec<Mat>,
+    pub y: Vec<Mat>,
+    classes: Vec<String>,
+    width: usize,
+    height: usize,
+    mode: ColorMode,
+}
+
+impl ImageFolderDataset {
+    pub fn new(root: &str, width: usize, height: usize, mode: ColorMode) -> ImageFolderDataset {
+        // sorted so the class -> index mapping is the same on every machine
+        let mut class_dirs: Vec<PathBuf> = fs::read_dir(root)
+            .expect("couldn't open dataset root ;(")
+            .map(|entry| entry.expect("couldn't read dataset root ;(").path())
+            .filter(|path| path.is_dir())
+            .collect();
+        class_dirs.sort();
+
+        let classes: Vec<String> = class_dirs
+            .iter()
+            .map(|dir| dir.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+
+        let mut x = Vec::<Mat>::new();
+        let mut y = Vec::<Mat>::new();
+        for (index, dir) in class_dirs.iter().enumerate() {
+            for path in image_files(dir) {
+                x.push(load_image(&path, width, height, mode));
+
+                //one hot encoding
+                let mut label = Mat::new(classes.len(), 1);
+                label[(index, 0)] = 1.0;
+                y.push(label);
+            }
+        }
+        ImageFolderDataset {
+            x,
+            y,
+            classes,
+            width,
+            height,
+            mode,
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.x.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.x.is_empty()
+    }
+    pub fn classes(&self) -> &Vec<String> {
+        &self.classes
+    }
+    pub fn class_name(&self, index: usize) -> &str {
+        &self.classes[index]
+    }
+    // size of a single input column
+    pub fn input_shape(&self) -> usize {
+        self.mode.channels() * self.width * self.height
+    }
+    pub fn output_shape(&self) -> usize {
+        self.classes.len()
+    }
+    pub fn into_xy(self) -> (Vec<Mat>, Vec<Mat>) {
+        (self.x, self.y)
+    }
+}
+
+fn image_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .expect("couldn't open class directory ;(")
+        .map(|entry| entry.expect("couldn't read class directory ;(").path())
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .extension()
+                    .map(|ext| {
+                        let ext = ext.to_string_lossy().to_lowercase();
+                        EXTENSIONS.contains(&ext.as_str())
+                    })
+                    .unwrap_or(false)
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+// decode a single image into a column matrix using the same layout as ImageFolderDataset
+pub fn load_image(path: &Path, width: usize, height: usize, mode: ColorMode) -> Mat {
+    let img = image::open(path)
+        .unwrap_or_else(|e| panic!("couldn't decode {} ;( {}", path.display(), e))
+        .resize_exact(width as u32, height as u32, FilterType::Triangle);
+
+    let plane = width * height;
+    let mut buffer = vec![0.0; mode.channels() * plane];
+    match mode {
+        ColorMode::Gray => {
+            for (x, y, pixel) in img.to_luma8().enumerate_pixels() {
+                buffer[Mat::map_2_to_1(x as usize, y as usize, width)] = pixel[0] as f64 / 255.0;
+            }
+        }
+        ColorMode::Rgb => {
+            for (x, y, pixel) in img.to_rgb8().enumerate_pixels() {
+                let index = Mat::map_2_to_1(x as usize, y as usize, width);
+                for c in 0..3 {
+                    buffer[c * plane + index] = pixel[c] as f64 / 255.0;
+                }
+            }
+        }
+    }
+    Mat::from_vec(buffer, mode.channels() * plane, 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use image::{Rgb, RgbImage};
+
+    use crate::data::image_folder::*;
+
+    #[test]
+    fn classes_labels_and_sizes() {
+        let root = std::env::temp_dir().join(format!("kek_image_folder_{}", std::process::id()));
+        // created out of order, discovery sorts them
+        for (class, files) in [("dog", vec!["b.png", "a.png"]), ("cat", vec!["c.jpg"])] {
+            let dir = root.join(class);
+            fs::create_dir_all(&dir).unwrap();
+            for (i, file) in files.iter().enumerate() {
+                let shade = if class == "cat" { 255 } else { 51 * i as u8 };
+                RgbImage::from_pixel(7 + i as u32, 5, Rgb([shade, 0, 255]))
+                    .save(dir.join(file))
+                    .unwrap();
+            }
+        }
+        fs::write(root.join("dog").join("notes.txt"), "not an image").unwrap();
+
+        let rgb = ImageFolderDataset::new(root.to_str().unwrap(), 4, 3, ColorMode::Rgb);
+        let gray = ImageFolderDataset::new(root.to_str().unwrap(), 2, 2, ColorMode::Gray);
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(rgb.classes(), &vec!["cat".to_string(), "dog".to_string()]);
+        assert_eq!(rgb.len(), 3);
+        assert_eq!((rgb.input_shape(), rgb.output_shape()), (36, 2));
+        let labels: Vec<&[f64]> = rgb.y.iter().map(|y| y.as_slice()).collect();
+        assert_eq!(labels, vec![&[1.0, 0.0][..], &[0.0, 1.0], &[0.0, 1.0]]);
+        for x in &rgb.x {
+            assert_eq!(x.shape(), (36, 1));
+        }
+        // channels are planes and files are sorted, a.png (shade 51) before b.png (shade 0)
+        let (red, blue) = (&rgb.x[1].as_slice()[..12], &rgb.x[1].as_slice()[24..]);
+        assert!(red.iter().all(|v| (v - 0.2).abs() < 1e-9));
+        assert!(blue.iter().all(|v| *v == 1.0));
+        assert!(rgb.x[2].as_slice()[..12].iter().all(|v| *v == 0.0));
+
+        assert_eq!(gray.input_shape(), 4);
+        assert!(gray.x.iter().all(|x| x.shape() == (4, 1)));
+    }
+}
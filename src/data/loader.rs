@@ -0,0 +1,193 @@
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use crate::data::augment::Augmenter;
+use crate::tools::matrix::*;
+
+// Iterates a dataset in batches, reshuffling every epoch and augmenting samples on the fly.
+// the original samples are never modified, every epoch sees fresh augmentations.
+pub struct DataLoader<'a> {
+    x: &'a [Mat],
+    y: &'a [Mat],
+    batch_size: usize,
+    shuffle: bool,
+    drop_last: bool,
+    augmenter: Option<Augmenter>,
+    rng: ChaCha8Rng,
+}
+
+impl<'a> DataLoader<'a> {
+    pub fn new(x: &'a [Mat], y: &'a [Mat], batch_size: usize) -> DataLoader<'a> {
+        assert_eq!(x.len(), y.len());
+        assert!(batch_size > 0);
+        DataLoader {
+            x,
+            y,
+            batch_size,
+            shuffle: true,
+            drop_last: false,
+            augmenter: None,
+            rng: ChaCha8Rng::from_entropy(),
+        }
+    }
+    pub fn seed(mut self, seed: u64) -> DataLoader<'a> {
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+        self
+    }
+    pub fn shuffle(mut self, shuffle: bool) -> DataLoader<'a> {
+        self.shuffle = shuffle;
+        self
+    }
+    // skip the final batch when it is smaller than batch_size
+    pub fn drop_last(mut self, drop_last: bool) -> DataLoader<'a> {
+        self.drop_last = drop_last;
+        self
+    }
+    pub fn augment(mut self, augmenter: Augmenter) -> DataLoader<'a> {
+        self.augmenter = Some(augmenter);
+        self
+    }
+    pub fn len(&self) -> usize {
+        if self.drop_last {
+            self.x.len() / self.batch_size
+        } else {
+            self.x.len().div_ceil(self.batch_size)
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+    // shuffling/augmentation rng, saved in training checkpoints
+    pub fn rng(&self) -> &ChaCha8Rng {
+        &self.rng
+    }
+    pub fn set_rng(&mut self, rng: ChaCha8Rng) {
+        self.rng = rng;
+    }
+    // one pass over the dataset
+    pub fn epoch(&mut self) -> Batches<'_, 'a> {
+        let mut order: Vec<usize> = (0..self.x.len()).collect();
+        if self.shuffle {
+            order.shuffle(&mut self.rng);
+        }
+        Batches {
+            loader: self,
+            order,
+            position: 0,
+        }
+    }
+}
+
+pub struct Batches<'l, 'a> {
+    loader: &'l mut DataLoader<'a>,
+    order: Vec<usize>,
+    position: usize,
+}
+
+impl Iterator for Batches<'_, '_> {
+    type Item = (Vec<Mat>, Vec<Mat>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let remaining = self.order.len() - self.position;
+        let loader = &mut *self.loader;
+        if remaining == 0 || (loader.drop_last && remaining < loader.batch_size) {
+            return None;
+        }
+        let end = self.position + remaining.min(loader.batch_size);
+        let mut x = Vec::<Mat>::with_capacity(end - self.position);
+        let mut y = Vec::<Mat>::with_capacity(end - self.position);
+        for &index in &self.order[self.position..end] {
+            x.push(match &loader.augmenter {
+                Some(augmenter) => augmenter.apply(&loader.x[index], &mut loader.rng),
+                None => loader.x[index].clone(),
+            });
+            y.push(loader.y[index].clone());
+        }
+        self.position = end;
+        Some((x, y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::augment::Augmenter;
+    use crate::data::loader::*;
+
+    fn dataset(len: usize) -> (Vec<Mat>, Vec<Mat>) {
+        let x = (0..len)
+            .map(|i| Mat::from_vec(vec![i as f64; 4], 4, 1))
+            .collect();
+        let y = (0..len)
+            .map(|i| Mat::from_vec(vec![i as f64], 1, 1))
+            .collect();
+        (x, y)
+    }
+
+    fn ids(batches: &[(Vec<Mat>, Vec<Mat>)]) -> Vec<usize> {
+        batches
+            .iter()
+            .flat_map(|(_, y)| y.iter().map(|label| label[(0, 0)] as usize))
+            .collect()
+    }
+
+    #[test]
+    fn batch_counts_with_and_without_drop_last() {
+        let (x, y) = dataset(10);
+        let mut loader = DataLoader::new(&x, &y, 4).seed(1);
+        assert_eq!(loader.len(), 3);
+        let batches: Vec<_> = loader.epoch().collect();
+        let sizes: Vec<usize> = batches.iter().map(|(x, _)| x.len()).collect();
+        assert_eq!(sizes, vec![4, 4, 2]);
+        let mut seen = ids(&batches);
+        seen.sort();
+        assert_eq!(seen, (0..10).collect::<Vec<_>>());
+
+        let mut loader = DataLoader::new(&x, &y, 4).seed(1).drop_last(true);
+        assert_eq!(loader.len(), 2);
+        assert_eq!(loader.epoch().count(), 2);
+        let mut loader = DataLoader::new(&x, &y, 5).drop_last(true);
+        assert_eq!((loader.len(), loader.epoch().count()), (2, 2));
+        assert!(DataLoader::new(&x, &y, 11).drop_last(true).is_empty());
+    }
+
+    #[test]
+    fn seeded_loaders_shuffle_the_same_way() {
+        let (x, y) = dataset(20);
+        let epochs = |seed: u64| -> Vec<Vec<usize>> {
+            let mut loader = DataLoader::new(&x, &y, 3).seed(seed);
+            (0..2)
+                .map(|_| ids(&loader.epoch().collect::<Vec<_>>()))
+                .collect()
+        };
+        let (a, b) = (epochs(5), epochs(5));
+        assert_eq!(a, b);
+        assert_ne!(a[0], a[1]);
+        assert_ne!(a, epochs(6));
+
+        let mut loader = DataLoader::new(&x, &y, 3).shuffle(false);
+        assert_eq!(
+            ids(&loader.epoch().collect::<Vec<_>>()),
+            (0..20).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn augmented_batches_keep_shapes_and_labels() {
+        let (x, y) = dataset(6);
+        let mut loader = DataLoader::new(&x, &y, 4)
+            .seed(2)
+            .augment(Augmenter::new(2, 2).gaussian_noise(0.5));
+        for (bx, by) in loader.epoch() {
+            for (xi, yi) in bx.iter().zip(&by) {
+                assert_eq!(xi.shape(), (4, 1));
+                assert_ne!(xi.as_slice(), x[yi[(0, 0)] as usize].as_slice());
+            }
+        }
+        // the dataset itself is untouched
+        assert_eq!(x[3].as_slice(), &[3.0; 4]);
+    }
+}
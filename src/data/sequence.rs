@@ -0,0 +1,28 @@
+use rand::Rng;
+
+use crate::tools::matrix::*;
+
+// Toy sequence to sequence task: `steps` random tokens out of `vocab`, one hot and stacked
+// like layers::recurrent expects, and the same tokens in reverse order as the target.
+// needs every position to look at another one, so it's a quick check for attention models
+pub fn reversal<R: Rng>(
+    samples: usize,
+    vocab: usize,
+    steps: usize,
+    rng: &mut R,
+) -> (Vec<Mat>, Vec<Mat>) {
+    let mut x = vec![];
+    let mut y = vec![];
+    for _ in 0..samples {
+        let tokens: Vec<usize> = (0..steps).map(|_| rng.gen_range(0..vocab)).collect();
+        let mut xi = Mat::new(steps * vocab, 1);
+        let mut yi = Mat::new(steps * vocab, 1);
+        for (t, token) in tokens.iter().enumerate() {
+            xi[(t * vocab + token, 0)] = 1.0;
+            yi[((steps - 1 - t) * vocab + token, 0)] = 1.0;
+        }
+        x.push(xi);
+        y.push(yi);
+    }
+    (x, y)
+}
@@ -0,0 +1,341 @@
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::metrics::{self, label_of};
+use crate::nn::NN;
+use crate::tools::matrix::*;
+
+// Indices into a dataset, use select to materialize them.
+#[derive(Clone, Debug)]
+pub struct Split {
+    pub train: Vec<usize>,
+    pub test: Vec<usize>,
+}
+
+pub fn select(x: &[Mat], indices: &[usize]) -> Vec<Mat> {
+    indices.iter().map(|&i| x[i].clone()).collect()
+}
+
+// random holdout, test_fraction of the samples go to test
+pub fn holdout<R: Rng>(len: usize, test_fraction: f64, rng: &mut R) -> Split {
+    assert!((0.0..=1.0).contains(&test_fraction));
+    let mut order: Vec<usize> = (0..len).collect();
+    order.shuffle(rng);
+    let test_len = (len as f64 * test_fraction).round() as usize;
+    let train = order.split_off(test_len);
+    Split { train, test: order }
+}
+
+// holdout that keeps the class proportions of y in both halves
+pub fn stratified_holdout<R: Rng>(y: &[Mat], test_fraction: f64, rng: &mut R) -> Split {
+    assert!((0.0..=1.0).contains(&test_fraction));
+    let mut split = Split {
+        train: vec![],
+        test: vec![],
+    };
+    for mut class in by_class(y) {
+        class.shuffle(rng);
+        let test_len = (class.len() as f64 * test_fraction).round() as usize;
+        split.train.extend_from_slice(&class[test_len..]);
+        split.test.extend_from_slice(&class[..test_len]);
+    }
+    split.train.shuffle(rng);
+    split.test.shuffle(rng);
+    split
+}
+
+// train/validation/test in one go, returns (train, validation, test) indices
+pub fn train_val_test<R: Rng>(
+    y: &[Mat],
+    val_fraction: f64,
+    test_fraction: f64,
+    stratify: bool,
+    rng: &mut R,
+) -> (Vec<usize>, Vec<usize>, Vec<usize>) {
+    assert!(val_fraction + test_fraction <= 1.0);
+    let split = |y: &[Mat], fraction: f64, rng: &mut R| {
+        if stratify {
+            stratified_holdout(y, fraction, rng)
+        } else {
+            holdout(y.len(), fraction, rng)
+        }
+    };
+    let outer = split(y, test_fraction, rng);
+    // the validation fraction is relative to the whole dataset, not to what is left
+    let rest = 1.0 - test_fraction;
+    let inner_fraction = if rest > 0.0 { val_fraction / rest } else { 0.0 };
+    let inner = split(&select(y, &outer.train), inner_fraction.min(1.0), rng);
+    let train = inner.train.iter().map(|&i| outer.train[i]).collect();
+    let val = inner.test.iter().map(|&i| outer.train[i]).collect();
+    (train, val, outer.test)
+}
+
+// k folds, every sample lands in exactly one test fold
+pub fn k_fold<R: Rng>(len: usize, k: usize, rng: &mut R) -> Vec<Split> {
+    assert!(
+        k >= 2 && k <= len,
+        "k has to be between 2 and the dataset size"
+    );
+    let mut order: Vec<usize> = (0..len).collect();
+    order.shuffle(rng);
+    let mut assignment = vec![vec![]; k];
+    for (i, index) in order.into_iter().enumerate() {
+        assignment[i % k].push(index);
+    }
+    folds_from(assignment)
+}
+
+// k folds that each keep the class proportions of y
+pub fn stratified_k_fold<R: Rng>(y: &[Mat], k: usize, rng: &mut R) -> Vec<Split> {
+    assert!(
+        k >= 2 && k <= y.len(),
+        "k has to be between 2 and the dataset size"
+    );
+    let mut assignment = vec![vec![]; k];
+    // deal every class round robin, continuing where the previous class stopped
+    // so small classes don't all pile into the first folds
+    let mut next = 0;
+    for mut class in by_class(y) {
+        class.shuffle(rng);
+        for index in class {
+            assignment[next % k].push(index);
+            next += 1;
+        }
+    }
+    folds_from(assignment)
+}
+
+fn folds_from(assignment: Vec<Vec<usize>>) -> Vec<Split> {
+    (0..assignment.len())
+        .map(|fold| {
+            let mut train = vec![];
+            for (other, indices) in assignment.iter().enumerate() {
+                if other != fold {
+                    train.extend_from_slice(indices);
+                }
+            }
+            Split {
+                train,
+                test: assignment[fold].clone(),
+            }
+        })
+        .collect()
+}
+
+// sample indices grouped by class, classes in ascending order
+fn by_class(y: &[Mat]) -> Vec<Vec<usize>> {
+    let mut classes: Vec<Vec<usize>> = vec![];
+    for (i, target) in y.iter().enumerate() {
+        let label = label_of(target);
+        if classes.len() <= label {
+            classes.resize(label + 1, vec![]);
+        }
+        classes[label].push(i);
+    }
+    classes.retain(|class| !class.is_empty());
+    classes
+}
+
+#[derive(Clone, Debug)]
+pub struct FoldReport {
+    pub fold: usize,
+    pub train_size: usize,
+    pub test_size: usize,
+    pub metrics: metrics::Report,
+}
+
+// Runs every fold with a freshly built model: make_model is called once per fold and
+// train is handed the fold's training data, the held out part is evaluated afterwards.
+pub fn cross_validate<M, T>(
+    x: &[Mat],
+    y: &[Mat],
+    folds: &[Split],
+    mut make_model: M,
+    mut train: T,
+    verbose: bool,
+) -> Vec<FoldReport>
+where
+    M: FnMut() -> NN,
+    T: FnMut(&mut NN, &[Mat], &[Mat]),
+{
+    assert_eq!(x.len(), y.len());
+    let mut reports = vec![];
+    for (fold, split) in folds.iter().enumerate() {
+        let mut model = make_model();
+        train(
+            &mut model,
+            &select(x, &split.train),
+            &select(y, &split.train),
+        );
+
+        let report = FoldReport {
+            fold,
+            train_size: split.train.len(),
+            test_size: split.test.len(),
+            metrics: model.evaluate(&select(x, &split.test), &select(y, &split.test)),
+        };
+        if verbose {
+            println!(
+                "fold:{} train:{} test:{} log loss:{} accuracy:{} macro f1:{}",
+                report.fold,
+                report.train_size,
+                report.test_size,
+                report.metrics.log_loss,
+                report.metrics.accuracy,
+                report.metrics.macro_avg.f1
+            );
+        }
+        reports.push(report);
+    }
+    reports
+}
+
+// mean and standard deviation of the fold accuracies
+pub fn accuracy_mean_std(reports: &[FoldReport]) -> (f64, f64) {
+    let n = reports.len() as f64;
+    let mean = reports.iter().map(|r| r.metrics.accuracy).sum::<f64>() / n;
+    let var = reports
+        .iter()
+        .map(|r| (r.metrics.accuracy - mean) * (r.metrics.accuracy - mean))
+        .sum::<f64>()
+        / n;
+    (mean, libm::sqrt(var))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::data::split::*;
+
+    // 60 of class 0, 30 of class 1 and 10 of class 2
+    fn labels() -> Vec<Mat> {
+        (0..100)
+            .map(|i| {
+                let mut label = Mat::new(3, 1);
+                let class = (i >= 60) as usize + (i >= 90) as usize;
+                label[(class, 0)] = 1.0;
+                label
+            })
+            .collect()
+    }
+
+    fn counts(y: &[Mat], indices: &[usize]) -> Vec<usize> {
+        let mut counts = vec![0; 3];
+        for &i in indices {
+            counts[label_of(&y[i])] += 1;
+        }
+        counts
+    }
+
+    fn sorted(mut indices: Vec<usize>) -> Vec<usize> {
+        indices.sort();
+        indices
+    }
+
+    #[test]
+    fn holdout_sizes() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let split = holdout(10, 0.3, &mut rng);
+        assert_eq!((split.train.len(), split.test.len()), (7, 3));
+        assert_eq!(
+            sorted([split.train, split.test].concat()),
+            (0..10).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn stratified_holdout_keeps_class_proportions() {
+        let y = labels();
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+        let split = stratified_holdout(&y, 0.2, &mut rng);
+        assert_eq!(counts(&y, &split.test), vec![12, 6, 2]);
+        assert_eq!(counts(&y, &split.train), vec![48, 24, 8]);
+        assert_eq!(
+            sorted([split.train, split.test].concat()),
+            (0..100).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn train_val_test_fractions_are_of_the_whole_dataset() {
+        let y = labels();
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let (train, val, test) = train_val_test(&y, 0.2, 0.1, true, &mut rng);
+        assert_eq!((train.len(), val.len(), test.len()), (70, 20, 10));
+        assert_eq!(counts(&y, &val), vec![12, 6, 2]);
+        assert_eq!(
+            sorted([train, val, test].concat()),
+            (0..100).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn k_fold_tests_are_disjoint_and_cover_every_index() {
+        let y = labels();
+        let mut rng = ChaCha8Rng::seed_from_u64(4);
+        for folds in [
+            k_fold(23, 4, &mut rng),
+            stratified_k_fold(&y[..23], 4, &mut rng),
+        ] {
+            assert_eq!(folds.len(), 4);
+            let tests: Vec<usize> = folds.iter().flat_map(|f| f.test.clone()).collect();
+            assert_eq!(sorted(tests), (0..23).collect::<Vec<_>>());
+            for fold in &folds {
+                assert!(fold.test.len() == 5 || fold.test.len() == 6);
+                // train is everything outside the fold
+                let all = sorted([fold.train.clone(), fold.test.clone()].concat());
+                assert_eq!(all, (0..23).collect::<Vec<_>>());
+            }
+        }
+
+        let folds = stratified_k_fold(&y, 5, &mut rng);
+        for fold in &folds {
+            assert_eq!(counts(&y, &fold.test), vec![12, 6, 2]);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "k has to be between 2 and the dataset size")]
+    fn k_fold_rejects_more_folds_than_samples() {
+        k_fold(3, 4, &mut ChaCha8Rng::seed_from_u64(5));
+    }
+
+    #[test]
+    fn cross_validate_trains_a_fresh_model_per_fold() {
+        let y = labels();
+        let x: Vec<Mat> = y.iter().map(|yi| yi.scaler_mul(2.0)).collect();
+        let mut rng = ChaCha8Rng::seed_from_u64(6);
+        let folds = k_fold(x.len(), 4, &mut rng);
+        // the first weights of every model make_model built
+        let built = std::cell::RefCell::new(vec![]);
+        let mut trained = 0;
+        let reports = cross_validate(
+            &x,
+            &y,
+            &folds,
+            || {
+                let nn = NN::new(3, 4, 3);
+                built.borrow_mut().push(nn.weights()[0].clone());
+                nn
+            },
+            |nn, x_train, y_train| {
+                // train gets the model make_model just built, untouched
+                let built = built.borrow();
+                assert_eq!(built.len(), trained + 1);
+                assert_eq!(nn.weights()[0].as_slice(), built[trained].as_slice());
+                assert_eq!(x_train.len(), 75);
+                nn.train(x_train, y_train, None, 1, 5, &mut []);
+                trained += 1;
+            },
+            false,
+        );
+        assert_eq!(trained, 4);
+        assert_eq!(reports.len(), 4);
+        for (i, report) in reports.iter().enumerate() {
+            assert_eq!(report.fold, i);
+            assert_eq!((report.train_size, report.test_size), (75, 25));
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::fs;
+
+use crate::tools::matrix::*;
+
+// The characters of a text, sorted, each mapped to its position for one hot encoding
+pub struct CharVocab {
+    chars: Vec<char>,
+}
+
+impl CharVocab {
+    pub fn new(text: &str) -> CharVocab {
+        let mut chars: Vec<char> = text.chars().collect();
+        chars.sort();
+        chars.dedup();
+        CharVocab { chars }
+    }
+    pub fn len(&self) -> usize {
+        self.chars.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.chars.is_empty()
+    }
+    pub fn index(&self, c: char) -> Option<usize> {
+        self.chars.binary_search(&c).ok()
+    }
+    pub fn char(&self, index: usize) -> char {
+        self.chars[index]
+    }
+    // (len, 1) column, unknown characters are all zeros
+    pub fn one_hot(&self, c: char) -> Mat {
+        let mut m = Mat::new(self.len(), 1);
+        if let Some(i) = self.index(c) {
+            m[(i, 0)] = 1.0;
+        }
+        m
+    }
+    // the characters one hot encoded and stacked in order, the layout layers::recurrent expects
+    pub fn encode(&self, chars: &[char]) -> Mat {
+        let steps: Vec<Mat> = chars.iter().map(|c| self.one_hot(*c)).collect();
+        Mat::vstack(&steps)
+    }
+    // the last `steps` characters encoded, left padded with all zero steps when there are fewer
+    pub fn context(&self, chars: &[char], steps: usize) -> Mat {
+        let chars = &chars[chars.len().saturating_sub(steps)..];
+        let padding = Mat::new((steps - chars.len()) * self.len(), 1);
+        Mat::vstack(&[padding, self.encode(chars)])
+    }
+}
+
+// Next character prediction pairs: the up to `steps` characters before a position and the
+// one hot character at it, for every `stride`th position. contexts near the start of the text
+// are shorter and left padded with all zero steps, which recurrent layers can mask out
+pub struct CharDataset {
+    pub x: Vec<Mat>,
+    pub y: Vec<Mat>,
+    vocab: CharVocab,
+    steps: usize,
+}
+
+impl CharDataset {
+    pub fn new(path: &str, steps: usize, stride: usize) -> CharDataset {
+        let text = fs::read_to_string(path).expect("couldn't read text file ;(");
+        CharDataset::from_text(&text, steps, stride)
+    }
+    pub fn from_text(text: &str, steps: usize, stride: usize) -> CharDataset {
+        assert!(steps > 0 && stride > 0);
+        let vocab = CharVocab::new(text);
+        let chars: Vec<char> = text.chars().collect();
+        let mut x = vec![];
+        let mut y = vec![];
+        for end in (1..chars.len()).step_by(stride) {
+            x.push(vocab.context(&chars[..end], steps));
+            y.push(vocab.one_hot(chars[end]));
+        }
+        CharDataset { x, y, vocab, steps }
+    }
+    pub fn vocab(&self) -> &CharVocab {
+        &self.vocab
+    }
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+    pub fn len(&self) -> usize {
+        self.x.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.x.is_empty()
+    }
+}
@@ -0,0 +1,316 @@
+use std::fs::File;
+
+use serde::{Deserialize, Serialize};
+
+use crate::tools::matrix::*;
+
+// A preprocessing step whose statistics are fitted once on the training set and then
+// reused for every other input (test set, drawing canvas, ...).
+// statistics are kept per element of the input matrix, so every sample has to share a shape.
+// the statistics can be left out of a file, e.g. { MinMax = { low = 0.0, high = 1.0 } } in a config
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub enum Transform {
+    // rescale every feature into [low, high], values outside the fitted range are clipped
+    MinMax {
+        low: f64,
+        high: f64,
+        #[serde(default)]
+        min: Vec<f64>,
+        #[serde(default)]
+        max: Vec<f64>,
+    },
+    // z-score every feature: (x - mean) / std
+    Standardize {
+        #[serde(default)]
+        mean: Vec<f64>,
+        #[serde(default)]
+        std: Vec<f64>,
+    },
+    // z-score with one mean/std per channel, channels stored one after another
+    // like data::image_folder::ImageFolderDataset does
+    ChannelStandardize {
+        channels: usize,
+        #[serde(default)]
+        mean: Vec<f64>,
+        #[serde(default)]
+        std: Vec<f64>,
+    },
+}
+
+impl Transform {
+    pub fn min_max(low: f64, high: f64) -> Transform {
+        assert!(low < high);
+        Transform::MinMax {
+            low,
+            high,
+            min: vec![],
+            max: vec![],
+        }
+    }
+    pub fn standardize() -> Transform {
+        Transform::Standardize {
+            mean: vec![],
+            std: vec![],
+        }
+    }
+    pub fn channel_standardize(channels: usize) -> Transform {
+        assert!(channels > 0);
+        Transform::ChannelStandardize {
+            channels,
+            mean: vec![],
+            std: vec![],
+        }
+    }
+    pub fn is_fitted(&self) -> bool {
+        match self {
+            Transform::MinMax { min, .. } => !min.is_empty(),
+            Transform::Standardize { mean, .. } => !mean.is_empty(),
+            Transform::ChannelStandardize { mean, .. } => !mean.is_empty(),
+        }
+    }
+    pub fn fit(&mut self, x: &[Mat]) {
+        assert!(!x.is_empty(), "can't fit a transform on an empty dataset");
+        let features = x[0].as_slice().len();
+        for sample in x {
+            assert_eq!(sample.as_slice().len(), features);
+        }
+        match self {
+            Transform::MinMax { min, max, .. } => {
+                *min = vec![f64::INFINITY; features];
+                *max = vec![f64::NEG_INFINITY; features];
+                for sample in x {
+                    for (i, ele) in sample.as_slice().iter().enumerate() {
+                        min[i] = min[i].min(*ele);
+                        max[i] = max[i].max(*ele);
+                    }
+                }
+            }
+            Transform::Standardize { mean, std } => {
+                let groups: Vec<usize> = (0..features).collect();
+                (*mean, *std) = mean_std(x, &groups, features);
+            }
+            Transform::ChannelStandardize {
+                channels,
+                mean,
+                std,
+            } => {
+                assert_eq!(
+                    features % *channels,
+                    0,
+                    "input size isn't divisible by the channel count"
+                );
+                let plane = features / *channels;
+                let groups: Vec<usize> = (0..features).map(|i| i / plane).collect();
+                (*mean, *std) = mean_std(x, &groups, *channels);
+            }
+        }
+    }
+    pub fn apply(&self, x: &Mat) -> Mat {
+        assert!(self.is_fitted(), "transform used before fit");
+        let mut out = x.clone();
+        let features = out.as_slice().len();
+        match self {
+            Transform::MinMax {
+                low,
+                high,
+                min,
+                max,
+            } => {
+                assert_eq!(features, min.len());
+                for (i, ele) in out.as_mut_slice().iter_mut().enumerate() {
+                    let range = max[i] - min[i];
+                    *ele = if range == 0.0 {
+                        *low
+                    } else {
+                        let t = ((*ele - min[i]) / range).clamp(0.0, 1.0);
+                        low + t * (high - low)
+                    };
+                }
+            }
+            Transform::Standardize { mean, std } => {
+                assert_eq!(features, mean.len());
+                for (i, ele) in out.as_mut_slice().iter_mut().enumerate() {
+                    *ele = (*ele - mean[i]) / std[i];
+                }
+            }
+            Transform::ChannelStandardize {
+                channels,
+                mean,
+                std,
+            } => {
+                assert_eq!(features % channels, 0);
+                let plane = features / channels;
+                for (i, ele) in out.as_mut_slice().iter_mut().enumerate() {
+                    let c = i / plane;
+                    *ele = (*ele - mean[c]) / std[c];
+                }
+            }
+        }
+        out
+    }
+}
+
+// mean and population std of the features falling in each group,
+// a constant group gets std 1 so it is only centered
+fn mean_std(x: &[Mat], groups: &[usize], group_count: usize) -> (Vec<f64>, Vec<f64>) {
+    let mut sum = vec![0.0; group_count];
+    let mut count = vec![0.0; group_count];
+    for sample in x {
+        for (i, ele) in sample.as_slice().iter().enumerate() {
+            sum[groups[i]] += ele;
+            count[groups[i]] += 1.0;
+        }
+    }
+    let mean: Vec<f64> = sum.iter().zip(&count).map(|(s, n)| s / n).collect();
+
+    let mut var = vec![0.0; group_count];
+    for sample in x {
+        for (i, ele) in sample.as_slice().iter().enumerate() {
+            let d = ele - mean[groups[i]];
+            var[groups[i]] += d * d;
+        }
+    }
+    let std = var
+        .iter()
+        .zip(&count)
+        .map(|(v, n)| {
+            let s = libm::sqrt(v / n);
+            if s == 0.0 {
+                1.0
+            } else {
+                s
+            }
+        })
+        .collect();
+    (mean, std)
+}
+
+// Ordered list of transforms, each step is fitted on the output of the steps before it.
+// save the fitted pipeline next to the model so inference sees identical preprocessing.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Pipeline {
+    steps: Vec<Transform>,
+}
+
+impl Pipeline {
+    pub fn new(steps: Vec<Transform>) -> Pipeline {
+        Pipeline { steps }
+    }
+    pub fn steps(&self) -> &Vec<Transform> {
+        &self.steps
+    }
+    pub fn fit(&mut self, x: &[Mat]) {
+        let mut current = x.to_vec();
+        for step in self.steps.iter_mut() {
+            step.fit(&current);
+            current = current.iter().map(|sample| step.apply(sample)).collect();
+        }
+    }
+    pub fn apply(&self, x: &Mat) -> Mat {
+        let mut out = x.clone();
+        for step in &self.steps {
+            out = step.apply(&out);
+        }
+        out
+    }
+    pub fn apply_all(&self, x: &[Mat]) -> Vec<Mat> {
+        x.iter().map(|sample| self.apply(sample)).collect()
+    }
+    // fit on x and return the transformed x
+    pub fn fit_apply(&mut self, x: &[Mat]) -> Vec<Mat> {
+        self.fit(x);
+        self.apply_all(x)
+    }
+    pub fn save(&self, path: &str) {
+        let file = File::create(path).expect("couldn't create transform file ;(");
+        serde_json::to_writer(file, self).expect("couldn't write transform file ;(");
+    }
+    pub fn load(path: &str) -> Pipeline {
+        let file = File::open(path).expect("couldn't open transform file ;(");
+        serde_json::from_reader(file).expect("couldn't parse transform file ;(")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::transform::*;
+
+    fn col(values: &[f64]) -> Mat {
+        Mat::from_vec(values.to_vec(), values.len(), 1)
+    }
+
+    fn close(a: &[f64], b: &[f64]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-12)
+    }
+
+    #[test]
+    fn min_max_rescales_and_clips() {
+        let mut t = Transform::min_max(0.0, 0.9);
+        t.fit(&[col(&[0.0, 10.0, 5.0]), col(&[2.0, 20.0, 5.0])]);
+        assert!(close(
+            t.apply(&col(&[1.0, 15.0, 5.0])).as_slice(),
+            &[0.45, 0.45, 0.0]
+        ));
+        // outside the fitted range, constant features map to low
+        assert!(close(
+            t.apply(&col(&[-1.0, 30.0, 7.0])).as_slice(),
+            &[0.0, 0.9, 0.0]
+        ));
+    }
+
+    #[test]
+    fn standardize_gives_zero_mean_unit_std() {
+        let mut t = Transform::standardize();
+        let x = [col(&[1.0, 4.0]), col(&[3.0, 4.0]), col(&[5.0, 4.0])];
+        t.fit(&x);
+        let Transform::Standardize { mean, std } = &t else {
+            unreachable!()
+        };
+        assert!(close(mean, &[3.0, 4.0]));
+        // population std of 1, 3, 5, the constant feature keeps std 1
+        assert!(close(std, &[libm::sqrt(8.0 / 3.0), 1.0]));
+        let out: Vec<f64> = x.iter().map(|xi| t.apply(xi).as_slice()[0]).collect();
+        assert!(close(&[out.iter().sum::<f64>()], &[0.0]));
+        assert!(close(t.apply(&col(&[3.0, 6.0])).as_slice(), &[0.0, 2.0]));
+    }
+
+    #[test]
+    fn channel_standardize_shares_statistics_per_channel() {
+        let mut t = Transform::channel_standardize(2);
+        // two channels of two pixels, channel 0 is 1s and 3s, channel 1 is all 10s
+        t.fit(&[col(&[1.0, 3.0, 10.0, 10.0]), col(&[3.0, 1.0, 10.0, 10.0])]);
+        let Transform::ChannelStandardize { mean, std, .. } = &t else {
+            unreachable!()
+        };
+        assert!(close(mean, &[2.0, 10.0]));
+        assert!(close(std, &[1.0, 1.0]));
+        assert!(close(
+            t.apply(&col(&[1.0, 4.0, 10.0, 12.0])).as_slice(),
+            &[-1.0, 2.0, 0.0, 2.0]
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "transform used before fit")]
+    fn unfitted_transforms_panic() {
+        Transform::standardize().apply(&col(&[1.0]));
+    }
+
+    #[test]
+    fn pipeline_round_trips_through_a_file() {
+        let x = [col(&[0.0, 2.0]), col(&[4.0, 6.0]), col(&[2.0, 1.0])];
+        let mut pipeline =
+            Pipeline::new(vec![Transform::min_max(0.0, 1.0), Transform::standardize()]);
+        let fitted = pipeline.fit_apply(&x);
+        let path = std::env::temp_dir().join(format!("kek_pipeline_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        pipeline.save(path);
+        let loaded = Pipeline::load(path);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(loaded.steps().len(), 2);
+        assert!(loaded.steps().iter().all(Transform::is_fitted));
+        for (xi, fi) in x.iter().zip(&fitted) {
+            assert_eq!(loaded.apply(xi).as_slice(), fi.as_slice());
+        }
+    }
+}
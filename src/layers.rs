@@ -0,0 +1,99 @@
+pub mod activation;
+pub mod attention;
+pub mod batch_norm;
+pub mod conv;
+pub mod dense;
+pub mod dropout;
+pub mod embedding;
+pub mod graph;
+pub mod layer_norm;
+pub mod pool;
+pub mod positional;
+pub mod recurrent;
+pub mod time_distributed;
+pub mod transformer;
+
+use serde::{Deserialize, Serialize};
+
+use crate::tools::matrix::*;
+
+// what a parameter is used for, weight decay is configured per group (see nn::regularization)
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ParamGroup {
+    // dense and convolution kernels, embeddings
+    Weight,
+    Bias,
+    // scale and shift of normalization layers
+    Norm,
+    // learned shape of an activation like the prelu slope
+    Slope,
+}
+
+// A building block of NN. Inputs hold one sample per column, so a batch of n samples
+// with k features is a (k, n) matrix and a single sample is a (k, 1) column.
+pub trait Layer {
+    // training switches on things like dropout, the layer keeps whatever backward needs
+    fn forward(&mut self, x: &Mat, training: bool) -> Mat;
+    // grad is d loss / d output of the last forward call, returns d loss / d input.
+    // parameter gradients are stored until the next backward call
+    fn backward(&mut self, grad: &Mat) -> Mat;
+    // learnable parameters, always in the same order
+    fn params(&self) -> Vec<&Mat> {
+        vec![]
+    }
+    // every parameter paired with its gradient from the last backward call
+    fn params_and_grads(&mut self) -> Vec<(&mut Mat, &Mat)> {
+        vec![]
+    }
+    // group of every parameter, same order as params
+    fn param_groups(&self) -> Vec<ParamGroup> {
+        vec![ParamGroup::Weight; self.params().len()]
+    }
+    // per parameter, Some(rows) when the last gradient is zero outside those rows.
+    // only they are updated then, which keeps large embedding tables cheap
+    fn sparse_rows(&self) -> Vec<Option<Vec<usize>>> {
+        vec![None; self.params().len()]
+    }
+    // non learnable tensors that belong to the weights, like running statistics.
+    // saved and restored together with params but never touched by the optimizer
+    fn buffers(&self) -> Vec<&Mat> {
+        vec![]
+    }
+    fn buffers_mut(&mut self) -> Vec<&mut Mat> {
+        vec![]
+    }
+    fn output_shape(&self, input_shape: usize) -> usize {
+        input_shape
+    }
+    // reseed any randomness the layer uses
+    fn seed(&mut self, _seed: u64) {}
+    // anything else needed to resume training exactly (rngs, ...), saved in checkpoints
+    fn state(&self) -> Option<serde_json::Value> {
+        None
+    }
+    fn load_state(&mut self, _state: serde_json::Value) {}
+    fn name(&self) -> String;
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use rand::Rng;
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::layers::Layer;
+    use crate::nn::gradcheck::GradCheck;
+    use crate::tools::matrix::*;
+
+    pub fn rand_mat(row: usize, col: usize, rng: &mut ChaCha8Rng) -> Mat {
+        Mat::from_vec(
+            (0..row * col).map(|_| rng.gen_range(-1.0..1.0)).collect(),
+            row,
+            col,
+        )
+    }
+
+    // backward against central differences for the input and every parameter, see nn::gradcheck
+    pub fn check_gradients(layer: &mut dyn Layer, x: &Mat) {
+        GradCheck::new().layer(layer, x).assert_below(1e-6);
+    }
+}
@@ -0,0 +1,268 @@
+use crate::layers::{Layer, ParamGroup};
+use crate::tools::activations::{self, ActivationFn};
+use crate::tools::matrix::*;
+
+// element wise non linearity, see tools::activations for the functions
+pub struct Activation {
+    function: Box<dyn ActivationFn>,
+    // pre activation of the last forward call, every derivative is taken there
+    z: Option<Mat>,
+}
+
+impl Activation {
+    pub fn new<F: ActivationFn + 'static>(function: F) -> Activation {
+        Activation {
+            function: Box::new(function),
+            z: None,
+        }
+    }
+    pub fn relu() -> Activation {
+        Activation::new(activations::Relu)
+    }
+    pub fn sigmoid() -> Activation {
+        Activation::new(activations::Sigmoid)
+    }
+    pub fn tanh() -> Activation {
+        Activation::new(activations::Tanh)
+    }
+    pub fn leaky_relu(slope: f64) -> Activation {
+        Activation::new(activations::LeakyRelu { slope })
+    }
+    pub fn elu(alpha: f64) -> Activation {
+        Activation::new(activations::Elu { alpha })
+    }
+    pub fn selu() -> Activation {
+        Activation::new(activations::Selu)
+    }
+    pub fn gelu() -> Activation {
+        Activation::new(activations::Gelu)
+    }
+    pub fn swish() -> Activation {
+        Activation::new(activations::Swish)
+    }
+    pub fn mish() -> Activation {
+        Activation::new(activations::Mish)
+    }
+    pub fn softplus() -> Activation {
+        Activation::new(activations::Softplus)
+    }
+    pub fn softsign() -> Activation {
+        Activation::new(activations::Softsign)
+    }
+    pub fn hard_sigmoid() -> Activation {
+        Activation::new(activations::HardSigmoid)
+    }
+}
+
+impl Layer for Activation {
+    fn forward(&mut self, x: &Mat, _training: bool) -> Mat {
+        let mut a = x.clone();
+        for v in a.as_mut_slice().iter_mut() {
+            *v = self.function.f(*v);
+        }
+        self.z = Some(x.clone());
+        a
+    }
+    fn backward(&mut self, grad: &Mat) -> Mat {
+        let mut d = self.z.clone().expect("backward called before forward");
+        for v in d.as_mut_slice().iter_mut() {
+            *v = self.function.df(*v);
+        }
+        d.ele_mul(grad)
+    }
+    fn name(&self) -> String {
+        format!("{:?}", self.function)
+    }
+}
+
+// He et al. 2015, leaky relu with one learnable slope shared by every feature
+pub struct Prelu {
+    slope: Mat,
+    dslope: Mat,
+    z: Option<Mat>,
+}
+
+impl Prelu {
+    pub fn new(slope: f64) -> Prelu {
+        Prelu {
+            slope: Mat::val_mat(1, 1, slope),
+            dslope: Mat::new(1, 1),
+            z: None,
+        }
+    }
+    fn function(&self) -> activations::LeakyRelu {
+        activations::LeakyRelu {
+            slope: self.slope[(0, 0)],
+        }
+    }
+}
+
+impl Default for Prelu {
+    // the initial slope of the paper
+    fn default() -> Self {
+        Prelu::new(0.25)
+    }
+}
+
+impl Layer for Prelu {
+    fn forward(&mut self, x: &Mat, _training: bool) -> Mat {
+        let function = self.function();
+        let mut a = x.clone();
+        for v in a.as_mut_slice().iter_mut() {
+            *v = function.f(*v);
+        }
+        self.z = Some(x.clone());
+        a
+    }
+    fn backward(&mut self, grad: &Mat) -> Mat {
+        let function = self.function();
+        let z = self.z.as_ref().expect("backward called before forward");
+        let mut dx = grad.clone();
+        let mut dslope = 0.0;
+        for ((d, &z), &g) in dx
+            .as_mut_slice()
+            .iter_mut()
+            .zip(z.as_slice())
+            .zip(grad.as_slice())
+        {
+            *d = function.df(z) * g;
+            if z < 0.0 {
+                dslope += z * g;
+            }
+        }
+        self.dslope[(0, 0)] = dslope;
+        dx
+    }
+    fn params(&self) -> Vec<&Mat> {
+        vec![&self.slope]
+    }
+    fn params_and_grads(&mut self) -> Vec<(&mut Mat, &Mat)> {
+        vec![(&mut self.slope, &self.dslope)]
+    }
+    // decaying the slope towards 0 would push it back to a plain relu
+    fn param_groups(&self) -> Vec<ParamGroup> {
+        vec![ParamGroup::Slope]
+    }
+    fn name(&self) -> String {
+        format!("Prelu({})", self.slope[(0, 0)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::layers::activation::{Activation, Prelu};
+    use crate::layers::testing::{check_gradients, rand_mat};
+    use crate::layers::{Layer, ParamGroup};
+    use crate::nn::regularization::Regularization;
+    use crate::tools::activations::{self, ActivationFn};
+    use crate::tools::matrix::*;
+
+    #[test]
+    fn gradients_match_numeric() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        // spread out so the saturating and the piecewise parts all get hit
+        let x = rand_mat(4, 5, &mut rng).scaler_mul(4.0);
+        for mut layer in [
+            Activation::relu(),
+            Activation::sigmoid(),
+            Activation::tanh(),
+            Activation::leaky_relu(0.1),
+            Activation::elu(1.0),
+            Activation::selu(),
+            Activation::gelu(),
+            Activation::swish(),
+            Activation::mish(),
+            Activation::softplus(),
+            Activation::softsign(),
+            Activation::hard_sigmoid(),
+        ] {
+            check_gradients(&mut layer, &x);
+        }
+        check_gradients(&mut Prelu::default(), &x);
+    }
+
+    // f at each point against reference values
+    fn check_values(function: impl ActivationFn, points: &[(f64, f64)]) {
+        for &(x, expected) in points {
+            let f = function.f(x);
+            assert!(
+                (f - expected).abs() < 1e-12,
+                "{function:?}({x}) = {f}, not {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn forward_values() {
+        // x * Phi(x), e.g. Phi(1) = 0.8413447460685429
+        check_values(
+            activations::Gelu,
+            &[
+                (0.0, 0.0),
+                (1.0, 0.8413447460685429),
+                (-1.0, -0.15865525393145707),
+                (2.0, 1.9544997361036416),
+            ],
+        );
+        // scale 1.0507009873554805 and alpha 1.6732632423543772, saturating at -scale * alpha
+        check_values(
+            activations::Selu,
+            &[
+                (0.0, 0.0),
+                (1.0, 1.0507009873554805),
+                (-1.0, -1.1113307378125625),
+                (-50.0, -1.7580993408473766),
+            ],
+        );
+        check_values(
+            activations::HardSigmoid,
+            &[
+                (-4.0, 0.0),
+                (-3.0, 0.0),
+                (0.0, 0.5),
+                (1.5, 0.75),
+                (3.0, 1.0),
+                (9.0, 1.0),
+            ],
+        );
+        check_values(
+            activations::LeakyRelu { slope: 0.1 },
+            &[(-2.0, -0.2), (0.0, 0.0), (3.0, 3.0)],
+        );
+        check_values(
+            activations::Softplus,
+            &[
+                (0.0, std::f64::consts::LN_2),
+                (1.0, 1.3132616875182228),
+                (-1.0, 0.31326168751822286),
+                (1000.0, 1000.0),
+                (-1000.0, 0.0),
+            ],
+        );
+    }
+
+    #[test]
+    fn sigmoid_is_finite_for_huge_inputs() {
+        for x in [-1000.0, 1000.0] {
+            let (f, df) = (activations::Sigmoid.f(x), activations::Sigmoid.df(x));
+            assert!(f.is_finite() && df.is_finite());
+            assert_eq!(f, (x > 0.0) as u8 as f64);
+            assert_eq!(df, 0.0);
+        }
+        let mut layer = Activation::sigmoid();
+        let x = Mat::from_vec(vec![-1000.0, 1000.0], 2, 1);
+        assert_eq!(layer.forward(&x, true).as_slice(), &[0.0, 1.0]);
+        let dx = layer.backward(&Mat::val_mat(2, 1, 1.0));
+        assert!(dx.as_slice().iter().all(|d| d.is_finite()));
+    }
+
+    #[test]
+    fn prelu_slope_isnt_decayed_by_default() {
+        let prelu = Prelu::default();
+        assert_eq!(prelu.param_groups(), vec![ParamGroup::Slope]);
+        assert!(Regularization::l2(0.1).penalty(ParamGroup::Slope).is_none());
+    }
+}
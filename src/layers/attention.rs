@@ -0,0 +1,269 @@
+use crate::layers::time_distributed::{columns_to_steps, steps_to_columns};
+use crate::layers::{Layer, ParamGroup};
+use crate::nn::loss::softmax;
+use crate::tools::matrix::*;
+
+// softmax(q^T k / sqrt(d)) applied to v, one column per position: q is (d, queries),
+// k is (d, keys) and v is (dv, keys). with causal set query i only sees keys 0..=i.
+// returns the (dv, queries) output and the (queries, keys) attention weights
+pub fn scaled_dot_product_attention(q: &Mat, k: &Mat, v: &Mat, causal: bool) -> (Mat, Mat) {
+    assert_eq!(q.row(), k.row());
+    assert_eq!(k.col(), v.col());
+    let scale = 1.0 / (q.row() as f64).sqrt();
+    let mut scores = (&q.transpose() * k).scaler_mul(scale);
+    if causal {
+        for i in 0..scores.row() {
+            for j in i + 1..scores.col() {
+                scores[(i, j)] = f64::NEG_INFINITY;
+            }
+        }
+    }
+    // softmax works on columns, the weights of a query are a row
+    let weights = softmax(&scores.transpose()).transpose();
+    (v * &weights.transpose(), weights)
+}
+
+// gradients of scaled_dot_product_attention w.r.t. q, k and v given the output gradient
+pub fn attention_backward(q: &Mat, k: &Mat, v: &Mat, weights: &Mat, grad: &Mat) -> (Mat, Mat, Mat) {
+    let scale = 1.0 / (q.row() as f64).sqrt();
+    let dv = grad * weights;
+    let dweights = &grad.transpose() * v;
+    // softmax backward row by row, masked weights are 0 and get no gradient
+    let mut dscores = Mat::zeroes_like(weights);
+    for i in 0..weights.row() {
+        let dot: f64 = (0..weights.col())
+            .map(|j| dweights[(i, j)] * weights[(i, j)])
+            .sum();
+        for j in 0..weights.col() {
+            dscores[(i, j)] = weights[(i, j)] * (dweights[(i, j)] - dot) * scale;
+        }
+    }
+    let dq = k * &dscores.transpose();
+    let dk = q * &dscores;
+    (dq, dk, dv)
+}
+
+// Multi head self attention over sequences laid out like layers::recurrent expects them.
+// every head attends with its own d_model / heads rows of the q, k and v projections,
+// the heads are concatenated and projected back to d_model
+pub struct MultiHeadAttention {
+    d_model: usize,
+    heads: usize,
+    steps: usize,
+    causal: bool,
+    // q, k, v and output projections with their biases
+    w: Vec<Mat>,
+    b: Vec<Mat>,
+    dw: Vec<Mat>,
+    db: Vec<Mat>,
+    // input, projections, concatenated heads and the attention weights of every (sample, head)
+    cache: Option<AttentionCache>,
+}
+
+struct AttentionCache {
+    x: Mat,
+    q: Mat,
+    k: Mat,
+    v: Mat,
+    heads: Mat,
+    weights: Vec<Mat>,
+}
+
+impl MultiHeadAttention {
+    pub fn new(d_model: usize, heads: usize, steps: usize) -> MultiHeadAttention {
+        assert!(heads > 0);
+        assert_eq!(d_model % heads, 0, "d_model not divisible by heads");
+        let bound = 1.0 / (d_model as f64).sqrt();
+        let w: Vec<Mat> = (0..4)
+            .map(|_| Mat::rand_mat(d_model, d_model, -bound, bound))
+            .collect();
+        let b = vec![Mat::new(d_model, 1); 4];
+        MultiHeadAttention {
+            d_model,
+            heads,
+            steps,
+            causal: false,
+            dw: w.iter().map(Mat::zeroes_like).collect(),
+            db: b.clone(),
+            w,
+            b,
+            cache: None,
+        }
+    }
+    // position i only attends to positions up to i
+    pub fn causal(mut self, causal: bool) -> MultiHeadAttention {
+        self.causal = causal;
+        self
+    }
+    // attention weights of every head for the samples of the last forward call,
+    // index sample * heads + head
+    pub fn attention_weights(&self) -> Option<&[Mat]> {
+        self.cache.as_ref().map(|cache| &cache.weights[..])
+    }
+    // rows of head h, columns of sample s
+    fn block(&self, m: &Mat, h: usize, s: usize) -> Mat {
+        let dk = self.d_model / self.heads;
+        m.rows(h * dk, (h + 1) * dk)
+            .cols(s * self.steps, (s + 1) * self.steps)
+    }
+    fn set_block(&self, m: &mut Mat, h: usize, s: usize, block: &Mat) {
+        let dk = self.d_model / self.heads;
+        for i in 0..dk {
+            for t in 0..self.steps {
+                m[(h * dk + i, s * self.steps + t)] = block[(i, t)];
+            }
+        }
+    }
+}
+
+impl Layer for MultiHeadAttention {
+    fn forward(&mut self, x: &Mat, _training: bool) -> Mat {
+        assert_eq!(x.row(), self.steps * self.d_model);
+        let n = x.col();
+        let x = steps_to_columns(x, self.steps);
+        let project = |i: usize| (&self.w[i] * &x).add_column(&self.b[i]);
+        let (q, k, v) = (project(0), project(1), project(2));
+        let mut heads = Mat::zeroes_like(&q);
+        let mut weights = vec![];
+        for s in 0..n {
+            for h in 0..self.heads {
+                let (out, w) = scaled_dot_product_attention(
+                    &self.block(&q, h, s),
+                    &self.block(&k, h, s),
+                    &self.block(&v, h, s),
+                    self.causal,
+                );
+                self.set_block(&mut heads, h, s, &out);
+                weights.push(w);
+            }
+        }
+        let out = (&self.w[3] * &heads).add_column(&self.b[3]);
+        self.cache = Some(AttentionCache {
+            x,
+            q,
+            k,
+            v,
+            heads,
+            weights,
+        });
+        columns_to_steps(&out, self.steps)
+    }
+    fn backward(&mut self, grad: &Mat) -> Mat {
+        let cache = self.cache.take().expect("backward called before forward");
+        let grad = steps_to_columns(grad, self.steps);
+        self.dw[3] = &grad * &cache.heads.transpose();
+        self.db[3] = grad.sum_columns();
+        let dheads = &self.w[3].transpose() * &grad;
+        let mut dq = Mat::zeroes_like(&cache.q);
+        let mut dk = Mat::zeroes_like(&cache.k);
+        let mut dv = Mat::zeroes_like(&cache.v);
+        for s in 0..grad.col() / self.steps {
+            for h in 0..self.heads {
+                let (bq, bk, bv) = attention_backward(
+                    &self.block(&cache.q, h, s),
+                    &self.block(&cache.k, h, s),
+                    &self.block(&cache.v, h, s),
+                    &cache.weights[s * self.heads + h],
+                    &self.block(&dheads, h, s),
+                );
+                self.set_block(&mut dq, h, s, &bq);
+                self.set_block(&mut dk, h, s, &bk);
+                self.set_block(&mut dv, h, s, &bv);
+            }
+        }
+        let mut dx = Mat::zeroes_like(&cache.x);
+        for (i, d) in [dq, dk, dv].iter().enumerate() {
+            self.dw[i] = d * &cache.x.transpose();
+            self.db[i] = d.sum_columns();
+            dx = &dx + &(&self.w[i].transpose() * d);
+        }
+        self.cache = Some(cache);
+        columns_to_steps(&dx, self.steps)
+    }
+    fn params(&self) -> Vec<&Mat> {
+        self.w.iter().chain(&self.b).collect()
+    }
+    fn params_and_grads(&mut self) -> Vec<(&mut Mat, &Mat)> {
+        self.w
+            .iter_mut()
+            .zip(&self.dw)
+            .chain(self.b.iter_mut().zip(&self.db))
+            .collect()
+    }
+    fn param_groups(&self) -> Vec<ParamGroup> {
+        [vec![ParamGroup::Weight; 4], vec![ParamGroup::Bias; 4]].concat()
+    }
+    fn output_shape(&self, input_shape: usize) -> usize {
+        assert_eq!(input_shape, self.steps * self.d_model);
+        input_shape
+    }
+    fn name(&self) -> String {
+        format!(
+            "MultiHeadAttention({}, {} heads, {} steps{})",
+            self.d_model,
+            self.heads,
+            self.steps,
+            if self.causal { ", causal" } else { "" }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::layers::attention::MultiHeadAttention;
+    use crate::layers::testing::{check_gradients, rand_mat};
+    use crate::layers::Layer;
+
+    #[test]
+    fn gradients_match_numeric() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        for causal in [false, true] {
+            let mut layer = MultiHeadAttention::new(4, 2, 3).causal(causal);
+            check_gradients(&mut layer, &rand_mat(12, 2, &mut rng));
+        }
+    }
+
+    #[test]
+    fn causal_weights_above_the_diagonal_are_zero() {
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+        let mut layer = MultiHeadAttention::new(4, 2, 3).causal(true);
+        layer.forward(&rand_mat(12, 2, &mut rng), false);
+        let weights = layer.attention_weights().unwrap();
+        // 2 samples of 2 heads
+        assert_eq!(weights.len(), 4);
+        for w in weights {
+            assert_eq!(w.shape(), (3, 3));
+            for i in 0..3 {
+                let row: f64 = (0..3).map(|j| w[(i, j)]).sum();
+                assert!((row - 1.0).abs() < 1e-12);
+                for j in i + 1..3 {
+                    assert_eq!(w[(i, j)], 0.0);
+                }
+            }
+            assert_eq!(w[(0, 0)], 1.0);
+        }
+    }
+
+    #[test]
+    fn causal_outputs_ignore_later_positions() {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let x = rand_mat(12, 2, &mut rng);
+        let mut changed = x.clone();
+        // the last of the 3 positions
+        for i in 8..12 {
+            changed[(i, 0)] += 1.0;
+            changed[(i, 1)] -= 1.0;
+        }
+        for causal in [true, false] {
+            let mut layer = MultiHeadAttention::new(4, 2, 3).causal(causal);
+            let before = layer.forward(&x, false);
+            let after = layer.forward(&changed, false);
+            let same = before.rows(0, 8).as_slice() == after.rows(0, 8).as_slice();
+            assert_eq!(same, causal);
+            assert_ne!(before.rows(8, 12).as_slice(), after.rows(8, 12).as_slice());
+        }
+    }
+}
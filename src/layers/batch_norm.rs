@@ -0,0 +1,235 @@
+use crate::layers::{Layer, ParamGroup};
+use crate::tools::matrix::*;
+
+// Normalizes every feature over the batch, then scales and shifts it with learnable gamma and beta.
+// running mean and variance are tracked while training and used instead of the batch
+// statistics in eval mode, so a single sample can be predicted. a training batch of one
+// sample (like the last batch of a DataLoader without drop_last) has no variance,
+// it is normalized with the running statistics and leaves them alone
+pub struct BatchNorm1d {
+    gamma: Mat,
+    beta: Mat,
+    dgamma: Mat,
+    dbeta: Mat,
+    running_mean: Mat,
+    running_var: Mat,
+    // weight of the current batch in the running statistics
+    momentum: f64,
+    eps: f64,
+    // normalized input and 1 / std per feature of the last forward call
+    x_hat: Option<Mat>,
+    inv_std: Option<Mat>,
+    // whether the last forward normalized with the batch statistics
+    batch_stats: bool,
+}
+
+impl BatchNorm1d {
+    pub fn new(features: usize) -> BatchNorm1d {
+        BatchNorm1d {
+            gamma: Mat::val_mat(features, 1, 1.0),
+            beta: Mat::new(features, 1),
+            dgamma: Mat::new(features, 1),
+            dbeta: Mat::new(features, 1),
+            running_mean: Mat::new(features, 1),
+            running_var: Mat::val_mat(features, 1, 1.0),
+            momentum: 0.1,
+            eps: 1e-5,
+            x_hat: None,
+            inv_std: None,
+            batch_stats: false,
+        }
+    }
+    pub fn momentum(mut self, momentum: f64) -> BatchNorm1d {
+        assert!((0.0..=1.0).contains(&momentum));
+        self.momentum = momentum;
+        self
+    }
+    pub fn eps(mut self, eps: f64) -> BatchNorm1d {
+        self.eps = eps;
+        self
+    }
+    pub fn running_mean(&self) -> &Mat {
+        &self.running_mean
+    }
+    pub fn running_var(&self) -> &Mat {
+        &self.running_var
+    }
+}
+
+impl Layer for BatchNorm1d {
+    fn forward(&mut self, x: &Mat, training: bool) -> Mat {
+        assert_eq!(x.row(), self.gamma.row());
+        let n = x.col();
+        let batch_stats = training && n > 1;
+        let (mean, var) = if batch_stats {
+            let mean = x.sum_columns().scaler_mul(1.0 / n as f64);
+            let mut var = Mat::zeroes_like(&mean);
+            for i in 0..x.row() {
+                for j in 0..n {
+                    var[(i, 0)] += (x[(i, j)] - mean[(i, 0)]).powi(2);
+                }
+            }
+            // the batch is normalized with the biased variance, the running one is unbiased
+            let unbiased = var.scaler_mul(1.0 / (n - 1) as f64);
+            let var = var.scaler_mul(1.0 / n as f64);
+            let keep = 1.0 - self.momentum;
+            self.running_mean =
+                &self.running_mean.scaler_mul(keep) + &mean.scaler_mul(self.momentum);
+            self.running_var =
+                &self.running_var.scaler_mul(keep) + &unbiased.scaler_mul(self.momentum);
+            (mean, var)
+        } else {
+            (self.running_mean.clone(), self.running_var.clone())
+        };
+        let mut inv_std = var;
+        for v in inv_std.as_mut_slice().iter_mut() {
+            *v = 1.0 / (*v + self.eps).sqrt();
+        }
+        let mut x_hat = x.clone();
+        let mut out = x.clone();
+        for i in 0..x.row() {
+            for j in 0..n {
+                x_hat[(i, j)] = (x[(i, j)] - mean[(i, 0)]) * inv_std[(i, 0)];
+                out[(i, j)] = self.gamma[(i, 0)] * x_hat[(i, j)] + self.beta[(i, 0)];
+            }
+        }
+        self.x_hat = Some(x_hat);
+        self.inv_std = Some(inv_std);
+        self.batch_stats = batch_stats;
+        out
+    }
+    fn backward(&mut self, grad: &Mat) -> Mat {
+        let missing = "backward called before forward";
+        let x_hat = self.x_hat.as_ref().expect(missing);
+        let inv_std = self.inv_std.as_ref().expect(missing);
+        let n = grad.col();
+        self.dbeta = grad.sum_columns();
+        self.dgamma = grad.ele_mul(x_hat).sum_columns();
+        let mut dx = Mat::zeroes_like(grad);
+        for i in 0..grad.row() {
+            let scale = self.gamma[(i, 0)] * inv_std[(i, 0)];
+            if !self.batch_stats {
+                // running statistics are constants
+                for j in 0..n {
+                    dx[(i, j)] = scale * grad[(i, j)];
+                }
+                continue;
+            }
+            // mean and variance depend on every sample of the batch
+            let (sum, dot) = (self.dbeta[(i, 0)], self.dgamma[(i, 0)]);
+            for j in 0..n {
+                dx[(i, j)] = scale * (grad[(i, j)] - (sum + x_hat[(i, j)] * dot) / n as f64);
+            }
+        }
+        dx
+    }
+    fn params(&self) -> Vec<&Mat> {
+        vec![&self.gamma, &self.beta]
+    }
+    fn params_and_grads(&mut self) -> Vec<(&mut Mat, &Mat)> {
+        vec![
+            (&mut self.gamma, &self.dgamma),
+            (&mut self.beta, &self.dbeta),
+        ]
+    }
+    fn param_groups(&self) -> Vec<ParamGroup> {
+        vec![ParamGroup::Norm, ParamGroup::Norm]
+    }
+    fn buffers(&self) -> Vec<&Mat> {
+        vec![&self.running_mean, &self.running_var]
+    }
+    fn buffers_mut(&mut self) -> Vec<&mut Mat> {
+        vec![&mut self.running_mean, &mut self.running_var]
+    }
+    fn name(&self) -> String {
+        format!("BatchNorm1d({})", self.gamma.row())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::layers::batch_norm::BatchNorm1d;
+    use crate::layers::dense::Dense;
+    use crate::layers::testing::{check_gradients, rand_mat};
+    use crate::layers::Layer;
+    use crate::nn::loss::Loss;
+    use crate::nn::NN;
+
+    #[test]
+    fn gradients_match_numeric() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let mut layer = BatchNorm1d::new(3);
+        layer.params_and_grads()[0]
+            .0
+            .clone_from(&rand_mat(3, 1, &mut rng));
+        layer.params_and_grads()[1]
+            .0
+            .clone_from(&rand_mat(3, 1, &mut rng));
+        check_gradients(&mut layer, &rand_mat(3, 5, &mut rng));
+    }
+
+    #[test]
+    fn eval_mode_uses_running_statistics() {
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+        let mut layer = BatchNorm1d::new(2).momentum(1.0);
+        let x = rand_mat(2, 4, &mut rng);
+        let train = layer.forward(&x, true);
+        // with momentum 1 the running stats are the last batch's, up to the unbiased variance
+        let eval = layer.forward(&x, false);
+        for (t, e) in train.as_slice().iter().zip(eval.as_slice()) {
+            assert!((t * (3.0f64 / 4.0).sqrt() - e).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn running_statistics_are_saved_with_the_weights() {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let make = || {
+            NN::from_layers(
+                3,
+                vec![Box::new(Dense::new(3, 4)), Box::new(BatchNorm1d::new(4))],
+                Loss::MeanSquaredError,
+            )
+        };
+        let mut nn = make();
+        let batch: Vec<_> = (0..6).map(|_| rand_mat(3, 1, &mut rng)).collect();
+        nn.train_batch(
+            &batch,
+            &batch
+                .iter()
+                .map(|_| rand_mat(4, 1, &mut rng))
+                .collect::<Vec<_>>(),
+        );
+        nn.eval_mode();
+
+        let path = std::env::temp_dir().join(format!("kek_bn_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        nn.save_weights(path);
+        let mut loaded = make();
+        loaded.load_weights(path);
+        std::fs::remove_file(path).unwrap();
+        loaded.eval_mode();
+
+        let x = rand_mat(3, 1, &mut rng);
+        assert_eq!(nn.predict(&x).as_slice(), loaded.predict(&x).as_slice());
+    }
+
+    #[test]
+    fn a_single_sample_batch_uses_running_statistics() {
+        let mut rng = ChaCha8Rng::seed_from_u64(4);
+        let mut layer = BatchNorm1d::new(3);
+        layer.forward(&rand_mat(3, 4, &mut rng), true);
+        let (mean, var) = (layer.running_mean().clone(), layer.running_var().clone());
+
+        let x = rand_mat(3, 1, &mut rng);
+        let train = layer.forward(&x, true);
+        assert_eq!(layer.running_mean().as_slice(), mean.as_slice());
+        assert_eq!(layer.running_var().as_slice(), var.as_slice());
+        assert_eq!(train.as_slice(), layer.forward(&x, false).as_slice());
+        assert!(train.as_slice().iter().all(|v| v.is_finite()));
+        check_gradients(&mut layer, &x);
+    }
+}
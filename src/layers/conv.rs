@@ -0,0 +1,350 @@
+use crate::layers::{Layer, ParamGroup};
+use crate::tools::matrix::*;
+
+// (channels, height, width) of an image, samples are stored channel by channel
+// and row by row in one column, like data::image_folder::load_image
+pub type ImageShape = (usize, usize, usize);
+
+// output size of a sliding window along one axis
+pub fn window_count(
+    size: usize,
+    kernel: usize,
+    stride: usize,
+    padding: usize,
+    dilation: usize,
+) -> usize {
+    let span = dilation * (kernel - 1) + 1;
+    assert!(size + 2 * padding >= span, "kernel doesn't fit the input");
+    (size + 2 * padding - span) / stride + 1
+}
+
+// 2d convolution over square kernels, computed by unrolling the input patches (im2col)
+// so every group is a single Mat multiplication
+pub struct Conv2d {
+    input: ImageShape,
+    out_channels: usize,
+    kernel: usize,
+    stride: usize,
+    padding: usize,
+    dilation: usize,
+    groups: usize,
+    // (out_channels, in_channels / groups * kernel * kernel), group g owns a block of rows
+    w: Mat,
+    b: Mat,
+    dw: Mat,
+    db: Mat,
+    // unrolled input of every group and the batch size of the last forward call
+    cols: Vec<Mat>,
+    batch: usize,
+}
+
+impl Conv2d {
+    // stride 1, no padding, no dilation and a single group, see the builder methods
+    pub fn new(input: ImageShape, out_channels: usize, kernel: usize) -> Conv2d {
+        let mut conv = Conv2d {
+            input,
+            out_channels,
+            kernel,
+            stride: 1,
+            padding: 0,
+            dilation: 1,
+            groups: 1,
+            w: Mat::new(0, 0),
+            b: Mat::new(0, 0),
+            dw: Mat::new(0, 0),
+            db: Mat::new(0, 0),
+            cols: vec![],
+            batch: 0,
+        };
+        conv.init();
+        conv
+    }
+    pub fn stride(mut self, stride: usize) -> Conv2d {
+        assert!(stride > 0);
+        self.stride = stride;
+        self
+    }
+    pub fn padding(mut self, padding: usize) -> Conv2d {
+        self.padding = padding;
+        self
+    }
+    pub fn dilation(mut self, dilation: usize) -> Conv2d {
+        assert!(dilation > 0);
+        self.dilation = dilation;
+        self
+    }
+    // channels are split into independent groups, changes the weight shape so weights are redrawn
+    pub fn groups(mut self, groups: usize) -> Conv2d {
+        self.groups = groups;
+        self.init();
+        self
+    }
+    // uniform in +-1/sqrt(fan_in), the [-1, 1] Dense uses blows up over a kernel's many inputs
+    fn init(&mut self) {
+        assert!(self.groups > 0);
+        assert_eq!(
+            self.input.0 % self.groups,
+            0,
+            "in channels not divisible by groups"
+        );
+        assert_eq!(
+            self.out_channels % self.groups,
+            0,
+            "out channels not divisible by groups"
+        );
+        let fan_in = self.input.0 / self.groups * self.kernel * self.kernel;
+        let bound = 1.0 / (fan_in as f64).sqrt();
+        self.w = Mat::rand_mat(self.out_channels, fan_in, -bound, bound);
+        self.b = Mat::rand_mat(self.out_channels, 1, -bound, bound);
+        self.dw = Mat::zeroes_like(&self.w);
+        self.db = Mat::zeroes_like(&self.b);
+    }
+    pub fn output(&self) -> ImageShape {
+        let (_, h, w) = self.input;
+        let out = |size| window_count(size, self.kernel, self.stride, self.padding, self.dilation);
+        (self.out_channels, out(h), out(w))
+    }
+    // input position read by kernel offset k at output position o, None inside the padding
+    fn source(&self, o: usize, k: usize, size: usize) -> Option<usize> {
+        let pos = (o * self.stride + k * self.dilation) as isize - self.padding as isize;
+        (pos >= 0 && (pos as usize) < size).then_some(pos as usize)
+    }
+    // for every (patch row, output column) pair the flat input index it reads, or None
+    // for padding. patch rows are (channel, ky, kx), output columns (sample, y, x)
+    fn patches(&self, group: usize, n: usize, mut f: impl FnMut(usize, usize, usize, usize)) {
+        let (_, h, w) = self.input;
+        let (_, oh, ow) = self.output();
+        let channels = self.input.0 / self.groups;
+        for ci in 0..channels {
+            let c = group * channels + ci;
+            for ky in 0..self.kernel {
+                for kx in 0..self.kernel {
+                    let row = (ci * self.kernel + ky) * self.kernel + kx;
+                    for oy in 0..oh {
+                        let Some(iy) = self.source(oy, ky, h) else {
+                            continue;
+                        };
+                        for ox in 0..ow {
+                            let Some(ix) = self.source(ox, kx, w) else {
+                                continue;
+                            };
+                            for s in 0..n {
+                                f(row, s * oh * ow + oy * ow + ox, (c * h + iy) * w + ix, s);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+    // rows of a group's output channels
+    fn group_rows(&self, m: &Mat, group: usize) -> Mat {
+        let per = self.out_channels / self.groups;
+        let mut out = Mat::new(per, m.col());
+        for i in 0..per {
+            for j in 0..m.col() {
+                out[(i, j)] = m[(group * per + i, j)];
+            }
+        }
+        out
+    }
+}
+
+impl Layer for Conv2d {
+    fn forward(&mut self, x: &Mat, _training: bool) -> Mat {
+        let (c, h, w) = self.input;
+        assert_eq!(x.row(), c * h * w);
+        let n = x.col();
+        let (_, oh, ow) = self.output();
+        let per = self.out_channels / self.groups;
+        let mut out = Mat::new(self.out_channels * oh * ow, n);
+        self.cols.clear();
+        for g in 0..self.groups {
+            let mut cols = Mat::new(self.w.col(), n * oh * ow);
+            self.patches(g, n, |row, col, i, s| cols[(row, col)] = x[(i, s)]);
+            let z = &self.group_rows(&self.w, g) * &cols;
+            for co in 0..per {
+                let channel = g * per + co;
+                for s in 0..n {
+                    for p in 0..oh * ow {
+                        out[(channel * oh * ow + p, s)] =
+                            z[(co, s * oh * ow + p)] + self.b[(channel, 0)];
+                    }
+                }
+            }
+            self.cols.push(cols);
+        }
+        self.batch = n;
+        out
+    }
+    fn backward(&mut self, grad: &Mat) -> Mat {
+        assert!(!self.cols.is_empty(), "backward called before forward");
+        let n = self.batch;
+        let (c, h, w) = self.input;
+        let (_, oh, ow) = self.output();
+        let per = self.out_channels / self.groups;
+        let mut dx = Mat::new(c * h * w, n);
+        for g in 0..self.groups {
+            // back to (channel, (sample, y, x)) like the forward product
+            let mut dz = Mat::new(per, n * oh * ow);
+            for co in 0..per {
+                let channel = g * per + co;
+                let mut db = 0.0;
+                for s in 0..n {
+                    for p in 0..oh * ow {
+                        dz[(co, s * oh * ow + p)] = grad[(channel * oh * ow + p, s)];
+                        db += grad[(channel * oh * ow + p, s)];
+                    }
+                }
+                self.db[(channel, 0)] = db;
+            }
+            let dw = &dz * &self.cols[g].transpose();
+            for i in 0..per {
+                for j in 0..dw.col() {
+                    self.dw[(g * per + i, j)] = dw[(i, j)];
+                }
+            }
+            let dcols = &self.group_rows(&self.w, g).transpose() * &dz;
+            self.patches(g, n, |row, col, i, s| dx[(i, s)] += dcols[(row, col)]);
+        }
+        dx
+    }
+    fn params(&self) -> Vec<&Mat> {
+        vec![&self.w, &self.b]
+    }
+    fn params_and_grads(&mut self) -> Vec<(&mut Mat, &Mat)> {
+        vec![(&mut self.w, &self.dw), (&mut self.b, &self.db)]
+    }
+    fn param_groups(&self) -> Vec<ParamGroup> {
+        vec![ParamGroup::Weight, ParamGroup::Bias]
+    }
+    fn output_shape(&self, input_shape: usize) -> usize {
+        let (c, h, w) = self.input;
+        assert_eq!(input_shape, c * h * w);
+        let (oc, oh, ow) = self.output();
+        oc * oh * ow
+    }
+    fn name(&self) -> String {
+        format!(
+            "Conv2d({:?} -> {:?}, kernel {}, stride {}, padding {}, dilation {}, groups {})",
+            self.input,
+            self.output(),
+            self.kernel,
+            self.stride,
+            self.padding,
+            self.dilation,
+            self.groups
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::layers::conv::{Conv2d, ImageShape};
+    use crate::layers::testing::{check_gradients, rand_mat};
+    use crate::layers::Layer;
+    use crate::models;
+    use crate::tools::matrix::*;
+
+    // direct sum over the kernel for every output element with (stride, padding, dilation),
+    // groups aren't covered
+    fn naive(
+        x: &Mat,
+        (c, h, w): ImageShape,
+        weights: &Mat,
+        bias: &Mat,
+        kernel: usize,
+        (stride, padding, dilation): (usize, usize, usize),
+    ) -> Mat {
+        let out = |size: usize| (size + 2 * padding - dilation * (kernel - 1) - 1) / stride + 1;
+        let (oh, ow) = (out(h), out(w));
+        let filters = weights.row();
+        let mut y = Mat::new(filters * oh * ow, x.col());
+        for s in 0..x.col() {
+            for f in 0..filters {
+                for oy in 0..oh {
+                    for ox in 0..ow {
+                        let mut sum = bias[(f, 0)];
+                        for ci in 0..c {
+                            for ky in 0..kernel {
+                                for kx in 0..kernel {
+                                    let iy =
+                                        (oy * stride + ky * dilation) as isize - padding as isize;
+                                    let ix =
+                                        (ox * stride + kx * dilation) as isize - padding as isize;
+                                    if iy < 0 || ix < 0 || iy >= h as isize || ix >= w as isize {
+                                        continue;
+                                    }
+                                    let (iy, ix) = (iy as usize, ix as usize);
+                                    sum += weights[(f, (ci * kernel + ky) * kernel + kx)]
+                                        * x[((ci * h + iy) * w + ix, s)];
+                                }
+                            }
+                        }
+                        y[((f * oh + oy) * ow + ox, s)] = sum;
+                    }
+                }
+            }
+        }
+        y
+    }
+
+    #[test]
+    fn gradients_match_numeric() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let mut plain = Conv2d::new((2, 5, 5), 3, 3);
+        check_gradients(&mut plain, &rand_mat(2 * 5 * 5, 2, &mut rng));
+        let mut strided = Conv2d::new((4, 6, 6), 2, 3)
+            .stride(2)
+            .padding(1)
+            .dilation(2)
+            .groups(2);
+        check_gradients(&mut strided, &rand_mat(4 * 6 * 6, 1, &mut rng));
+    }
+
+    #[test]
+    fn forward_matches_a_naive_convolution() {
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+        for (stride, padding, dilation) in [(1, 0, 1), (2, 1, 1), (1, 2, 2)] {
+            let input = (2, 7, 6);
+            let mut conv = Conv2d::new(input, 3, 3)
+                .stride(stride)
+                .padding(padding)
+                .dilation(dilation);
+            let x = rand_mat(2 * 7 * 6, 2, &mut rng);
+            let y = conv.forward(&x, false);
+            let params = conv.params();
+            let settings = (stride, padding, dilation);
+            let expected = naive(&x, input, params[0], params[1], 3, settings);
+            assert_eq!(y.shape(), expected.shape());
+            let (c, oh, ow) = conv.output();
+            assert_eq!(y.row(), c * oh * ow);
+            for (a, b) in y.as_slice().iter().zip(expected.as_slice()) {
+                assert!((a - b).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn lenet5_shapes() {
+        let mut nn = models::lenet5(10);
+        let mut shape = 28 * 28;
+        let shapes: Vec<usize> = nn
+            .layers()
+            .iter()
+            .map(|layer| {
+                shape = layer.output_shape(shape);
+                shape
+            })
+            .collect();
+        // conv 6x28x28, pool 6x14x14, conv 16x10x10, pool 16x5x5, then the dense layers
+        assert_eq!(
+            shapes,
+            [4704, 4704, 1176, 1600, 1600, 400, 400, 120, 120, 84, 84, 10]
+        );
+        assert_eq!(nn.forward(&Mat::new(28 * 28, 3)).shape(), (10, 3));
+    }
+}
@@ -0,0 +1,99 @@
+use crate::layers::{Layer, ParamGroup};
+use crate::tools::matrix::*;
+
+// fully connected layer: w * x + b
+pub struct Dense {
+    w: Mat,
+    b: Mat,
+    dw: Mat,
+    db: Mat,
+    x: Option<Mat>,
+}
+
+impl Dense {
+    // weights and biases start uniform in [-1, 1]
+    pub fn new(input_shape: usize, output_shape: usize) -> Dense {
+        Dense::from_weights(
+            Mat::rand_mat(output_shape, input_shape, -1.0, 1.0),
+            Mat::rand_mat(output_shape, 1, -1.0, 1.0),
+        )
+    }
+    // uniform in +-1/sqrt(input_shape), keeps activations in range for wide inputs
+    pub fn scaled(input_shape: usize, output_shape: usize) -> Dense {
+        let bound = 1.0 / (input_shape as f64).sqrt();
+        Dense::from_weights(
+            Mat::rand_mat(output_shape, input_shape, -bound, bound),
+            Mat::rand_mat(output_shape, 1, -bound, bound),
+        )
+    }
+    // Glorot & Bengio 2010, uniform in +-sqrt(6 / (in + out)) with zero biases, for tanh and sigmoid
+    pub fn xavier(input_shape: usize, output_shape: usize) -> Dense {
+        let bound = (6.0 / (input_shape + output_shape) as f64).sqrt();
+        Dense::from_weights(
+            Mat::rand_mat(output_shape, input_shape, -bound, bound),
+            Mat::new(output_shape, 1),
+        )
+    }
+    // He et al. 2015, uniform in +-sqrt(6 / in) with zero biases, for relu
+    pub fn he(input_shape: usize, output_shape: usize) -> Dense {
+        let bound = (6.0 / input_shape as f64).sqrt();
+        Dense::from_weights(
+            Mat::rand_mat(output_shape, input_shape, -bound, bound),
+            Mat::new(output_shape, 1),
+        )
+    }
+    pub fn from_weights(w: Mat, b: Mat) -> Dense {
+        assert_eq!(b.shape(), (w.row(), 1));
+        Dense {
+            dw: Mat::zeroes_like(&w),
+            db: Mat::zeroes_like(&b),
+            w,
+            b,
+            x: None,
+        }
+    }
+}
+
+impl Layer for Dense {
+    fn forward(&mut self, x: &Mat, _training: bool) -> Mat {
+        let z = (&self.w * x).add_column(&self.b);
+        self.x = Some(x.clone());
+        z
+    }
+    fn backward(&mut self, grad: &Mat) -> Mat {
+        let x = self.x.as_ref().expect("backward called before forward");
+        self.dw = grad * &x.transpose();
+        self.db = grad.sum_columns();
+        &self.w.transpose() * grad
+    }
+    fn params(&self) -> Vec<&Mat> {
+        vec![&self.w, &self.b]
+    }
+    fn params_and_grads(&mut self) -> Vec<(&mut Mat, &Mat)> {
+        vec![(&mut self.w, &self.dw), (&mut self.b, &self.db)]
+    }
+    fn param_groups(&self) -> Vec<ParamGroup> {
+        vec![ParamGroup::Weight, ParamGroup::Bias]
+    }
+    fn output_shape(&self, _input_shape: usize) -> usize {
+        self.w.row()
+    }
+    fn name(&self) -> String {
+        format!("Dense({}, {})", self.w.col(), self.w.row())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::layers::dense::Dense;
+    use crate::layers::testing::{check_gradients, rand_mat};
+
+    #[test]
+    fn gradients_match_numeric() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        check_gradients(&mut Dense::new(3, 4), &rand_mat(3, 5, &mut rng));
+    }
+}
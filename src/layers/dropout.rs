@@ -0,0 +1,180 @@
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::layers::Layer;
+use crate::tools::matrix::*;
+
+// Zeroes every element with probability p while training.
+// inverted dropout scales the kept elements by 1 / (1 - p) during training and does nothing
+// at inference, standard dropout leaves training values alone and scales by (1 - p) at inference.
+// either way the expected activation is the same in both modes.
+pub struct Dropout {
+    p: f64,
+    inverted: bool,
+    rng: ChaCha8Rng,
+    mask: Option<Mat>,
+}
+
+impl Dropout {
+    pub fn new(p: f64) -> Dropout {
+        assert!(
+            (0.0..1.0).contains(&p),
+            "dropout probability has to be in [0, 1)"
+        );
+        Dropout {
+            p,
+            inverted: true,
+            rng: ChaCha8Rng::from_entropy(),
+            mask: None,
+        }
+    }
+    pub fn standard(p: f64) -> Dropout {
+        Dropout {
+            inverted: false,
+            ..Dropout::new(p)
+        }
+    }
+    pub fn p(&self) -> f64 {
+        self.p
+    }
+}
+
+impl Layer for Dropout {
+    fn forward(&mut self, x: &Mat, training: bool) -> Mat {
+        let keep = 1.0 - self.p;
+        if !training {
+            self.mask = None;
+            return if self.inverted {
+                x.clone()
+            } else {
+                x.scaler_mul(keep)
+            };
+        }
+        let scale = if self.inverted { 1.0 / keep } else { 1.0 };
+        let mut mask = Mat::zeroes_like(x);
+        for m in mask.as_mut_slice().iter_mut() {
+            if self.rng.gen::<f64>() >= self.p {
+                *m = scale;
+            }
+        }
+        let out = x.ele_mul(&mask);
+        self.mask = Some(mask);
+        out
+    }
+    fn backward(&mut self, grad: &Mat) -> Mat {
+        match &self.mask {
+            Some(mask) => grad.ele_mul(mask),
+            // last forward was at inference
+            None if self.inverted => grad.clone(),
+            None => grad.scaler_mul(1.0 - self.p),
+        }
+    }
+    fn seed(&mut self, seed: u64) {
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+    }
+    fn state(&self) -> Option<serde_json::Value> {
+        Some(serde_json::to_value(&self.rng).expect("couldn't save dropout rng"))
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        self.rng = serde_json::from_value(state).expect("bad dropout state");
+    }
+    fn name(&self) -> String {
+        let kind = if self.inverted {
+            "Dropout"
+        } else {
+            "StandardDropout"
+        };
+        format!("{}({})", kind, self.p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::layers::dropout::Dropout;
+    use crate::layers::testing::{check_gradients, rand_mat};
+    use crate::layers::Layer;
+    use crate::nn::loss::Loss;
+    use crate::nn::NN;
+    use crate::tools::matrix::*;
+
+    // share of zeroed elements and mean of the output for an input of ones
+    fn dropped_and_mean(out: &Mat) -> (f64, f64) {
+        let n = out.as_slice().len() as f64;
+        let dropped = out.as_slice().iter().filter(|v| **v == 0.0).count() as f64;
+        (dropped / n, out.sum_all() / n)
+    }
+
+    #[test]
+    fn gradients_match_numeric() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        // gradcheck reseeds the layer, so every pass drops the same units
+        check_gradients(&mut Dropout::new(0.5), &rand_mat(4, 3, &mut rng));
+        check_gradients(&mut Dropout::standard(0.3), &rand_mat(4, 3, &mut rng));
+    }
+
+    #[test]
+    fn eval_mode_is_deterministic() {
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+        let x = rand_mat(5, 4, &mut rng);
+        let mut inverted = Dropout::new(0.5);
+        let mut standard = Dropout::standard(0.5);
+        for _ in 0..3 {
+            assert_eq!(inverted.forward(&x, false).as_slice(), x.as_slice());
+            assert_eq!(
+                standard.forward(&x, false).as_slice(),
+                x.scaler_mul(0.5).as_slice()
+            );
+        }
+    }
+
+    #[test]
+    fn drop_rate_and_expected_value() {
+        let ones = Mat::val_mat(100, 100, 1.0);
+        let mut inverted = Dropout::new(0.3);
+        inverted.seed(3);
+        let out = inverted.forward(&ones, true);
+        let (dropped, mean) = dropped_and_mean(&out);
+        assert!((dropped - 0.3).abs() < 0.02, "dropped {dropped}");
+        // kept units are scaled up so the mean stays at 1, like at inference
+        assert!(out.as_slice().iter().all(|v| *v == 0.0 || *v == 1.0 / 0.7));
+        assert!((mean - 1.0).abs() < 0.03, "mean {mean}");
+        assert_eq!(
+            dropped_and_mean(&inverted.forward(&ones, false)),
+            (0.0, 1.0)
+        );
+
+        let mut standard = Dropout::standard(0.3);
+        standard.seed(4);
+        let out = standard.forward(&ones, true);
+        let (dropped, mean) = dropped_and_mean(&out);
+        assert!((dropped - 0.3).abs() < 0.02, "dropped {dropped}");
+        // kept units stay as they are, inference scales by 1 - p instead
+        assert!(out.as_slice().iter().all(|v| *v == 0.0 || *v == 1.0));
+        assert!((mean - 0.7).abs() < 0.03, "mean {mean}");
+        let (dropped, mean) = dropped_and_mean(&standard.forward(&ones, false));
+        assert_eq!(dropped, 0.0);
+        assert!((mean - 0.7).abs() < 1e-12);
+    }
+
+    #[test]
+    fn model_modes_switch_dropout() {
+        let mut nn = NN::from_layers(
+            50,
+            vec![Box::new(Dropout::new(0.5))],
+            Loss::MeanSquaredError,
+        );
+        let ones = Mat::val_mat(50, 4, 1.0);
+        nn.train_mode();
+        let (dropped, _) = dropped_and_mean(&nn.forward(&ones));
+        assert!(dropped > 0.2 && dropped < 0.8);
+        nn.eval_mode();
+        assert!(!nn.is_training());
+        assert_eq!(nn.forward(&ones).as_slice(), ones.as_slice());
+        nn.train_mode();
+        let (dropped, _) = dropped_and_mean(&nn.forward(&ones));
+        assert!(dropped > 0.2);
+    }
+}
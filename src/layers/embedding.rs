@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use crate::layers::Layer;
+use crate::tools::matrix::*;
+
+// Looks up a learned vector for integer ids. a sample is a column of `steps` ids stored
+// as f64, the output stacks their vectors like layers::recurrent expects sequences.
+// only the rows of ids in the batch get a gradient, see Layer::sparse_rows.
+// the padding id maps to a zero vector that is never trained, so with mask_value(0.0)
+// recurrent layers skip padded steps
+pub struct Embedding {
+    // (vocab, dim), row i is the vector of id i
+    table: Mat,
+    grad: Mat,
+    steps: usize,
+    padding: Option<usize>,
+    frozen: bool,
+    ids: Option<Mat>,
+    // rows with a non zero gradient
+    touched: Vec<usize>,
+}
+
+impl Embedding {
+    // vectors start normal with std 1 like torch, drawn with Box-Muller
+    pub fn new(vocab: usize, dim: usize, steps: usize) -> Embedding {
+        let mut rng = rand::thread_rng();
+        let values = (0..vocab * dim)
+            .map(|_| crate::data::augment::gaussian(&mut rng))
+            .collect();
+        Embedding::from_table(Mat::from_vec(values, vocab, dim), steps)
+    }
+    pub fn from_table(table: Mat, steps: usize) -> Embedding {
+        assert!(steps > 0);
+        Embedding {
+            grad: Mat::zeroes_like(&table),
+            table,
+            steps,
+            padding: None,
+            frozen: false,
+            ids: None,
+            touched: vec![],
+        }
+    }
+    // Vectors from a word2vec (text) or GloVe file: one `word v1 v2 ...` per line,
+    // a word2vec `count dim` header line is skipped. returns the words in file order,
+    // word i is id i
+    pub fn from_pretrained(path: &str, steps: usize) -> (Embedding, Vec<String>) {
+        let (words, vectors) = read_vectors(path);
+        assert!(!vectors.is_empty(), "no vectors in {path}");
+        let dim = vectors[0].len();
+        let table = Mat::from_vec(vectors.concat(), words.len(), dim);
+        (Embedding::from_table(table, steps), words)
+    }
+    // copy the vectors of a word2vec / GloVe file into the rows of the words it knows,
+    // words[i] being the word of id i. returns how many rows were replaced
+    pub fn load_pretrained(&mut self, path: &str, words: &[String]) -> usize {
+        assert_eq!(words.len(), self.table.row());
+        let ids: HashMap<&str, usize> = words
+            .iter()
+            .enumerate()
+            .map(|(i, w)| (w.as_str(), i))
+            .collect();
+        let mut found = 0;
+        for (word, vector) in read_vectors_iter(path) {
+            let Some(&id) = ids.get(word.as_str()) else {
+                continue;
+            };
+            assert_eq!(
+                vector.len(),
+                self.table.col(),
+                "vector size doesn't match the table"
+            );
+            for (j, v) in vector.into_iter().enumerate() {
+                self.table[(id, j)] = v;
+            }
+            found += 1;
+        }
+        self.zero_padding();
+        found
+    }
+    // the vector of this id is zeroed and stays zero
+    pub fn padding(mut self, id: usize) -> Embedding {
+        assert!(id < self.table.row());
+        self.padding = Some(id);
+        self.zero_padding();
+        self
+    }
+    // a frozen table gets no gradient, e.g. to keep pretrained vectors as they are
+    pub fn freeze(mut self, frozen: bool) -> Embedding {
+        self.frozen = frozen;
+        self
+    }
+    pub fn table(&self) -> &Mat {
+        &self.table
+    }
+    pub fn dim(&self) -> usize {
+        self.table.col()
+    }
+    fn zero_padding(&mut self) {
+        if let Some(p) = self.padding {
+            for j in 0..self.table.col() {
+                self.table[(p, j)] = 0.0;
+            }
+        }
+    }
+    fn id(&self, value: f64) -> usize {
+        let id = value as usize;
+        assert!(
+            value >= 0.0 && value.fract() == 0.0 && id < self.table.row(),
+            "{value} is not an id of this embedding"
+        );
+        id
+    }
+}
+
+fn parse_line(line: &str) -> Option<(String, Vec<f64>)> {
+    let mut parts = line.split_whitespace();
+    let word = parts.next()?.to_string();
+    let vector: Vec<f64> = parts
+        .map(|v| v.parse().expect("couldn't parse vector ;("))
+        .collect();
+    Some((word, vector))
+}
+
+fn read_vectors_iter(path: &str) -> impl Iterator<Item = (String, Vec<f64>)> {
+    let file = File::open(path).expect("couldn't open vectors file ;(");
+    let mut lines = BufReader::new(file)
+        .lines()
+        .map(|line| line.expect("couldn't read vectors file ;("))
+        .peekable();
+    // word2vec header: two integers
+    if let Some(first) = lines.peek() {
+        let header: Vec<&str> = first.split_whitespace().collect();
+        if header.len() == 2 && header.iter().all(|v| v.parse::<usize>().is_ok()) {
+            lines.next();
+        }
+    }
+    lines.filter_map(|line| parse_line(&line))
+}
+
+fn read_vectors(path: &str) -> (Vec<String>, Vec<Vec<f64>>) {
+    let mut words = vec![];
+    let mut vectors: Vec<Vec<f64>> = vec![];
+    for (word, vector) in read_vectors_iter(path) {
+        if let Some(first) = vectors.first() {
+            assert_eq!(
+                first.len(),
+                vector.len(),
+                "vectors of different sizes in {path}"
+            );
+        }
+        words.push(word);
+        vectors.push(vector);
+    }
+    (words, vectors)
+}
+
+impl Layer for Embedding {
+    fn forward(&mut self, x: &Mat, _training: bool) -> Mat {
+        assert_eq!(x.row(), self.steps);
+        let dim = self.dim();
+        let mut out = Mat::new(self.steps * dim, x.col());
+        for s in 0..x.col() {
+            for t in 0..self.steps {
+                let id = self.id(x[(t, s)]);
+                for j in 0..dim {
+                    out[(t * dim + j, s)] = self.table[(id, j)];
+                }
+            }
+        }
+        self.ids = Some(x.clone());
+        out
+    }
+    // ids aren't differentiable, the returned input gradient is all zeros
+    fn backward(&mut self, grad: &Mat) -> Mat {
+        let ids = self.ids.as_ref().expect("backward called before forward");
+        let dim = self.table.col();
+        // only the rows touched last time need clearing
+        for &r in &self.touched {
+            for j in 0..dim {
+                self.grad[(r, j)] = 0.0;
+            }
+        }
+        self.touched.clear();
+        if !self.frozen {
+            for s in 0..ids.col() {
+                for t in 0..self.steps {
+                    let id = ids[(t, s)] as usize;
+                    if Some(id) == self.padding {
+                        continue;
+                    }
+                    for j in 0..dim {
+                        self.grad[(id, j)] += grad[(t * dim + j, s)];
+                    }
+                    self.touched.push(id);
+                }
+            }
+            self.touched.sort();
+            self.touched.dedup();
+        }
+        Mat::zeroes_like(ids)
+    }
+    fn params(&self) -> Vec<&Mat> {
+        vec![&self.table]
+    }
+    fn params_and_grads(&mut self) -> Vec<(&mut Mat, &Mat)> {
+        vec![(&mut self.table, &self.grad)]
+    }
+    fn sparse_rows(&self) -> Vec<Option<Vec<usize>>> {
+        vec![Some(self.touched.clone())]
+    }
+    fn output_shape(&self, input_shape: usize) -> usize {
+        assert_eq!(input_shape, self.steps);
+        self.steps * self.dim()
+    }
+    fn name(&self) -> String {
+        format!(
+            "Embedding({}, {}, {} steps)",
+            self.table.row(),
+            self.dim(),
+            self.steps
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::layers::dense::Dense;
+    use crate::layers::embedding::Embedding;
+    use crate::layers::testing::rand_mat;
+    use crate::layers::Layer;
+    use crate::nn::gradcheck::GradCheck;
+    use crate::nn::loss::Loss;
+    use crate::nn::regularization::Regularization;
+    use crate::nn::NN;
+    use crate::tools::matrix::*;
+
+    fn vectors_file(name: &str, text: &str) -> String {
+        let path = std::env::temp_dir().join(format!("kek_{name}_{}.txt", std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    // embedding of 5 ids with 2 steps into a dense layer, one sample per column of ids
+    fn model(embedding: Embedding) -> NN {
+        NN::from_layers(
+            2,
+            vec![Box::new(embedding), Box::new(Dense::new(2 * 3, 2))],
+            Loss::MeanSquaredError,
+        )
+    }
+
+    fn table(nn: &NN) -> Mat {
+        nn.layers()[0].params()[0].clone()
+    }
+
+    #[test]
+    fn gradients_match_numeric() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let mut layer = Embedding::from_table(rand_mat(5, 3, &mut rng), 2);
+        let ids = Mat::from_vec(vec![1.0, 4.0, 0.0, 1.0, 3.0, 2.0], 2, 3);
+        // ids aren't differentiable
+        GradCheck::new()
+            .input(false)
+            .layer(&mut layer, &ids)
+            .assert_below(1e-6);
+    }
+
+    #[test]
+    fn pretrained_word2vec_and_glove_files() {
+        let word2vec = vectors_file("word2vec", "3 2\nthe 0.5 -1\ncat 1 2\ndog 3 4\n");
+        let glove = vectors_file("glove", "the 0.5 -1\ncat 1 2\ndog 3 4\n");
+        for path in [&word2vec, &glove] {
+            let (embedding, words) = Embedding::from_pretrained(path, 4);
+            assert_eq!(words, ["the", "cat", "dog"]);
+            assert_eq!(embedding.table().shape(), (3, 2));
+            assert_eq!(
+                embedding.table().as_slice(),
+                &[0.5, -1.0, 1.0, 2.0, 3.0, 4.0]
+            );
+        }
+
+        // known words are replaced, the padding row stays zero and unknown words are kept
+        let words: Vec<String> = ["<pad>", "dog", "bird", "the"]
+            .iter()
+            .map(|w| w.to_string())
+            .collect();
+        let mut embedding = Embedding::from_table(Mat::val_mat(4, 2, 7.0), 1).padding(0);
+        let with_pad = vectors_file("pad", "<pad> 9 9\nthe 0.5 -1\ndog 3 4\n");
+        assert_eq!(embedding.load_pretrained(&with_pad, &words), 3);
+        assert_eq!(
+            embedding.table().as_slice(),
+            &[0.0, 0.0, 3.0, 4.0, 7.0, 7.0, 0.5, -1.0]
+        );
+        for path in [word2vec, glove, with_pad] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn sparse_rows_are_the_ids_of_the_last_batch() {
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+        let mut layer = Embedding::from_table(rand_mat(5, 3, &mut rng), 2).padding(0);
+        let out = layer.forward(&Mat::from_vec(vec![3.0, 1.0, 0.0, 3.0], 2, 2), true);
+        layer.backward(&Mat::val_mat(out.row(), out.col(), 1.0));
+        // the padding id gets no gradient
+        assert_eq!(layer.sparse_rows(), vec![Some(vec![1, 3])]);
+
+        let out = layer.forward(&Mat::from_vec(vec![2.0, 2.0], 2, 1), true);
+        layer.backward(&Mat::val_mat(out.row(), out.col(), 1.0));
+        assert_eq!(layer.sparse_rows(), vec![Some(vec![2])]);
+        // earlier rows are cleared
+        let grad = layer.params_and_grads()[0].1.clone();
+        for r in [0, 1, 3, 4] {
+            assert_eq!(grad.rows(r, r + 1).as_slice(), &[0.0; 3]);
+        }
+        assert_eq!(grad.rows(2, 3).as_slice(), &[2.0; 3]);
+    }
+
+    #[test]
+    fn updates_only_touch_the_rows_in_the_batch() {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let mut nn = model(Embedding::from_table(rand_mat(5, 3, &mut rng), 2).padding(0));
+        // weight decay would move every row of a dense update
+        nn.set_regularization(Some(Regularization::l2(0.1)));
+        let before = table(&nn);
+        let x = vec![
+            Mat::from_vec(vec![0.0, 2.0], 2, 1),
+            Mat::from_vec(vec![4.0, 0.0], 2, 1),
+        ];
+        let y = vec![Mat::val_mat(2, 1, 1.0); 2];
+        nn.train_batch(&x, &y);
+        let after = table(&nn);
+        for r in 0..5 {
+            let changed = before.rows(r, r + 1).as_slice() != after.rows(r, r + 1).as_slice();
+            assert_eq!(changed, r == 2 || r == 4, "row {r}");
+        }
+        // the padding row is still zero
+        assert_eq!(after.rows(0, 1).as_slice(), &[0.0; 3]);
+    }
+
+    #[test]
+    fn frozen_tables_dont_change() {
+        let mut rng = ChaCha8Rng::seed_from_u64(4);
+        let embedding = Embedding::from_table(rand_mat(5, 3, &mut rng), 2).freeze(true);
+        let mut nn = model(embedding);
+        let before = table(&nn);
+        let x = vec![Mat::from_vec(vec![1.0, 2.0], 2, 1)];
+        nn.train_batch(&x, &[Mat::val_mat(2, 1, 1.0)]);
+        assert_eq!(table(&nn).as_slice(), before.as_slice());
+        assert_eq!(nn.layers()[0].sparse_rows(), vec![Some(vec![])]);
+    }
+}
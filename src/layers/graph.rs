@@ -0,0 +1,423 @@
+use crate::layers::{Layer, ParamGroup};
+use crate::tools::matrix::*;
+
+// index of a node in its Graph
+pub type NodeId = usize;
+
+enum Op {
+    Input,
+    Layer(Box<dyn Layer>),
+    // element wise sum of the inputs
+    Add,
+    // inputs stacked on top of each other
+    Concat,
+    // rows start..end of the input, Graph::split makes one per part
+    Split { start: usize, end: usize },
+}
+
+struct Node {
+    op: Op,
+    inputs: Vec<NodeId>,
+    size: usize,
+}
+
+// Layers wired up as a directed acyclic graph, every edge carries a Mat with one sample
+// per column. nodes can only use nodes added before them, so the insertion order is a
+// topological order: forward runs in it and backward in reverse, summing the gradients
+// of nodes that feed several others.
+//
+// as a Layer (e.g. inside NN) a graph needs exactly one input and one output, multi input
+// models split one column with `split` and multi output ones `concat` their heads.
+// forward_all / backward_all work on any number of inputs and outputs
+pub struct Graph {
+    nodes: Vec<Node>,
+    inputs: Vec<NodeId>,
+    outputs: Vec<NodeId>,
+    // output of every node in the last forward call
+    values: Vec<Mat>,
+}
+
+impl Graph {
+    pub fn new() -> Graph {
+        Graph {
+            nodes: vec![],
+            inputs: vec![],
+            outputs: vec![],
+            values: vec![],
+        }
+    }
+    fn push(&mut self, op: Op, inputs: &[NodeId], size: usize) -> NodeId {
+        for &input in inputs {
+            assert!(input < self.nodes.len(), "node {input} doesn't exist yet");
+        }
+        self.nodes.push(Node {
+            op,
+            inputs: inputs.to_vec(),
+            size,
+        });
+        self.nodes.len() - 1
+    }
+    // a new graph input with `size` rows, inputs are fed in the order they were added
+    pub fn input(&mut self, size: usize) -> NodeId {
+        let id = self.push(Op::Input, &[], size);
+        self.inputs.push(id);
+        id
+    }
+    pub fn layer<L: Layer + 'static>(&mut self, layer: L, input: NodeId) -> NodeId {
+        self.boxed(Box::new(layer), input)
+    }
+    pub fn boxed(&mut self, layer: Box<dyn Layer>, input: NodeId) -> NodeId {
+        let size = layer.output_shape(self.size(input));
+        self.push(Op::Layer(layer), &[input], size)
+    }
+    // element wise sum, e.g. for residual connections
+    pub fn add(&mut self, inputs: &[NodeId]) -> NodeId {
+        assert!(!inputs.is_empty());
+        let size = self.size(inputs[0]);
+        for &input in inputs {
+            assert_eq!(self.size(input), size, "add needs inputs of the same size");
+        }
+        self.push(Op::Add, inputs, size)
+    }
+    pub fn concat(&mut self, inputs: &[NodeId]) -> NodeId {
+        assert!(!inputs.is_empty());
+        let size = inputs.iter().map(|&input| self.size(input)).sum();
+        self.push(Op::Concat, inputs, size)
+    }
+    // consecutive row ranges of `sizes` rows each, they have to cover the whole input
+    pub fn split(&mut self, input: NodeId, sizes: &[usize]) -> Vec<NodeId> {
+        assert_eq!(
+            sizes.iter().sum::<usize>(),
+            self.size(input),
+            "split sizes don't add up"
+        );
+        let mut start = 0;
+        let mut parts = vec![];
+        for &size in sizes {
+            let end = start + size;
+            parts.push(self.push(Op::Split { start, end }, &[input], size));
+            start = end;
+        }
+        parts
+    }
+    // marks a node as a graph output, outputs come back in the order they were marked
+    pub fn output(&mut self, node: NodeId) {
+        assert!(node < self.nodes.len());
+        self.outputs.push(node);
+    }
+    pub fn size(&self, node: NodeId) -> usize {
+        self.nodes[node].size
+    }
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+    fn layers(&self) -> impl Iterator<Item = &Box<dyn Layer>> {
+        self.nodes.iter().filter_map(|node| match &node.op {
+            Op::Layer(layer) => Some(layer),
+            _ => None,
+        })
+    }
+    fn layers_mut(&mut self) -> impl Iterator<Item = &mut Box<dyn Layer>> {
+        self.nodes.iter_mut().filter_map(|node| match &mut node.op {
+            Op::Layer(layer) => Some(layer),
+            _ => None,
+        })
+    }
+    pub fn forward_all(&mut self, inputs: &[Mat], training: bool) -> Vec<Mat> {
+        assert_eq!(
+            inputs.len(),
+            self.inputs.len(),
+            "wrong number of graph inputs"
+        );
+        let mut values: Vec<Mat> = Vec::with_capacity(self.nodes.len());
+        let mut fed = inputs.iter();
+        for node in self.nodes.iter_mut() {
+            let input = |i: usize| &values[node.inputs[i]];
+            let value = match &mut node.op {
+                Op::Input => fed.next().unwrap().clone(),
+                Op::Layer(layer) => layer.forward(input(0), training),
+                Op::Add => {
+                    let mut sum = input(0).clone();
+                    for i in 1..node.inputs.len() {
+                        sum = &sum + input(i);
+                    }
+                    sum
+                }
+                Op::Concat => {
+                    let parts: Vec<Mat> =
+                        (0..node.inputs.len()).map(|i| input(i).clone()).collect();
+                    Mat::vstack(&parts)
+                }
+                Op::Split { start, end } => input(0).rows(*start, *end),
+            };
+            assert_eq!(value.row(), node.size);
+            values.push(value);
+        }
+        let outputs = self.outputs.iter().map(|&o| values[o].clone()).collect();
+        self.values = values;
+        outputs
+    }
+    // grads line up with the outputs, returns the gradient of every input
+    pub fn backward_all(&mut self, grads: &[Mat]) -> Vec<Mat> {
+        assert_eq!(
+            grads.len(),
+            self.outputs.len(),
+            "wrong number of output gradients"
+        );
+        assert_eq!(
+            self.values.len(),
+            self.nodes.len(),
+            "backward called before forward"
+        );
+        let mut acc: Vec<Option<Mat>> = vec![None; self.nodes.len()];
+        let add = |acc: &mut Vec<Option<Mat>>, node: NodeId, grad: Mat| {
+            acc[node] = Some(match acc[node].take() {
+                Some(sum) => &sum + &grad,
+                None => grad,
+            });
+        };
+        for (&o, grad) in self.outputs.iter().zip(grads) {
+            add(&mut acc, o, grad.clone());
+        }
+        for id in (0..self.nodes.len()).rev() {
+            let node = &mut self.nodes[id];
+            if matches!(node.op, Op::Input) {
+                continue;
+            }
+            // nodes that don't lead to an output get no gradient
+            let Some(grad) = acc[id].take() else {
+                continue;
+            };
+            match &mut node.op {
+                Op::Input => unreachable!(),
+                Op::Layer(layer) => add(&mut acc, node.inputs[0], layer.backward(&grad)),
+                Op::Add => {
+                    for &input in &node.inputs {
+                        add(&mut acc, input, grad.clone());
+                    }
+                }
+                Op::Concat => {
+                    let mut start = 0;
+                    for &input in &node.inputs {
+                        let size = self.values[input].row();
+                        add(&mut acc, input, grad.rows(start, start + size));
+                        start += size;
+                    }
+                }
+                Op::Split { start, end } => {
+                    let input = node.inputs[0];
+                    let mut full = Mat::zeroes_like(&self.values[input]);
+                    for i in *start..*end {
+                        for j in 0..grad.col() {
+                            full[(i, j)] = grad[(i - *start, j)];
+                        }
+                    }
+                    add(&mut acc, input, full);
+                }
+            }
+        }
+        self.inputs
+            .iter()
+            .map(|&i| {
+                acc[i]
+                    .take()
+                    .unwrap_or_else(|| Mat::zeroes_like(&self.values[i]))
+            })
+            .collect()
+    }
+}
+
+impl Default for Graph {
+    fn default() -> Self {
+        Graph::new()
+    }
+}
+
+impl Layer for Graph {
+    fn forward(&mut self, x: &Mat, training: bool) -> Mat {
+        assert!(
+            self.inputs.len() == 1 && self.outputs.len() == 1,
+            "as a layer a graph needs one input and one output"
+        );
+        self.forward_all(std::slice::from_ref(x), training)
+            .swap_remove(0)
+    }
+    fn backward(&mut self, grad: &Mat) -> Mat {
+        self.backward_all(std::slice::from_ref(grad)).swap_remove(0)
+    }
+    fn params(&self) -> Vec<&Mat> {
+        self.layers().flat_map(|layer| layer.params()).collect()
+    }
+    fn params_and_grads(&mut self) -> Vec<(&mut Mat, &Mat)> {
+        self.layers_mut()
+            .flat_map(|layer| layer.params_and_grads())
+            .collect()
+    }
+    fn param_groups(&self) -> Vec<ParamGroup> {
+        self.layers()
+            .flat_map(|layer| layer.param_groups())
+            .collect()
+    }
+    fn sparse_rows(&self) -> Vec<Option<Vec<usize>>> {
+        self.layers()
+            .flat_map(|layer| layer.sparse_rows())
+            .collect()
+    }
+    fn buffers(&self) -> Vec<&Mat> {
+        self.layers().flat_map(|layer| layer.buffers()).collect()
+    }
+    fn buffers_mut(&mut self) -> Vec<&mut Mat> {
+        self.layers_mut()
+            .flat_map(|layer| layer.buffers_mut())
+            .collect()
+    }
+    fn output_shape(&self, input_shape: usize) -> usize {
+        assert_eq!(self.inputs.len(), 1);
+        assert_eq!(self.outputs.len(), 1);
+        assert_eq!(input_shape, self.size(self.inputs[0]));
+        self.size(self.outputs[0])
+    }
+    fn seed(&mut self, seed: u64) {
+        for (i, layer) in self.layers_mut().enumerate() {
+            layer.seed(seed.wrapping_add(i as u64));
+        }
+    }
+    fn state(&self) -> Option<serde_json::Value> {
+        let states: Vec<Option<serde_json::Value>> =
+            self.layers().map(|layer| layer.state()).collect();
+        Some(serde_json::to_value(states).expect("couldn't save graph state"))
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        let states: Vec<Option<serde_json::Value>> =
+            serde_json::from_value(state).expect("bad graph state");
+        for (layer, state) in self.layers_mut().zip(states) {
+            if let Some(state) = state {
+                layer.load_state(state);
+            }
+        }
+    }
+    fn name(&self) -> String {
+        let layers: Vec<String> = self.layers().map(|layer| layer.name()).collect();
+        format!("Graph({} nodes: {})", self.nodes.len(), layers.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::layers::activation::Activation;
+    use crate::layers::dense::Dense;
+    use crate::layers::graph::Graph;
+    use crate::layers::testing::{check_gradients, rand_mat};
+    use crate::layers::Layer;
+    use crate::models;
+    use crate::tools::matrix::*;
+
+    fn col(values: &[f64]) -> Mat {
+        Mat::from_vec(values.to_vec(), values.len(), 1)
+    }
+
+    #[test]
+    fn gradients_match_numeric() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let mut graph = Graph::new();
+        let x = graph.input(5);
+        let parts = graph.split(x, &[2, 3]);
+        let a = graph.layer(Dense::new(2, 4), parts[0]);
+        let b = graph.layer(Dense::new(3, 4), parts[1]);
+        let b = graph.layer(Activation::tanh(), b);
+        // a feeds the sum twice and x is used three times, their gradients add up
+        let sum = graph.add(&[a, b, a]);
+        let joined = graph.concat(&[sum, parts[1], x]);
+        let out = graph.layer(Dense::new(12, 3), joined);
+        graph.output(out);
+        check_gradients(&mut graph, &rand_mat(5, 4, &mut rng));
+    }
+
+    #[test]
+    fn split_add_and_concat_route_values_and_gradients() {
+        let mut graph = Graph::new();
+        let x = graph.input(4);
+        let parts = graph.split(x, &[2, 2]);
+        let sum = graph.add(&[parts[0], parts[1]]);
+        let joined = graph.concat(&[parts[1], sum]);
+        graph.output(joined);
+        let y = graph.forward(&col(&[1.0, 2.0, 3.0, 4.0]), false);
+        assert_eq!(y.as_slice(), &[3.0, 4.0, 4.0, 6.0]);
+        // the second part gets its own rows of the concat plus the sum's gradient,
+        // the first part only the sum's
+        let dx = graph.backward(&col(&[1.0, 2.0, 3.0, 4.0]));
+        assert_eq!(dx.as_slice(), &[3.0, 4.0, 4.0, 6.0]);
+    }
+
+    #[test]
+    fn several_inputs_and_outputs() {
+        let mut graph = Graph::new();
+        let a = graph.input(2);
+        let b = graph.input(2);
+        let unused = graph.input(1);
+        let dense = Dense::from_weights(col(&[1.0, -1.0]).transpose(), col(&[0.5]));
+        let scored = graph.layer(dense, a);
+        let sum = graph.add(&[a, b]);
+        graph.output(sum);
+        graph.output(scored);
+        let inputs = [col(&[1.0, 2.0]), col(&[10.0, 20.0]), col(&[7.0])];
+        let outputs = graph.forward_all(&inputs, false);
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(outputs[0].as_slice(), &[11.0, 22.0]);
+        assert_eq!(outputs[1].as_slice(), &[-0.5]);
+        assert!(graph.size(unused) == 1 && graph.len() == 5);
+
+        let grads = graph.backward_all(&[col(&[1.0, 2.0]), col(&[3.0])]);
+        assert_eq!(grads.len(), 3);
+        // a gets the sum's gradient and the dense layer's w^T * 3
+        assert_eq!(grads[0].as_slice(), &[4.0, -1.0]);
+        assert_eq!(grads[1].as_slice(), &[1.0, 2.0]);
+        // inputs not leading to an output get zeros
+        assert_eq!(grads[2].as_slice(), &[0.0]);
+    }
+
+    #[test]
+    fn model_shapes() {
+        let mut resnet = models::resnet_mlp(6, 8, 3, 4);
+        assert_eq!(resnet.layers()[0].output_shape(6), 4);
+        // the input projection, two dense layers per block and the output layer
+        let shapes: Vec<_> = resnet.layers()[0]
+            .params()
+            .iter()
+            .map(|p| p.shape())
+            .collect();
+        assert_eq!(shapes.len(), 2 * (1 + 2 * 3 + 1));
+        assert_eq!(
+            shapes[..6],
+            [(8, 6), (8, 1), (16, 8), (16, 1), (8, 16), (8, 1)]
+        );
+        assert_eq!(resnet.forward(&Mat::new(6, 5)).shape(), (4, 5));
+
+        let mut two_tower = models::two_tower(3, 5, 6, 4, 2);
+        assert_eq!(two_tower.layers()[0].output_shape(8), 2);
+        let params = two_tower.layers()[0].params();
+        let shapes: Vec<_> = params.iter().map(|p| p.shape()).collect();
+        assert_eq!(
+            shapes,
+            [
+                (6, 3),
+                (6, 1),
+                (4, 6),
+                (4, 1),
+                (6, 5),
+                (6, 1),
+                (4, 6),
+                (4, 1),
+                (2, 8),
+                (2, 1)
+            ]
+        );
+        assert_eq!(two_tower.forward(&Mat::new(8, 3)).shape(), (2, 3));
+    }
+}
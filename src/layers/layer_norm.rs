@@ -0,0 +1,125 @@
+use crate::layers::{Layer, ParamGroup};
+use crate::tools::matrix::*;
+
+// Normalizes every sample over its features, then scales and shifts with learnable gamma and beta.
+// unlike BatchNorm1d it doesn't depend on the batch, so it acts the same in training and eval mode
+pub struct LayerNorm {
+    gamma: Mat,
+    beta: Mat,
+    dgamma: Mat,
+    dbeta: Mat,
+    eps: f64,
+    // normalized input and 1 / std per sample of the last forward call
+    x_hat: Option<Mat>,
+    inv_std: Vec<f64>,
+}
+
+impl LayerNorm {
+    pub fn new(features: usize) -> LayerNorm {
+        LayerNorm {
+            gamma: Mat::val_mat(features, 1, 1.0),
+            beta: Mat::new(features, 1),
+            dgamma: Mat::new(features, 1),
+            dbeta: Mat::new(features, 1),
+            eps: 1e-5,
+            x_hat: None,
+            inv_std: vec![],
+        }
+    }
+    pub fn eps(mut self, eps: f64) -> LayerNorm {
+        self.eps = eps;
+        self
+    }
+}
+
+impl Layer for LayerNorm {
+    fn forward(&mut self, x: &Mat, _training: bool) -> Mat {
+        assert_eq!(x.row(), self.gamma.row());
+        let d = x.row() as f64;
+        let mut x_hat = x.clone();
+        let mut out = x.clone();
+        self.inv_std = vec![0.0; x.col()];
+        for j in 0..x.col() {
+            let mean = (0..x.row()).map(|i| x[(i, j)]).sum::<f64>() / d;
+            let var = (0..x.row())
+                .map(|i| (x[(i, j)] - mean).powi(2))
+                .sum::<f64>()
+                / d;
+            let inv_std = 1.0 / (var + self.eps).sqrt();
+            for i in 0..x.row() {
+                x_hat[(i, j)] = (x[(i, j)] - mean) * inv_std;
+                out[(i, j)] = self.gamma[(i, 0)] * x_hat[(i, j)] + self.beta[(i, 0)];
+            }
+            self.inv_std[j] = inv_std;
+        }
+        self.x_hat = Some(x_hat);
+        out
+    }
+    fn backward(&mut self, grad: &Mat) -> Mat {
+        let x_hat = self.x_hat.as_ref().expect("backward called before forward");
+        let d = grad.row() as f64;
+        self.dbeta = grad.sum_columns();
+        self.dgamma = grad.ele_mul(x_hat).sum_columns();
+        let mut dx = Mat::zeroes_like(grad);
+        for j in 0..grad.col() {
+            // gradient w.r.t. x_hat, then through the per sample mean and variance
+            let g: Vec<f64> = (0..grad.row())
+                .map(|i| grad[(i, j)] * self.gamma[(i, 0)])
+                .collect();
+            let sum: f64 = g.iter().sum();
+            let dot: f64 = g.iter().enumerate().map(|(i, gi)| gi * x_hat[(i, j)]).sum();
+            for (i, gi) in g.iter().enumerate() {
+                dx[(i, j)] = self.inv_std[j] * (gi - (sum + x_hat[(i, j)] * dot) / d);
+            }
+        }
+        dx
+    }
+    fn params(&self) -> Vec<&Mat> {
+        vec![&self.gamma, &self.beta]
+    }
+    fn params_and_grads(&mut self) -> Vec<(&mut Mat, &Mat)> {
+        vec![
+            (&mut self.gamma, &self.dgamma),
+            (&mut self.beta, &self.dbeta),
+        ]
+    }
+    fn param_groups(&self) -> Vec<ParamGroup> {
+        vec![ParamGroup::Norm, ParamGroup::Norm]
+    }
+    fn name(&self) -> String {
+        format!("LayerNorm({})", self.gamma.row())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::layers::layer_norm::LayerNorm;
+    use crate::layers::testing::{check_gradients, rand_mat};
+    use crate::layers::Layer;
+
+    #[test]
+    fn gradients_match_numeric() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let mut layer = LayerNorm::new(4);
+        layer.params_and_grads()[0]
+            .0
+            .clone_from(&rand_mat(4, 1, &mut rng));
+        layer.params_and_grads()[1]
+            .0
+            .clone_from(&rand_mat(4, 1, &mut rng));
+        check_gradients(&mut layer, &rand_mat(4, 3, &mut rng));
+    }
+
+    #[test]
+    fn samples_are_normalized_independently() {
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+        let mut layer = LayerNorm::new(5);
+        let x = rand_mat(5, 2, &mut rng);
+        let both = layer.forward(&x, true);
+        let first = layer.forward(&x.column(0), false);
+        assert_eq!(both.column(0).as_slice(), first.as_slice());
+    }
+}
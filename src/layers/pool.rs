@@ -0,0 +1,318 @@
+use crate::layers::conv::{window_count, ImageShape};
+use crate::layers::Layer;
+use crate::tools::matrix::*;
+
+// largest value of every kernel sized window, stride defaults to the kernel size
+pub struct MaxPool2d {
+    input: ImageShape,
+    kernel: usize,
+    stride: usize,
+    // input row each output element was taken from, per sample
+    argmax: Vec<Vec<usize>>,
+}
+
+impl MaxPool2d {
+    pub fn new(input: ImageShape, kernel: usize) -> MaxPool2d {
+        MaxPool2d {
+            input,
+            kernel,
+            stride: kernel,
+            argmax: vec![],
+        }
+    }
+    pub fn stride(mut self, stride: usize) -> MaxPool2d {
+        assert!(stride > 0);
+        self.stride = stride;
+        self
+    }
+    pub fn output(&self) -> ImageShape {
+        pooled(self.input, self.kernel, self.stride)
+    }
+}
+
+// mean of every kernel sized window, stride defaults to the kernel size
+pub struct AvgPool2d {
+    input: ImageShape,
+    kernel: usize,
+    stride: usize,
+}
+
+impl AvgPool2d {
+    pub fn new(input: ImageShape, kernel: usize) -> AvgPool2d {
+        AvgPool2d {
+            input,
+            kernel,
+            stride: kernel,
+        }
+    }
+    pub fn stride(mut self, stride: usize) -> AvgPool2d {
+        assert!(stride > 0);
+        self.stride = stride;
+        self
+    }
+    pub fn output(&self) -> ImageShape {
+        pooled(self.input, self.kernel, self.stride)
+    }
+}
+
+// mean of every channel, (c, h, w) -> c
+pub struct GlobalAvgPool {
+    input: ImageShape,
+}
+
+impl GlobalAvgPool {
+    pub fn new(input: ImageShape) -> GlobalAvgPool {
+        GlobalAvgPool { input }
+    }
+}
+
+// Samples are already flat columns, so this only marks where the image layers end.
+// kept so models read like their usual definitions
+pub struct Flatten;
+
+impl Flatten {
+    pub fn new() -> Flatten {
+        Flatten
+    }
+}
+
+impl Default for Flatten {
+    fn default() -> Self {
+        Flatten::new()
+    }
+}
+
+fn pooled(input: ImageShape, kernel: usize, stride: usize) -> ImageShape {
+    let (c, h, w) = input;
+    (
+        c,
+        window_count(h, kernel, stride, 0, 1),
+        window_count(w, kernel, stride, 0, 1),
+    )
+}
+
+// calls f(output row, input rows of the window) for every window of every channel
+fn windows(input: ImageShape, kernel: usize, stride: usize, mut f: impl FnMut(usize, &[usize])) {
+    let (c, h, w) = input;
+    let (_, oh, ow) = pooled(input, kernel, stride);
+    let mut rows = Vec::with_capacity(kernel * kernel);
+    for ch in 0..c {
+        for oy in 0..oh {
+            for ox in 0..ow {
+                rows.clear();
+                for ky in 0..kernel {
+                    for kx in 0..kernel {
+                        rows.push((ch * h + oy * stride + ky) * w + ox * stride + kx);
+                    }
+                }
+                f((ch * oh + oy) * ow + ox, &rows);
+            }
+        }
+    }
+}
+
+fn size(shape: ImageShape) -> usize {
+    shape.0 * shape.1 * shape.2
+}
+
+impl Layer for MaxPool2d {
+    fn forward(&mut self, x: &Mat, _training: bool) -> Mat {
+        assert_eq!(x.row(), size(self.input));
+        let mut out = Mat::new(size(self.output()), x.col());
+        self.argmax = vec![vec![0; out.row()]; x.col()];
+        for s in 0..x.col() {
+            let argmax = &mut self.argmax[s];
+            windows(self.input, self.kernel, self.stride, |o, rows| {
+                let mut best = rows[0];
+                for &r in rows {
+                    if x[(r, s)] > x[(best, s)] {
+                        best = r;
+                    }
+                }
+                argmax[o] = best;
+                out[(o, s)] = x[(best, s)];
+            });
+        }
+        out
+    }
+    fn backward(&mut self, grad: &Mat) -> Mat {
+        assert!(!self.argmax.is_empty(), "backward called before forward");
+        let mut dx = Mat::new(size(self.input), grad.col());
+        for s in 0..grad.col() {
+            for (o, &r) in self.argmax[s].iter().enumerate() {
+                dx[(r, s)] += grad[(o, s)];
+            }
+        }
+        dx
+    }
+    fn output_shape(&self, input_shape: usize) -> usize {
+        assert_eq!(input_shape, size(self.input));
+        size(self.output())
+    }
+    fn name(&self) -> String {
+        format!(
+            "MaxPool2d({:?} -> {:?}, kernel {}, stride {})",
+            self.input,
+            self.output(),
+            self.kernel,
+            self.stride
+        )
+    }
+}
+
+impl Layer for AvgPool2d {
+    fn forward(&mut self, x: &Mat, _training: bool) -> Mat {
+        assert_eq!(x.row(), size(self.input));
+        let mut out = Mat::new(size(self.output()), x.col());
+        let area = (self.kernel * self.kernel) as f64;
+        for s in 0..x.col() {
+            windows(self.input, self.kernel, self.stride, |o, rows| {
+                out[(o, s)] = rows.iter().map(|&r| x[(r, s)]).sum::<f64>() / area;
+            });
+        }
+        out
+    }
+    fn backward(&mut self, grad: &Mat) -> Mat {
+        let mut dx = Mat::new(size(self.input), grad.col());
+        let area = (self.kernel * self.kernel) as f64;
+        for s in 0..grad.col() {
+            windows(self.input, self.kernel, self.stride, |o, rows| {
+                for &r in rows {
+                    dx[(r, s)] += grad[(o, s)] / area;
+                }
+            });
+        }
+        dx
+    }
+    fn output_shape(&self, input_shape: usize) -> usize {
+        assert_eq!(input_shape, size(self.input));
+        size(self.output())
+    }
+    fn name(&self) -> String {
+        format!(
+            "AvgPool2d({:?} -> {:?}, kernel {}, stride {})",
+            self.input,
+            self.output(),
+            self.kernel,
+            self.stride
+        )
+    }
+}
+
+impl Layer for GlobalAvgPool {
+    fn forward(&mut self, x: &Mat, _training: bool) -> Mat {
+        let (c, h, w) = self.input;
+        assert_eq!(x.row(), c * h * w);
+        let mut out = Mat::new(c, x.col());
+        for ch in 0..c {
+            for s in 0..x.col() {
+                out[(ch, s)] =
+                    (0..h * w).map(|p| x[(ch * h * w + p, s)]).sum::<f64>() / (h * w) as f64;
+            }
+        }
+        out
+    }
+    fn backward(&mut self, grad: &Mat) -> Mat {
+        let (c, h, w) = self.input;
+        let mut dx = Mat::new(c * h * w, grad.col());
+        for ch in 0..c {
+            for s in 0..grad.col() {
+                for p in 0..h * w {
+                    dx[(ch * h * w + p, s)] = grad[(ch, s)] / (h * w) as f64;
+                }
+            }
+        }
+        dx
+    }
+    fn output_shape(&self, input_shape: usize) -> usize {
+        assert_eq!(input_shape, size(self.input));
+        self.input.0
+    }
+    fn name(&self) -> String {
+        format!("GlobalAvgPool({:?})", self.input)
+    }
+}
+
+impl Layer for Flatten {
+    fn forward(&mut self, x: &Mat, _training: bool) -> Mat {
+        x.clone()
+    }
+    fn backward(&mut self, grad: &Mat) -> Mat {
+        grad.clone()
+    }
+    fn name(&self) -> String {
+        "Flatten".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::layers::pool::{AvgPool2d, Flatten, GlobalAvgPool, MaxPool2d};
+    use crate::layers::testing::{check_gradients, rand_mat};
+    use crate::layers::Layer;
+    use crate::tools::matrix::*;
+
+    // one 4x4 channel, row by row
+    fn image() -> Mat {
+        let values = [
+            1.0, 5.0, 2.0, 0.0, //
+            3.0, 4.0, 8.0, 1.0, //
+            0.0, 2.0, 6.0, 7.0, //
+            9.0, 1.0, 3.0, 5.0,
+        ];
+        Mat::from_vec(values.to_vec(), 16, 1)
+    }
+
+    #[test]
+    fn gradients_match_numeric() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let x = rand_mat(2 * 5 * 5, 2, &mut rng);
+        check_gradients(&mut MaxPool2d::new((2, 5, 5), 2), &x);
+        check_gradients(&mut MaxPool2d::new((2, 5, 5), 3).stride(1), &x);
+        check_gradients(&mut AvgPool2d::new((2, 5, 5), 2), &x);
+        check_gradients(&mut GlobalAvgPool::new((2, 5, 5)), &x);
+    }
+
+    #[test]
+    fn max_pool_takes_the_maximum_and_routes_the_gradient_to_it() {
+        let mut pool = MaxPool2d::new((1, 4, 4), 2);
+        assert_eq!(
+            pool.forward(&image(), true).as_slice(),
+            &[5.0, 8.0, 9.0, 7.0]
+        );
+        let dx = pool.backward(&Mat::from_vec(vec![1.0, 2.0, 3.0, 4.0], 4, 1));
+        let mut expected = [0.0; 16];
+        // positions of 5, 8, 9 and 7
+        for (row, grad) in [(1, 1.0), (6, 2.0), (12, 3.0), (11, 4.0)] {
+            expected[row] = grad;
+        }
+        assert_eq!(dx.as_slice(), &expected[..]);
+    }
+
+    #[test]
+    fn avg_pool_averages_each_window() {
+        let mut pool = AvgPool2d::new((1, 4, 4), 2);
+        assert_eq!(
+            pool.forward(&image(), true).as_slice(),
+            &[13.0 / 4.0, 11.0 / 4.0, 12.0 / 4.0, 21.0 / 4.0]
+        );
+        // overlapping windows with stride 1
+        let mut pool = AvgPool2d::new((1, 4, 4), 3).stride(1);
+        assert_eq!(
+            pool.forward(&image(), true).as_slice(),
+            &[31.0 / 9.0, 35.0 / 9.0, 36.0 / 9.0, 37.0 / 9.0]
+        );
+    }
+
+    #[test]
+    fn flatten_keeps_the_shape() {
+        let mut flatten = Flatten::new();
+        let x = Mat::val_mat(12, 3, 1.0);
+        assert_eq!(flatten.output_shape(12), 12);
+        assert_eq!(flatten.forward(&x, true).shape(), (12, 3));
+        assert_eq!(flatten.backward(&x).shape(), (12, 3));
+    }
+}
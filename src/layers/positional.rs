@@ -0,0 +1,112 @@
+use crate::layers::Layer;
+use crate::tools::matrix::*;
+
+// Vaswani et al.: sin / cos of the position at geometrically spaced frequencies,
+// added to every sample. sequences are laid out like layers::recurrent expects them
+pub struct SinusoidalEncoding {
+    encoding: Mat,
+}
+
+impl SinusoidalEncoding {
+    pub fn new(d_model: usize, steps: usize) -> SinusoidalEncoding {
+        let mut encoding = Mat::new(steps * d_model, 1);
+        for t in 0..steps {
+            for i in 0..d_model {
+                let rate = libm::pow(10000.0, (2 * (i / 2)) as f64 / d_model as f64);
+                let angle = t as f64 / rate;
+                encoding[(t * d_model + i, 0)] = if i % 2 == 0 {
+                    libm::sin(angle)
+                } else {
+                    libm::cos(angle)
+                };
+            }
+        }
+        SinusoidalEncoding { encoding }
+    }
+    pub fn encoding(&self) -> &Mat {
+        &self.encoding
+    }
+}
+
+impl Layer for SinusoidalEncoding {
+    fn forward(&mut self, x: &Mat, _training: bool) -> Mat {
+        x.add_column(&self.encoding)
+    }
+    fn backward(&mut self, grad: &Mat) -> Mat {
+        grad.clone()
+    }
+    fn name(&self) -> String {
+        format!("SinusoidalEncoding({})", self.encoding.row())
+    }
+}
+
+// a learned vector per position added to every sample, starts small and random
+pub struct LearnedPositionalEncoding {
+    encoding: Mat,
+    grad: Mat,
+}
+
+impl LearnedPositionalEncoding {
+    pub fn new(d_model: usize, steps: usize) -> LearnedPositionalEncoding {
+        let encoding = Mat::rand_mat(steps * d_model, 1, -0.1, 0.1);
+        LearnedPositionalEncoding {
+            grad: Mat::zeroes_like(&encoding),
+            encoding,
+        }
+    }
+}
+
+impl Layer for LearnedPositionalEncoding {
+    fn forward(&mut self, x: &Mat, _training: bool) -> Mat {
+        x.add_column(&self.encoding)
+    }
+    fn backward(&mut self, grad: &Mat) -> Mat {
+        self.grad = grad.sum_columns();
+        grad.clone()
+    }
+    fn params(&self) -> Vec<&Mat> {
+        vec![&self.encoding]
+    }
+    fn params_and_grads(&mut self) -> Vec<(&mut Mat, &Mat)> {
+        vec![(&mut self.encoding, &self.grad)]
+    }
+    fn name(&self) -> String {
+        format!("LearnedPositionalEncoding({})", self.encoding.row())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::layers::positional::{LearnedPositionalEncoding, SinusoidalEncoding};
+    use crate::layers::testing::{check_gradients, rand_mat};
+
+    #[test]
+    fn gradients_match_numeric() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let x = rand_mat(4 * 3, 2, &mut rng);
+        check_gradients(&mut SinusoidalEncoding::new(4, 3), &x);
+        check_gradients(&mut LearnedPositionalEncoding::new(4, 3), &x);
+    }
+
+    #[test]
+    fn sinusoidal_values() {
+        let encoding = SinusoidalEncoding::new(4, 4);
+        let at = |t: usize, i: usize| encoding.encoding()[(t * 4 + i, 0)];
+        // pairs of dimensions share the frequency 1 / 10000^(2 * (i / 2) / 4)
+        let expected = [
+            (0, 0, 0.0),
+            (0, 1, 1.0),
+            (1, 0, 1f64.sin()),
+            (1, 1, 1f64.cos()),
+            (2, 2, 0.02f64.sin()),
+            (3, 3, 0.03f64.cos()),
+            (3, 0, 3f64.sin()),
+        ];
+        for (t, i, value) in expected {
+            assert!((at(t, i) - value).abs() < 1e-12, "({t}, {i})");
+        }
+    }
+}
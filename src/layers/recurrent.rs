@@ -0,0 +1,656 @@
+use crate::layers::{Layer, ParamGroup};
+use crate::tools::activations::{self, ActivationFn, Sigmoid, Tanh};
+use crate::tools::matrix::*;
+
+// One time step of a recurrent layer. states[0] is always the hidden state h the layer
+// outputs, LSTM carries its cell state as states[1]. all matrices hold one sample per column
+pub trait Cell {
+    // what backward_step needs from step
+    type Cache;
+    const NAME: &'static str;
+    fn new(input_size: usize, hidden_size: usize) -> Self;
+    fn state_count(&self) -> usize;
+    fn step(&self, x: &Mat, states: &[Mat]) -> (Vec<Mat>, Self::Cache);
+    // dstates is d loss / d states returned by step, accumulates the parameter gradients
+    // and returns (d loss / d x, d loss / d previous states)
+    fn backward_step(&mut self, cache: &Self::Cache, dstates: &[Mat]) -> (Mat, Vec<Mat>);
+    fn zero_grads(&mut self);
+    fn params(&self) -> Vec<&Mat>;
+    fn params_and_grads(&mut self) -> Vec<(&mut Mat, &Mat)>;
+    fn param_groups(&self) -> Vec<ParamGroup>;
+}
+
+// Runs a Cell over sequences of `steps` time steps. a sample is one column with the
+// steps stacked on top of each other, step t is rows t * input_size .. (t + 1) * input_size.
+//
+// shorter sequences are padded up to `steps`: with mask_value set, a step whose features
+// all equal it is skipped, the state is carried over unchanged and the output there is 0.
+// with truncate(k) gradients flow back at most k steps through the state, the chunks
+// are counted from the last step
+pub struct Recurrent<C: Cell> {
+    cell: C,
+    input_size: usize,
+    hidden_size: usize,
+    steps: usize,
+    return_sequences: bool,
+    mask_value: Option<f64>,
+    truncate: Option<usize>,
+    // per step of the last forward call
+    caches: Vec<C::Cache>,
+    masks: Vec<Vec<bool>>,
+}
+
+pub type Rnn = Recurrent<RnnCell>;
+pub type Lstm = Recurrent<LstmCell>;
+pub type Gru = Recurrent<GruCell>;
+
+impl<C: Cell> Recurrent<C> {
+    // outputs the last hidden state, see return_sequences
+    pub fn new(input_size: usize, hidden_size: usize, steps: usize) -> Recurrent<C> {
+        assert!(steps > 0);
+        Recurrent {
+            cell: C::new(input_size, hidden_size),
+            input_size,
+            hidden_size,
+            steps,
+            return_sequences: false,
+            mask_value: None,
+            truncate: None,
+            caches: vec![],
+            masks: vec![],
+        }
+    }
+    // output the hidden state of every step (steps * hidden_size rows), e.g. to stack layers
+    pub fn return_sequences(mut self, val: bool) -> Recurrent<C> {
+        self.return_sequences = val;
+        self
+    }
+    pub fn mask_value(mut self, val: f64) -> Recurrent<C> {
+        self.mask_value = Some(val);
+        self
+    }
+    pub fn truncate(mut self, steps: usize) -> Recurrent<C> {
+        assert!(steps > 0);
+        self.truncate = Some(steps);
+        self
+    }
+    fn is_masked(&self, x: &Mat, s: usize) -> bool {
+        match self.mask_value {
+            Some(mask) => (0..x.row()).all(|i| x[(i, s)] == mask),
+            None => false,
+        }
+    }
+}
+
+// keep the columns of `old` where the step was masked
+fn carry(new: &mut Mat, old: &Mat, valid: &[bool]) {
+    for (s, _) in valid.iter().enumerate().filter(|(_, v)| !**v) {
+        for i in 0..new.row() {
+            new[(i, s)] = old[(i, s)];
+        }
+    }
+}
+
+fn zero_masked(m: &mut Mat, valid: &[bool]) {
+    for (s, _) in valid.iter().enumerate().filter(|(_, v)| !**v) {
+        for i in 0..m.row() {
+            m[(i, s)] = 0.0;
+        }
+    }
+}
+
+impl<C: Cell> Layer for Recurrent<C> {
+    fn forward(&mut self, x: &Mat, _training: bool) -> Mat {
+        assert_eq!(x.row(), self.steps * self.input_size);
+        let n = x.col();
+        let mut states = vec![Mat::new(self.hidden_size, n); self.cell.state_count()];
+        let mut outputs = vec![];
+        self.caches.clear();
+        self.masks.clear();
+        for t in 0..self.steps {
+            let xt = x.rows(t * self.input_size, (t + 1) * self.input_size);
+            let valid: Vec<bool> = (0..n).map(|s| !self.is_masked(&xt, s)).collect();
+            let (mut new, cache) = self.cell.step(&xt, &states);
+            for (new, old) in new.iter_mut().zip(&states) {
+                carry(new, old, &valid);
+            }
+            states = new;
+            if self.return_sequences {
+                let mut out = states[0].clone();
+                zero_masked(&mut out, &valid);
+                outputs.push(out);
+            }
+            self.caches.push(cache);
+            self.masks.push(valid);
+        }
+        if self.return_sequences {
+            Mat::vstack(&outputs)
+        } else {
+            states.swap_remove(0)
+        }
+    }
+    fn backward(&mut self, grad: &Mat) -> Mat {
+        assert_eq!(
+            self.caches.len(),
+            self.steps,
+            "backward called before forward"
+        );
+        let n = grad.col();
+        self.cell.zero_grads();
+        let mut dstates = vec![Mat::new(self.hidden_size, n); self.cell.state_count()];
+        let mut dx = vec![];
+        for t in (0..self.steps).rev() {
+            let last = t == self.steps - 1;
+            if let Some(k) = self.truncate {
+                if !last && (self.steps - 1 - t).is_multiple_of(k) {
+                    for d in dstates.iter_mut() {
+                        *d = Mat::new(self.hidden_size, n);
+                    }
+                }
+            }
+            let valid = &self.masks[t];
+            if self.return_sequences {
+                let mut g = grad.rows(t * self.hidden_size, (t + 1) * self.hidden_size);
+                // masked steps output a constant 0, nothing flows back from there
+                zero_masked(&mut g, valid);
+                dstates[0] = &dstates[0] + &g;
+            } else if last {
+                dstates[0] = &dstates[0] + grad;
+            }
+            // masked samples skip the cell, their gradient passes straight to the previous step
+            let mut through = dstates.clone();
+            for d in through.iter_mut() {
+                zero_masked(d, valid);
+            }
+            let (dxt, mut dprev) = self.cell.backward_step(&self.caches[t], &through);
+            for (dprev, d) in dprev.iter_mut().zip(&dstates) {
+                carry(dprev, d, valid);
+            }
+            dstates = dprev;
+            dx.push(dxt);
+        }
+        dx.reverse();
+        Mat::vstack(&dx)
+    }
+    fn params(&self) -> Vec<&Mat> {
+        self.cell.params()
+    }
+    fn params_and_grads(&mut self) -> Vec<(&mut Mat, &Mat)> {
+        self.cell.params_and_grads()
+    }
+    fn param_groups(&self) -> Vec<ParamGroup> {
+        self.cell.param_groups()
+    }
+    fn output_shape(&self, input_shape: usize) -> usize {
+        assert_eq!(input_shape, self.steps * self.input_size);
+        if self.return_sequences {
+            self.steps * self.hidden_size
+        } else {
+            self.hidden_size
+        }
+    }
+    fn name(&self) -> String {
+        format!(
+            "{}({} -> {}, {} steps)",
+            C::NAME,
+            self.input_size,
+            self.hidden_size,
+            self.steps
+        )
+    }
+}
+
+// uniform in +-1/sqrt(hidden_size)
+fn init(row: usize, col: usize, hidden_size: usize) -> Mat {
+    let bound = 1.0 / (hidden_size as f64).sqrt();
+    Mat::rand_mat(row, col, -bound, bound)
+}
+
+// d function / d z at the pre activations z
+fn derivative<F: ActivationFn>(function: F, z: &Mat) -> Mat {
+    let mut d = z.clone();
+    for v in d.as_mut_slice().iter_mut() {
+        *v = function.df(*v);
+    }
+    d
+}
+
+// h' = tanh(wx * x + wh * h + b)
+pub struct RnnCell {
+    wx: Mat,
+    wh: Mat,
+    b: Mat,
+    dwx: Mat,
+    dwh: Mat,
+    db: Mat,
+}
+
+// input, previous hidden state and the pre activation of the new one
+pub struct RnnCache {
+    x: Mat,
+    h: Mat,
+    z: Mat,
+}
+
+impl Cell for RnnCell {
+    type Cache = RnnCache;
+    const NAME: &'static str = "Rnn";
+    fn new(input_size: usize, hidden_size: usize) -> RnnCell {
+        let wx = init(hidden_size, input_size, hidden_size);
+        let wh = init(hidden_size, hidden_size, hidden_size);
+        let b = init(hidden_size, 1, hidden_size);
+        RnnCell {
+            dwx: Mat::zeroes_like(&wx),
+            dwh: Mat::zeroes_like(&wh),
+            db: Mat::zeroes_like(&b),
+            wx,
+            wh,
+            b,
+        }
+    }
+    fn state_count(&self) -> usize {
+        1
+    }
+    fn step(&self, x: &Mat, states: &[Mat]) -> (Vec<Mat>, RnnCache) {
+        let h = &states[0];
+        let z = (&(&self.wx * x) + &(&self.wh * h)).add_column(&self.b);
+        let h_new = z.map(activations::tanh);
+        let cache = RnnCache {
+            x: x.clone(),
+            h: h.clone(),
+            z,
+        };
+        (vec![h_new], cache)
+    }
+    fn backward_step(&mut self, cache: &RnnCache, dstates: &[Mat]) -> (Mat, Vec<Mat>) {
+        let dz = derivative(Tanh, &cache.z).ele_mul(&dstates[0]);
+        self.dwx = &self.dwx + &(&dz * &cache.x.transpose());
+        self.dwh = &self.dwh + &(&dz * &cache.h.transpose());
+        self.db = &self.db + &dz.sum_columns();
+        let dx = &self.wx.transpose() * &dz;
+        let dh = &self.wh.transpose() * &dz;
+        (dx, vec![dh])
+    }
+    fn zero_grads(&mut self) {
+        self.dwx = Mat::zeroes_like(&self.wx);
+        self.dwh = Mat::zeroes_like(&self.wh);
+        self.db = Mat::zeroes_like(&self.b);
+    }
+    fn params(&self) -> Vec<&Mat> {
+        vec![&self.wx, &self.wh, &self.b]
+    }
+    fn params_and_grads(&mut self) -> Vec<(&mut Mat, &Mat)> {
+        vec![
+            (&mut self.wx, &self.dwx),
+            (&mut self.wh, &self.dwh),
+            (&mut self.b, &self.db),
+        ]
+    }
+    fn param_groups(&self) -> Vec<ParamGroup> {
+        vec![ParamGroup::Weight, ParamGroup::Weight, ParamGroup::Bias]
+    }
+}
+
+// Hochreiter & Schmidhuber with a forget gate. the gate rows of wx, wh and b are
+// input, forget, cell candidate and output, hidden_size rows each.
+// the forget bias starts at 1 so the cell remembers by default
+pub struct LstmCell {
+    hidden_size: usize,
+    wx: Mat,
+    wh: Mat,
+    b: Mat,
+    dwx: Mat,
+    dwh: Mat,
+    db: Mat,
+}
+
+pub struct LstmCache {
+    x: Mat,
+    h: Mat,
+    c: Mat,
+    // gate pre activations, stacked like the rows of wx
+    z: Mat,
+    i: Mat,
+    f: Mat,
+    g: Mat,
+    o: Mat,
+    // the new cell state and its tanh
+    c_new: Mat,
+    tc: Mat,
+}
+
+impl Cell for LstmCell {
+    type Cache = LstmCache;
+    const NAME: &'static str = "Lstm";
+    fn new(input_size: usize, hidden_size: usize) -> LstmCell {
+        let wx = init(4 * hidden_size, input_size, hidden_size);
+        let wh = init(4 * hidden_size, hidden_size, hidden_size);
+        let mut b = init(4 * hidden_size, 1, hidden_size);
+        for i in hidden_size..2 * hidden_size {
+            b[(i, 0)] = 1.0;
+        }
+        LstmCell {
+            hidden_size,
+            dwx: Mat::zeroes_like(&wx),
+            dwh: Mat::zeroes_like(&wh),
+            db: Mat::zeroes_like(&b),
+            wx,
+            wh,
+            b,
+        }
+    }
+    fn state_count(&self) -> usize {
+        2
+    }
+    fn step(&self, x: &Mat, states: &[Mat]) -> (Vec<Mat>, LstmCache) {
+        let (h, c) = (&states[0], &states[1]);
+        let hs = self.hidden_size;
+        let z = (&(&self.wx * x) + &(&self.wh * h)).add_column(&self.b);
+        let i = z.rows(0, hs).map(activations::sigmoid);
+        let f = z.rows(hs, 2 * hs).map(activations::sigmoid);
+        let g = z.rows(2 * hs, 3 * hs).map(activations::tanh);
+        let o = z.rows(3 * hs, 4 * hs).map(activations::sigmoid);
+        let c_new = &f.ele_mul(c) + &i.ele_mul(&g);
+        let tc = c_new.map(activations::tanh);
+        let h_new = o.ele_mul(&tc);
+        let cache = LstmCache {
+            x: x.clone(),
+            h: h.clone(),
+            c: c.clone(),
+            z,
+            i,
+            f,
+            g,
+            o,
+            c_new: c_new.clone(),
+            tc,
+        };
+        (vec![h_new, c_new], cache)
+    }
+    fn backward_step(&mut self, cache: &LstmCache, dstates: &[Mat]) -> (Mat, Vec<Mat>) {
+        let (dh, dc) = (&dstates[0], &dstates[1]);
+        let hs = self.hidden_size;
+        let gate = |k: usize| cache.z.rows(k * hs, (k + 1) * hs);
+        let do_ = dh.ele_mul(&cache.tc);
+        let dc = dc
+            + &dh
+                .ele_mul(&cache.o)
+                .ele_mul(&derivative(Tanh, &cache.c_new));
+        let di = dc.ele_mul(&cache.g);
+        let dg = dc.ele_mul(&cache.i);
+        let df = dc.ele_mul(&cache.c);
+        let dc_prev = dc.ele_mul(&cache.f);
+        let dz = Mat::vstack(&[
+            di.ele_mul(&derivative(Sigmoid, &gate(0))),
+            df.ele_mul(&derivative(Sigmoid, &gate(1))),
+            dg.ele_mul(&derivative(Tanh, &gate(2))),
+            do_.ele_mul(&derivative(Sigmoid, &gate(3))),
+        ]);
+        self.dwx = &self.dwx + &(&dz * &cache.x.transpose());
+        self.dwh = &self.dwh + &(&dz * &cache.h.transpose());
+        self.db = &self.db + &dz.sum_columns();
+        let dx = &self.wx.transpose() * &dz;
+        let dh_prev = &self.wh.transpose() * &dz;
+        (dx, vec![dh_prev, dc_prev])
+    }
+    fn zero_grads(&mut self) {
+        self.dwx = Mat::zeroes_like(&self.wx);
+        self.dwh = Mat::zeroes_like(&self.wh);
+        self.db = Mat::zeroes_like(&self.b);
+    }
+    fn params(&self) -> Vec<&Mat> {
+        vec![&self.wx, &self.wh, &self.b]
+    }
+    fn params_and_grads(&mut self) -> Vec<(&mut Mat, &Mat)> {
+        vec![
+            (&mut self.wx, &self.dwx),
+            (&mut self.wh, &self.dwh),
+            (&mut self.b, &self.db),
+        ]
+    }
+    fn param_groups(&self) -> Vec<ParamGroup> {
+        vec![ParamGroup::Weight, ParamGroup::Weight, ParamGroup::Bias]
+    }
+}
+
+// Cho et al., in the form torch uses: the reset gate scales wh * h + bh of the candidate.
+// gate rows are reset, update and candidate, hidden_size rows each
+pub struct GruCell {
+    hidden_size: usize,
+    wx: Mat,
+    wh: Mat,
+    bx: Mat,
+    bh: Mat,
+    dwx: Mat,
+    dwh: Mat,
+    dbx: Mat,
+    dbh: Mat,
+}
+
+pub struct GruCache {
+    x: Mat,
+    h: Mat,
+    r: Mat,
+    z: Mat,
+    n: Mat,
+    // pre activations of r, z and n
+    r_pre: Mat,
+    z_pre: Mat,
+    n_pre: Mat,
+    // wh * h + bh of the candidate rows
+    hn: Mat,
+}
+
+impl Cell for GruCell {
+    type Cache = GruCache;
+    const NAME: &'static str = "Gru";
+    fn new(input_size: usize, hidden_size: usize) -> GruCell {
+        let wx = init(3 * hidden_size, input_size, hidden_size);
+        let wh = init(3 * hidden_size, hidden_size, hidden_size);
+        let bx = init(3 * hidden_size, 1, hidden_size);
+        let bh = init(3 * hidden_size, 1, hidden_size);
+        GruCell {
+            hidden_size,
+            dwx: Mat::zeroes_like(&wx),
+            dwh: Mat::zeroes_like(&wh),
+            dbx: Mat::zeroes_like(&bx),
+            dbh: Mat::zeroes_like(&bh),
+            wx,
+            wh,
+            bx,
+            bh,
+        }
+    }
+    fn state_count(&self) -> usize {
+        1
+    }
+    fn step(&self, x: &Mat, states: &[Mat]) -> (Vec<Mat>, GruCache) {
+        let h = &states[0];
+        let hs = self.hidden_size;
+        let gx = (&self.wx * x).add_column(&self.bx);
+        let gh = (&self.wh * h).add_column(&self.bh);
+        let r_pre = &gx.rows(0, hs) + &gh.rows(0, hs);
+        let z_pre = &gx.rows(hs, 2 * hs) + &gh.rows(hs, 2 * hs);
+        let r = r_pre.map(activations::sigmoid);
+        let z = z_pre.map(activations::sigmoid);
+        let hn = gh.rows(2 * hs, 3 * hs);
+        let n_pre = &gx.rows(2 * hs, 3 * hs) + &r.ele_mul(&hn);
+        let n = n_pre.map(activations::tanh);
+        // (1 - z) * n + z * h
+        let h_new = &(&n - &z.ele_mul(&n)) + &z.ele_mul(h);
+        let cache = GruCache {
+            x: x.clone(),
+            h: h.clone(),
+            r,
+            z,
+            n,
+            r_pre,
+            z_pre,
+            n_pre,
+            hn,
+        };
+        (vec![h_new], cache)
+    }
+    fn backward_step(&mut self, cache: &GruCache, dstates: &[Mat]) -> (Mat, Vec<Mat>) {
+        let dh = &dstates[0];
+        let dn = dh - &dh.ele_mul(&cache.z);
+        let dz = dh.ele_mul(&(&cache.h - &cache.n));
+        let dn_pre = dn.ele_mul(&derivative(Tanh, &cache.n_pre));
+        let dr = dn_pre.ele_mul(&cache.hn);
+        let dr_pre = dr.ele_mul(&derivative(Sigmoid, &cache.r_pre));
+        let dz_pre = dz.ele_mul(&derivative(Sigmoid, &cache.z_pre));
+        let dgx = Mat::vstack(&[dr_pre.clone(), dz_pre.clone(), dn_pre.clone()]);
+        let dgh = Mat::vstack(&[dr_pre, dz_pre, dn_pre.ele_mul(&cache.r)]);
+        self.dwx = &self.dwx + &(&dgx * &cache.x.transpose());
+        self.dwh = &self.dwh + &(&dgh * &cache.h.transpose());
+        self.dbx = &self.dbx + &dgx.sum_columns();
+        self.dbh = &self.dbh + &dgh.sum_columns();
+        let dx = &self.wx.transpose() * &dgx;
+        let dh_prev = &dh.ele_mul(&cache.z) + &(&self.wh.transpose() * &dgh);
+        (dx, vec![dh_prev])
+    }
+    fn zero_grads(&mut self) {
+        self.dwx = Mat::zeroes_like(&self.wx);
+        self.dwh = Mat::zeroes_like(&self.wh);
+        self.dbx = Mat::zeroes_like(&self.bx);
+        self.dbh = Mat::zeroes_like(&self.bh);
+    }
+    fn params(&self) -> Vec<&Mat> {
+        vec![&self.wx, &self.wh, &self.bx, &self.bh]
+    }
+    fn params_and_grads(&mut self) -> Vec<(&mut Mat, &Mat)> {
+        vec![
+            (&mut self.wx, &self.dwx),
+            (&mut self.wh, &self.dwh),
+            (&mut self.bx, &self.dbx),
+            (&mut self.bh, &self.dbh),
+        ]
+    }
+    fn param_groups(&self) -> Vec<ParamGroup> {
+        vec![
+            ParamGroup::Weight,
+            ParamGroup::Weight,
+            ParamGroup::Bias,
+            ParamGroup::Bias,
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::layers::recurrent::{Gru, Lstm, Rnn};
+    use crate::layers::testing::{check_gradients, rand_mat};
+    use crate::layers::Layer;
+    use crate::nn::gradcheck::GradCheck;
+    use crate::tools::matrix::*;
+
+    // 4 steps of 3 features, the second sample is padded with 0 after 2 steps
+    fn padded(rng: &mut ChaCha8Rng) -> Mat {
+        let mut x = rand_mat(3 * 4, 2, rng);
+        for i in 6..12 {
+            x[(i, 1)] = 0.0;
+        }
+        x
+    }
+
+    fn layers(sequences: bool) -> Vec<Box<dyn Layer>> {
+        vec![
+            Box::new(
+                Rnn::new(3, 4, 4)
+                    .mask_value(0.0)
+                    .return_sequences(sequences),
+            ),
+            Box::new(
+                Lstm::new(3, 4, 4)
+                    .mask_value(0.0)
+                    .return_sequences(sequences),
+            ),
+            Box::new(
+                Gru::new(3, 4, 4)
+                    .mask_value(0.0)
+                    .return_sequences(sequences),
+            ),
+        ]
+    }
+
+    #[test]
+    fn gradients_match_numeric() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        // truncated backprop cuts gradients on purpose, so only full sequences are checked
+        let x = rand_mat(3 * 4, 2, &mut rng);
+        for sequences in [false, true] {
+            check_gradients(&mut Rnn::new(3, 4, 4).return_sequences(sequences), &x);
+            check_gradients(&mut Lstm::new(3, 4, 4).return_sequences(sequences), &x);
+            check_gradients(&mut Gru::new(3, 4, 4).return_sequences(sequences), &x);
+        }
+    }
+
+    #[test]
+    fn masked_gradients_match_numeric() {
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+        let x = padded(&mut rng);
+        for sequences in [false, true] {
+            for mut layer in layers(sequences) {
+                // nudging a padded input would unmask its step, so only parameters are checked
+                GradCheck::new()
+                    .input(false)
+                    .layer(layer.as_mut(), &x)
+                    .assert_below(1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn masked_steps_keep_the_state() {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let x = padded(&mut rng);
+        let mut rnn = Rnn::new(3, 4, 4).mask_value(0.0);
+        let last = rnn.forward(&x, false);
+        let mut rnn = rnn.return_sequences(true);
+        let all = rnn.forward(&x, false);
+        for i in 0..4 {
+            // the padded sample ends with the state of its second step, its outputs there are 0
+            assert_eq!(last[(i, 1)], all[(4 + i, 1)]);
+            assert_eq!((all[(8 + i, 1)], all[(12 + i, 1)]), (0.0, 0.0));
+            assert_eq!(last[(i, 0)], all[(12 + i, 0)]);
+        }
+
+        // the lstm cell state is carried too: padding in the middle changes nothing
+        let mut lstm = Lstm::new(3, 4, 3).mask_value(0.0);
+        let short = x.rows(0, 9);
+        let gap = Mat::vstack(&[short.rows(0, 3), Mat::new(3, 2), short.rows(3, 9)]);
+        let mut lstm4 = Lstm::new(3, 4, 4).mask_value(0.0);
+        for ((p, _), (q, _)) in lstm4
+            .params_and_grads()
+            .into_iter()
+            .zip(lstm.params_and_grads())
+        {
+            *p = q.clone();
+        }
+        let a = lstm.forward(&short, false);
+        let b = lstm4.forward(&gap, false);
+        for (a, b) in a.as_slice().iter().zip(b.as_slice()) {
+            assert!((a - b).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn truncation_cuts_gradients_after_k_steps() {
+        let mut rng = ChaCha8Rng::seed_from_u64(4);
+        let x = rand_mat(3 * 5, 2, &mut rng);
+        let grad = rand_mat(4, 2, &mut rng);
+        let mut full = Gru::new(3, 4, 5);
+        full.forward(&x, true);
+        let dx_full = full.backward(&grad);
+        let mut truncated = full.truncate(2);
+        truncated.forward(&x, true);
+        let dx = truncated.backward(&grad);
+        // the last two steps get their full gradient, the three before nothing
+        assert_eq!(dx.rows(9, 15).as_slice(), dx_full.rows(9, 15).as_slice());
+        assert!(dx.rows(0, 9).as_slice().iter().all(|v| *v == 0.0));
+        assert!(dx_full.rows(0, 9).as_slice().iter().any(|v| *v != 0.0));
+    }
+}
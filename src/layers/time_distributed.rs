@@ -0,0 +1,115 @@
+use crate::layers::{Layer, ParamGroup};
+use crate::tools::matrix::*;
+
+// (steps * features, n) sequences, like layers::recurrent takes them, to a (features, steps * n)
+// matrix with one column per step. column s * steps + t is step t of sample s
+pub fn steps_to_columns(x: &Mat, steps: usize) -> Mat {
+    assert_eq!(x.row() % steps, 0);
+    let features = x.row() / steps;
+    let mut out = Mat::new(features, steps * x.col());
+    for s in 0..x.col() {
+        for t in 0..steps {
+            for f in 0..features {
+                out[(f, s * steps + t)] = x[(t * features + f, s)];
+            }
+        }
+    }
+    out
+}
+
+// inverse of steps_to_columns
+pub fn columns_to_steps(x: &Mat, steps: usize) -> Mat {
+    assert_eq!(x.col() % steps, 0);
+    let features = x.row();
+    let n = x.col() / steps;
+    let mut out = Mat::new(steps * features, n);
+    for s in 0..n {
+        for t in 0..steps {
+            for f in 0..features {
+                out[(t * features + f, s)] = x[(f, s * steps + t)];
+            }
+        }
+    }
+    out
+}
+
+// applies a layer to every step of a sequence on its own, sharing the weights between steps
+pub struct TimeDistributed {
+    layer: Box<dyn Layer>,
+    steps: usize,
+}
+
+impl TimeDistributed {
+    pub fn new(layer: Box<dyn Layer>, steps: usize) -> TimeDistributed {
+        assert!(steps > 0);
+        TimeDistributed { layer, steps }
+    }
+}
+
+impl Layer for TimeDistributed {
+    fn forward(&mut self, x: &Mat, training: bool) -> Mat {
+        let out = self
+            .layer
+            .forward(&steps_to_columns(x, self.steps), training);
+        columns_to_steps(&out, self.steps)
+    }
+    fn backward(&mut self, grad: &Mat) -> Mat {
+        let dx = self.layer.backward(&steps_to_columns(grad, self.steps));
+        columns_to_steps(&dx, self.steps)
+    }
+    fn params(&self) -> Vec<&Mat> {
+        self.layer.params()
+    }
+    fn params_and_grads(&mut self) -> Vec<(&mut Mat, &Mat)> {
+        self.layer.params_and_grads()
+    }
+    fn param_groups(&self) -> Vec<ParamGroup> {
+        self.layer.param_groups()
+    }
+    fn sparse_rows(&self) -> Vec<Option<Vec<usize>>> {
+        self.layer.sparse_rows()
+    }
+    fn buffers(&self) -> Vec<&Mat> {
+        self.layer.buffers()
+    }
+    fn buffers_mut(&mut self) -> Vec<&mut Mat> {
+        self.layer.buffers_mut()
+    }
+    fn output_shape(&self, input_shape: usize) -> usize {
+        assert_eq!(input_shape % self.steps, 0);
+        self.layer.output_shape(input_shape / self.steps) * self.steps
+    }
+    fn seed(&mut self, seed: u64) {
+        self.layer.seed(seed);
+    }
+    fn state(&self) -> Option<serde_json::Value> {
+        self.layer.state()
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        self.layer.load_state(state);
+    }
+    fn name(&self) -> String {
+        format!(
+            "TimeDistributed({}, {} steps)",
+            self.layer.name(),
+            self.steps
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::layers::dense::Dense;
+    use crate::layers::testing::{check_gradients, rand_mat};
+    use crate::layers::time_distributed::TimeDistributed;
+
+    #[test]
+    fn gradients_match_numeric() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let mut layer = TimeDistributed::new(Box::new(Dense::new(3, 2)), 4);
+        check_gradients(&mut layer, &rand_mat(3 * 4, 2, &mut rng));
+    }
+}
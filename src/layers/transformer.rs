@@ -0,0 +1,120 @@
+use crate::layers::activation::Activation;
+use crate::layers::attention::MultiHeadAttention;
+use crate::layers::dense::Dense;
+use crate::layers::layer_norm::LayerNorm;
+use crate::layers::time_distributed::TimeDistributed;
+use crate::layers::{Layer, ParamGroup};
+use crate::tools::matrix::*;
+
+// Pre norm transformer encoder block over sequences laid out like layers::recurrent expects:
+//   x = x + attention(norm(x))
+//   x = x + dense(relu(dense(norm(x))))
+// normalization and the feed forward part act on every step on its own
+pub struct TransformerEncoderBlock {
+    // norm, attention, norm, dense, relu, dense
+    layers: Vec<Box<dyn Layer>>,
+    d_model: usize,
+    steps: usize,
+}
+
+impl TransformerEncoderBlock {
+    pub fn new(
+        d_model: usize,
+        heads: usize,
+        ff_size: usize,
+        steps: usize,
+    ) -> TransformerEncoderBlock {
+        TransformerEncoderBlock::from_attention(
+            MultiHeadAttention::new(d_model, heads, steps),
+            d_model,
+            ff_size,
+            steps,
+        )
+    }
+    // e.g. with a causal MultiHeadAttention
+    pub fn from_attention(
+        attention: MultiHeadAttention,
+        d_model: usize,
+        ff_size: usize,
+        steps: usize,
+    ) -> TransformerEncoderBlock {
+        let step = |layer: Box<dyn Layer>| -> Box<dyn Layer> {
+            Box::new(TimeDistributed::new(layer, steps))
+        };
+        TransformerEncoderBlock {
+            layers: vec![
+                step(Box::new(LayerNorm::new(d_model))),
+                Box::new(attention),
+                step(Box::new(LayerNorm::new(d_model))),
+                step(Box::new(Dense::scaled(d_model, ff_size))),
+                step(Box::new(Activation::relu())),
+                step(Box::new(Dense::scaled(ff_size, d_model))),
+            ],
+            d_model,
+            steps,
+        }
+    }
+}
+
+impl Layer for TransformerEncoderBlock {
+    fn forward(&mut self, x: &Mat, training: bool) -> Mat {
+        let [norm1, attention, norm2, ff1, relu, ff2] = &mut self.layers[..] else {
+            unreachable!()
+        };
+        let a = attention.forward(&norm1.forward(x, training), training);
+        let x = x + &a;
+        let f = ff1.forward(&norm2.forward(&x, training), training);
+        let f = ff2.forward(&relu.forward(&f, training), training);
+        &x + &f
+    }
+    fn backward(&mut self, grad: &Mat) -> Mat {
+        let [norm1, attention, norm2, ff1, relu, ff2] = &mut self.layers[..] else {
+            unreachable!()
+        };
+        let df = ff1.backward(&relu.backward(&ff2.backward(grad)));
+        let dx = grad + &norm2.backward(&df);
+        let da = norm1.backward(&attention.backward(&dx));
+        &dx + &da
+    }
+    fn params(&self) -> Vec<&Mat> {
+        self.layers
+            .iter()
+            .flat_map(|layer| layer.params())
+            .collect()
+    }
+    fn params_and_grads(&mut self) -> Vec<(&mut Mat, &Mat)> {
+        self.layers
+            .iter_mut()
+            .flat_map(|layer| layer.params_and_grads())
+            .collect()
+    }
+    fn param_groups(&self) -> Vec<ParamGroup> {
+        self.layers
+            .iter()
+            .flat_map(|layer| layer.param_groups())
+            .collect()
+    }
+    fn output_shape(&self, input_shape: usize) -> usize {
+        assert_eq!(input_shape, self.steps * self.d_model);
+        input_shape
+    }
+    fn name(&self) -> String {
+        format!("TransformerEncoderBlock({})", self.layers[1].name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::layers::testing::{check_gradients, rand_mat};
+    use crate::layers::transformer::TransformerEncoderBlock;
+
+    #[test]
+    fn gradients_match_numeric() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let mut layer = TransformerEncoderBlock::new(4, 2, 6, 3);
+        check_gradients(&mut layer, &rand_mat(4 * 3, 2, &mut rng));
+    }
+}
@@ -0,0 +1,9 @@
+// the network library, the kek binary in main.rs and the benches are built on top of it
+pub mod config;
+pub mod data;
+pub mod layers;
+pub mod metrics;
+pub mod models;
+pub mod nn;
+pub mod search;
+pub mod tools;
@@ -1,62 +1,534 @@
-mod tools;
+use std::fs::File;
+use std::path::{Path, PathBuf};
 
-extern crate sdl2;
-mod nn;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
-use sdl2::rect::Rect;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
 
-use std::time::Duration;
-use tools::matrix::*;
-const GRID_SIZE: usize = 28;
-const CELL_SIZE: i32 = 20; // Adjust this value to change cell size
+use kek::config::{Config, DataConfig, TrainingConfig};
+use kek::data::image_folder::{self, ColorMode};
+use kek::data::sequence;
+use kek::data::text::CharDataset;
+use kek::data::transform::{Pipeline, Transform};
+use kek::metrics;
+use kek::models;
+use kek::nn::callbacks::{Callback, LogFormat, MetricsLogger, ProgressPrinter};
+use kek::nn::early_stopping::{EarlyStopping, Monitor};
+use kek::nn::optim::{Optimizer, OptimizerKind};
+use kek::nn::regularization::{GradientClipping, Regularization};
+use kek::nn::tensorboard::TensorBoardLogger;
+use kek::nn::{self, NN};
+use kek::search::{self, Search, SearchConfig};
+use kek::tools::matrix::*;
 
-fn main() {
-    let (x_test, y_test) = nn::parse_mnist(&String::from("mnist_test.csv"));
-    let (x_train, y_train) = nn::parse_mnist(&String::from("mnist_train.csv"));
+const SIDE: usize = 28;
+const CLASSES: usize = 10;
+
+#[derive(Parser)]
+#[command(
+    name = "kek",
+    about = "train and use digit classifiers on mnist style csv files"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[command(about = "train on a mnist style csv (label, 784 pixels) and write a model file")]
+    Train(Box<TrainArgs>),
+    #[command(about = "search hyperparameters of a config and write a leaderboard csv")]
+    Search {
+        #[arg(long, help = "base config with a data section, see configs/")]
+        config: String,
+        #[arg(
+            long,
+            help = "strategy, objective and search space, see configs/search.toml"
+        )]
+        search: String,
+        #[arg(long, default_value = "leaderboard.csv")]
+        leaderboard: String,
+        #[arg(long, help = "write the config of the best trial here")]
+        save_best: Option<String>,
+        #[arg(long, help = "trials run at once, defaults to the number of cores")]
+        threads: Option<usize>,
+    },
+    #[command(about = "print metrics of a model on a labeled csv")]
+    Eval {
+        #[arg(long, default_value = "model.json")]
+        model: String,
+        #[arg(long, default_value = "mnist_test.csv")]
+        data: String,
+    },
+    #[command(about = "classify the rows of a csv and/or image files")]
+    Predict {
+        #[arg(long, default_value = "model.json")]
+        model: String,
+        #[arg(
+            long,
+            help = "csv with 784 pixel columns, an extra leading column is read as the label"
+        )]
+        csv: Option<String>,
+        #[arg(
+            long,
+            help = "images are dark ink on a light background, mnist is the other way round"
+        )]
+        invert: bool,
+        #[arg(help = "image files, scaled to 28x28 gray")]
+        images: Vec<PathBuf>,
+    },
+    // only built with the sdl feature, it needs the native SDL2 library
+    #[cfg(feature = "sdl")]
+    #[command(about = "draw digits on a canvas and watch the model classify them")]
+    Draw {
+        #[arg(long, default_value = "model.json")]
+        model: String,
+    },
+    #[command(about = "train the character level lstm on a text file and print samples")]
+    CharLm {
+        file: String,
+        #[arg(long, default_value_t = 32)]
+        steps: usize,
+        #[arg(long, default_value_t = 128)]
+        hidden: usize,
+        #[arg(long, default_value_t = 10)]
+        epochs: i32,
+        #[arg(long, default_value_t = 0.003)]
+        lr: f64,
+    },
+    #[command(
+        about = "learn to reverse random token sequences with a transformer, no data needed"
+    )]
+    Transformer {
+        #[arg(long, default_value_t = 5)]
+        epochs: i32,
+        #[arg(long, default_value_t = 0.002)]
+        lr: f64,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum Arch {
+    // models::mnist_mlp
+    Mlp,
+    // models::lenet5
+    Lenet,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum OptimizerName {
+    Sgd,
+    Momentum,
+    Adam,
+}
+
+#[derive(Args)]
+struct TrainArgs {
+    #[arg(long, default_value = "mnist_train.csv")]
+    data: String,
+    #[arg(long, help = "labeled csv to report metrics on after training")]
+    test: Option<String>,
+    #[arg(long, default_value = "model.json")]
+    out: String,
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "data", "test", "arch", "hidden", "dropout", "lr", "optimizer", "momentum", "beta1",
+            "beta2", "eps", "l2", "clip_norm", "epochs", "batch_size", "val_split", "patience",
+            "min_delta", "no_augment", "seed",
+        ],
+        help = "toml or json model, training and data config, instead of the flags it covers"
+    )]
+    config: Option<String>,
+    #[arg(
+        long,
+        help = "write the config of this run (.toml or .json) before training"
+    )]
+    save_config: Option<String>,
+    #[arg(long, value_enum, default_value_t = Arch::Mlp)]
+    arch: Arch,
+    #[arg(long, default_value_t = 32, help = "hidden layer size of the mlp")]
+    hidden: usize,
+    #[arg(
+        long,
+        default_value_t = 0.1,
+        help = "dropout after every hidden layer of the mlp"
+    )]
+    dropout: f64,
+    #[arg(
+        long,
+        help = "learning rate, 0.2 for the mlp and 0.1 for lenet by default"
+    )]
+    lr: Option<f64>,
+    #[arg(long, value_enum, default_value_t = OptimizerName::Sgd)]
+    optimizer: OptimizerName,
+    #[arg(long, default_value_t = 0.9, help = "beta of the momentum optimizer")]
+    momentum: f64,
+    #[arg(long, default_value_t = 0.9)]
+    beta1: f64,
+    #[arg(long, default_value_t = 0.999)]
+    beta2: f64,
+    #[arg(long, default_value_t = 1e-8)]
+    eps: f64,
+    #[arg(long, help = "l2 weight decay")]
+    l2: Option<f64>,
+    #[arg(long, help = "clip the global gradient norm")]
+    clip_norm: Option<f64>,
+    #[arg(long, default_value_t = 30)]
+    epochs: i32,
+    #[arg(long, default_value_t = 16)]
+    batch_size: usize,
+    #[arg(
+        long,
+        default_value_t = 0.1,
+        help = "share of the training csv held out for validation"
+    )]
+    val_split: f64,
+    #[arg(
+        long,
+        default_value_t = 3,
+        help = "epochs without val accuracy gains before stopping, 0 never stops"
+    )]
+    patience: usize,
+    #[arg(long, default_value_t = 0.001)]
+    min_delta: f64,
+    #[arg(
+        long,
+        help = "train on the images as they are, no random shifts and rotations"
+    )]
+    no_augment: bool,
+    #[arg(long, default_value_t = 42)]
+    seed: u64,
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "print the running loss every n batches"
+    )]
+    log_every: usize,
+    #[arg(
+        long,
+        help = "write per epoch metrics here, .jsonl or .json for json lines, csv otherwise"
+    )]
+    metrics: Option<String>,
+    #[arg(
+        long,
+        requires = "metrics",
+        help = "write per step metrics here, in the format of --metrics"
+    )]
+    step_metrics: Option<String>,
+    #[arg(
+        long,
+        default_value_t = 10,
+        help = "batches between rows of --step-metrics and tensorboard step scalars"
+    )]
+    step_every: usize,
+    #[arg(
+        long,
+        help = "write tensorboard event files with scalars and weight histograms to this dir"
+    )]
+    tensorboard: Option<String>,
+}
+
+// what train writes: the resolved config rebuilds the network, the transform prepares inputs
+#[derive(Serialize, Deserialize)]
+struct ModelFile {
+    config: Config,
+    transform: Pipeline,
+    weights: Vec<Mat>,
+}
+
+impl ModelFile {
+    fn save(&self, path: &str) {
+        let file = File::create(path).expect("couldn't create model file ;(");
+        serde_json::to_writer(file, self).expect("couldn't write model file ;(");
+    }
+    // the network in eval mode and the input transform
+    fn load(path: &str) -> (NN, Pipeline) {
+        let file = File::open(path).expect("couldn't open model file ;(");
+        let model: ModelFile = serde_json::from_reader(file).expect("couldn't parse model file ;(");
+        let mut nn = model.config.build();
+        nn.set_weights(model.weights);
+        nn.eval_mode();
+        (nn, model.transform)
+    }
+}
+
+fn config_from_flags(args: &TrainArgs) -> Config {
+    let (model, lr) = match args.arch {
+        Arch::Mlp => (
+            models::mnist_mlp_config(args.hidden, CLASSES, args.dropout),
+            0.2,
+        ),
+        Arch::Lenet => (models::lenet5_config(CLASSES), 0.1),
+    };
+    Config {
+        model,
+        training: TrainingConfig {
+            optimizer: match args.optimizer {
+                OptimizerName::Sgd => OptimizerKind::Sgd,
+                OptimizerName::Momentum => OptimizerKind::Momentum {
+                    beta: args.momentum,
+                },
+                OptimizerName::Adam => OptimizerKind::Adam {
+                    beta1: args.beta1,
+                    beta2: args.beta2,
+                    eps: args.eps,
+                },
+            },
+            lr: args.lr.unwrap_or(lr),
+            schedule: None,
+            epochs: args.epochs,
+            batch_size: args.batch_size,
+            seed: args.seed,
+            regularization: args.l2.map(Regularization::l2),
+            clipping: args.clip_norm.map(GradientClipping::Norm),
+            // stop once validation accuracy stalls and keep the best epoch's weights
+            early_stopping: (args.patience > 0)
+                .then(|| EarlyStopping::new(Monitor::ValAccuracy, args.patience, args.min_delta)),
+        },
+        data: Some(DataConfig {
+            train: args.data.clone(),
+            test: args.test.clone(),
+            val_split: args.val_split,
+            // small random shifts/rotations so the net copes with off center drawings
+            augment: !args.no_augment,
+            transform: vec![Transform::min_max(0.0, 0.9)],
+        }),
+    }
+}
+
+fn train(args: TrainArgs) {
+    let config = match &args.config {
+        Some(path) => Config::load(path),
+        None => config_from_flags(&args),
+    };
+    if let Some(path) = &args.save_config {
+        config.save(path);
+        println!("saved {}", path);
+    }
+    let mut callbacks: Vec<Box<dyn Callback>> =
+        vec![Box::new(ProgressPrinter::every(args.log_every))];
+    if let Some(path) = &args.metrics {
+        let format = match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("jsonl" | "json") => LogFormat::JsonLines,
+            _ => LogFormat::Csv,
+        };
+        let mut logger = MetricsLogger::new(path, format);
+        if let Some(steps) = &args.step_metrics {
+            logger = logger.steps(steps, args.step_every);
+        }
+        callbacks.push(Box::new(logger));
+    }
+    if let Some(dir) = &args.tensorboard {
+        callbacks.push(Box::new(TensorBoardLogger::new(dir).every(args.step_every)));
+    }
+    let trained = config.train(callbacks);
+    if let Some(report) = &trained.test {
+        println!("{}", report);
+    }
+    ModelFile {
+        config,
+        transform: trained.transform,
+        weights: trained.nn.weights(),
+    }
+    .save(&args.out);
+    println!("saved {}", args.out);
+}
+
+fn tune(
+    config: &str,
+    search: &str,
+    leaderboard: &str,
+    save_best: Option<&str>,
+    threads: Option<usize>,
+) {
+    if let Some(threads) = threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .expect("couldn't set up the thread pool ;(");
+    }
+    let base = Config::load(config);
+    let data = base.data.as_ref().expect("config has no data section ;(");
+    let (x, y) = nn::parse_mnist(&data.train);
+    let trials = Search::new(base.clone(), SearchConfig::load(search))
+        .verbose(true)
+        .run(&x, &y);
+    search::write_leaderboard(leaderboard, &trials);
+    println!("saved {}", leaderboard);
+    let best = &trials[0];
     println!(
-        "y size:{}\nx size:{}\ny_train size:{:?}\ny_test size:{:?}",
-        y_train.len(),
-        x_train.len(),
-        y_train[0].shape(),
-        x_train[0].shape(),
+        "best trial:{} score:{:.4} {}",
+        best.id,
+        best.score,
+        serde_json::to_string(&best.params).unwrap()
     );
+    if let Some(path) = save_best {
+        search::apply(&base, &best.params).save(path);
+        println!("saved {}", path);
+    }
+}
+
+fn eval(model: &str, data: &str) {
+    let (mut nn, transform) = ModelFile::load(model);
+    let (x, y) = nn::parse_mnist(data);
+    println!("{}", nn.evaluate(&transform.apply_all(&x), &y));
+}
 
-    let row = 28;
-    let col = 28;
-    let output_classes = 10;
-    let hidden_layer_size = 32;
-
-    let mut nn = nn::NN::new(row * col, hidden_layer_size, output_classes);
-    nn.set_lr(0.2);
-    nn.train(&x_train, &y_train, 10, 16, true);
-
-    println!("test predictions:");
-    let num_prediction = x_test.len() / 2;
-    let mut miss = 0;
-    for i in 0..num_prediction {
-        let prediction = &nn.feed_forward(&x_test[i]).5.get_max().1;
-        let label = &y_test[i].get_max().1;
-        if prediction != label {
-            miss += 1;
+// pixel rows of a csv, with the label when there is a leading label column
+fn read_rows(path: &str) -> Vec<(Option<usize>, Mat)> {
+    let file = File::open(path).expect("couldn't open file ;(");
+    let mut rdr = csv::Reader::from_reader(file);
+    let mut rows = vec![];
+    for result in rdr.records() {
+        let record = result.expect("couldn't read csv row ;(");
+        let values: Vec<f64> = record
+            .iter()
+            .map(|v| v.trim().parse().expect("csv values have to be numbers ;("))
+            .collect();
+        let row = match values.len() {
+            n if n == SIDE * SIDE => (None, Mat::from_vec(values, n, 1)),
+            n if n == SIDE * SIDE + 1 => (
+                Some(values[0] as usize),
+                Mat::from_vec(values[1..].to_vec(), n - 1, 1),
+            ),
+            n => panic!("rows need {} pixels, got {} columns", SIDE * SIDE, n),
+        };
+        rows.push(row);
+    }
+    rows
+}
+
+fn predict(model: &str, csv: Option<&str>, images: &[PathBuf], invert: bool) {
+    let (mut nn, transform) = ModelFile::load(model);
+    let mut classify = |x: &Mat| metrics::label_of(&nn.predict(&transform.apply(x)));
+    if let Some(path) = csv {
+        for (i, (label, x)) in read_rows(path).iter().enumerate() {
+            match label {
+                Some(label) => println!("{}: {} (label {})", i, classify(x), label),
+                None => println!("{}: {}", i, classify(x)),
+            }
         }
     }
-    let accuracy = (num_prediction - miss) as f64 / num_prediction as f64;
-    println!("accuracy = {}", accuracy);
+    for path in images {
+        // load_image gives 0..1, the transform expects raw 0..255 pixels like the csv
+        let x = image_folder::load_image(path, SIDE, SIDE, ColorMode::Gray);
+        let x = x.map(if invert {
+            |v| (1.0 - v) * 255.0
+        } else {
+            |v| v * 255.0
+        });
+        println!("{}: {}", path.display(), classify(&x));
+    }
+}
 
-    let (xo, yo) = nn::parse_mnist(&String::from("output.csv"));
-    println!(
-        "y size:{}\nx size:{}\ny_train size:{:?}\ny_test size:{:?}",
-        yo.len(),
-        xo.len(),
-        yo[0].shape(),
-        xo[0].shape(),
+// trains models::char_lm on a text file and prints a few samples
+fn char_lm(path: &str, steps: usize, hidden: usize, epochs: i32, lr: f64) {
+    let data = CharDataset::new(path, steps, 3);
+    println!("samples:{} vocab:{}", data.len(), data.vocab().len());
+    let mut nn = models::char_lm(data.vocab().len(), steps, hidden);
+    nn.set_optimizer(Optimizer::adam(0.9, 0.999, 1e-8));
+    nn.set_lr(lr);
+    nn.set_gradient_clipping(Some(GradientClipping::Norm(5.0)));
+    nn.train(
+        &data.x,
+        &data.y,
+        None,
+        epochs,
+        32,
+        &mut [Box::new(ProgressPrinter::every(100))],
+    );
+
+    nn.eval_mode();
+    let mut rng = rand::thread_rng();
+    for temperature in [0.5, 0.8, 1.0] {
+        let text = models::sample_text(
+            &mut nn,
+            data.vocab(),
+            steps,
+            "The ",
+            200,
+            temperature,
+            &mut rng,
+        );
+        println!("temperature:{}\n{}\n", temperature, text);
+    }
+}
+
+// learns to reverse random token sequences, nothing has to be downloaded
+fn transformer(epochs: i32, lr: f64) {
+    let (vocab, steps) = (10, 8);
+    let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+    let (x, y) = sequence::reversal(5000, vocab, steps, &mut rng);
+    let (x_val, y_val) = sequence::reversal(500, vocab, steps, &mut rng);
+    let mut nn = models::sequence_transformer(vocab, steps, 32, 4, 2);
+    nn.set_optimizer(Optimizer::adam(0.9, 0.999, 1e-8));
+    nn.set_lr(lr);
+    nn.train(
+        &x,
+        &y,
+        Some((&x_val, &y_val)),
+        epochs,
+        32,
+        &mut [Box::new(ProgressPrinter::new())],
     );
 
-    let prediction = &nn.feed_forward(&xo[0]).5.get_max().1;
-    let label = &yo[0].get_max().1;
-    println!("(L:{},P:{})", label, prediction);
+    nn.eval_mode();
+    let predictions: Vec<Mat> = x_val.iter().map(|xi| nn.predict(xi)).collect();
+    let accuracy = metrics::sequence_accuracy(&predictions, &y_val, steps);
+    println!("validation accuracy per token:{}", accuracy);
+}
+
+fn main() {
+    match Cli::parse().command {
+        Command::Train(args) => train(*args),
+        Command::Search {
+            config,
+            search,
+            leaderboard,
+            save_best,
+            threads,
+        } => tune(
+            &config,
+            &search,
+            &leaderboard,
+            save_best.as_deref(),
+            threads,
+        ),
+        Command::Eval { model, data } => eval(&model, &data),
+        Command::Predict {
+            model,
+            csv,
+            invert,
+            images,
+        } => predict(&model, csv.as_deref(), &images, invert),
+        #[cfg(feature = "sdl")]
+        Command::Draw { model } => draw(&model),
+        Command::CharLm {
+            file,
+            steps,
+            hidden,
+            epochs,
+            lr,
+        } => char_lm(&file, steps, hidden, epochs, lr),
+        Command::Transformer { epochs, lr } => transformer(epochs, lr),
+    }
+}
+
+#[cfg(feature = "sdl")]
+fn draw(model: &str) {
+    use sdl2::event::Event;
+    use sdl2::keyboard::Keycode;
+    use sdl2::pixels::Color;
+    use sdl2::rect::Rect;
+    use std::time::Duration;
+
+    const GRID_SIZE: usize = SIDE;
+    const CELL_SIZE: i32 = 20; // Adjust this value to change cell size
+    let (row, col) = (SIDE, SIDE);
+    let (mut nn, transform) = ModelFile::load(model);
+
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
 
@@ -91,15 +563,17 @@ fn main() {
                 } => {
                     break 'running;
                 }
-                Event::MouseButtonDown { mouse_btn, .. } => {
-                    if mouse_btn == sdl2::mouse::MouseButton::Left {
-                        is_mouse_down = true;
-                    }
+                Event::MouseButtonDown {
+                    mouse_btn: sdl2::mouse::MouseButton::Left,
+                    ..
+                } => {
+                    is_mouse_down = true;
                 }
-                Event::MouseButtonUp { mouse_btn, .. } => {
-                    if mouse_btn == sdl2::mouse::MouseButton::Left {
-                        is_mouse_down = false;
-                    }
+                Event::MouseButtonUp {
+                    mouse_btn: sdl2::mouse::MouseButton::Left,
+                    ..
+                } => {
+                    is_mouse_down = false;
                 }
                 Event::MouseMotion { x, y, .. } if is_mouse_down => {
                     let cell_x = (x / CELL_SIZE) as usize;
@@ -134,15 +608,14 @@ fn main() {
         let mut buffer = vec![0.0; 28 * 28];
         for i in 0..28 {
             for j in 0..28 {
-                buffer[Mat::map_2_to_1(j, i, col)] = ((grid[i][j] as i32) as f64) * 0.9;
+                buffer[Mat::map_2_to_1(j, i, col)] = ((grid[i][j] as i32) as f64) * 255.0;
             }
         }
-        let x = Mat::from_vec(buffer, row * col, 1);
-        let prediction = &nn.feed_forward(&x).5.get_max().1;
+        let x = transform.apply(&Mat::from_vec(buffer, row * col, 1));
+        let prediction = metrics::label_of(&nn.predict(&x));
 
         println!("prediction:{}", prediction);
         // Add a small delay to avoid high CPU usage
         std::thread::sleep(Duration::from_millis(10));
     }
-    return;
 }
@@ -0,0 +1,485 @@
+use core::fmt;
+
+use crate::tools::matrix::*;
+
+// clip probabilities away from 0 and 1 before taking logs
+const EPS: f64 = 1e-15;
+
+// class of a prediction or target column: argmax of a column,
+// a single output is treated as binary and thresholded at 0.5
+pub fn label_of(y: &Mat) -> usize {
+    if y.row() * y.col() == 1 {
+        return (y[(0, 0)] >= 0.5) as usize;
+    }
+    y.get_max().1
+}
+
+// number of classes a column encodes, a single output counts as two
+pub fn class_count(y: &Mat) -> usize {
+    (y.row() * y.col()).max(2)
+}
+
+pub fn accuracy(predictions: &[Mat], targets: &[Mat]) -> f64 {
+    assert_eq!(predictions.len(), targets.len());
+    let hits = predictions
+        .iter()
+        .zip(targets)
+        .filter(|(p, t)| label_of(p) == label_of(t))
+        .count();
+    hits as f64 / predictions.len() as f64
+}
+
+// accuracy over every step of sequence outputs, each step being its own block of rows
+pub fn sequence_accuracy(predictions: &[Mat], targets: &[Mat], steps: usize) -> f64 {
+    assert_eq!(predictions.len(), targets.len());
+    let mut hits = 0;
+    for (p, t) in predictions.iter().zip(targets) {
+        let size = p.row() / steps;
+        for step in 0..steps {
+            let rows = |m: &Mat| m.rows(step * size, (step + 1) * size);
+            hits += (label_of(&rows(p)) == label_of(&rows(t))) as usize;
+        }
+    }
+    hits as f64 / (predictions.len() * steps) as f64
+}
+
+// fraction of samples whose true class is among the k highest scoring outputs
+pub fn top_k_accuracy(predictions: &[Mat], targets: &[Mat], k: usize) -> f64 {
+    assert_eq!(predictions.len(), targets.len());
+    assert!(k > 0);
+    let mut hits = 0;
+    for (p, t) in predictions.iter().zip(targets) {
+        let scores = p.as_slice();
+        let label = label_of(t);
+        if scores.len() == 1 {
+            // a single output only ranks two classes, top 1 is plain accuracy
+            hits += (k > 1 || label_of(p) == label) as usize;
+            continue;
+        }
+        // classes scoring strictly higher than the true class, ties go in the true class' favour
+        let higher = scores.iter().filter(|s| **s > scores[label]).count();
+        if higher < k {
+            hits += 1;
+        }
+    }
+    hits as f64 / predictions.len() as f64
+}
+
+// mean cross entropy, predictions are rescaled to sum to 1 per sample first,
+// single output predictions use the binary form
+pub fn log_loss(predictions: &[Mat], targets: &[Mat]) -> f64 {
+    assert_eq!(predictions.len(), targets.len());
+    let mut total = 0.0;
+    for (p, t) in predictions.iter().zip(targets) {
+        let scores = p.as_slice();
+        if scores.len() == 1 {
+            let q = scores[0].clamp(EPS, 1.0 - EPS);
+            let y = t.as_slice()[0];
+            total -= y * libm::log(q) + (1.0 - y) * libm::log(1.0 - q);
+        } else {
+            let sum: f64 = scores.iter().map(|s| s.clamp(EPS, 1.0)).sum();
+            let q = scores[label_of(t)].clamp(EPS, 1.0) / sum;
+            total -= libm::log(q);
+        }
+    }
+    total / predictions.len() as f64
+}
+
+// area under the roc curve for a binary task, computed from the rank sum so ties count half
+pub fn roc_auc(scores: &[f64], labels: &[bool]) -> f64 {
+    assert_eq!(scores.len(), labels.len());
+    let positives = labels.iter().filter(|l| **l).count();
+    let negatives = labels.len() - positives;
+    assert!(
+        positives > 0 && negatives > 0,
+        "roc auc needs both classes present"
+    );
+
+    let mut order: Vec<usize> = (0..scores.len()).collect();
+    order.sort_by(|a, b| scores[*a].total_cmp(&scores[*b]));
+    let mut rank_sum = 0.0;
+    let mut i = 0;
+    while i < order.len() {
+        let mut j = i;
+        while j + 1 < order.len() && scores[order[j + 1]] == scores[order[i]] {
+            j += 1;
+        }
+        // ranks are 1 based, tied scores share the average rank
+        let rank = (i + j) as f64 / 2.0 + 1.0;
+        for index in &order[i..=j] {
+            if labels[*index] {
+                rank_sum += rank;
+            }
+        }
+        i = j + 1;
+    }
+    let positives = positives as f64;
+    (rank_sum - positives * (positives + 1.0) / 2.0) / (positives * negatives as f64)
+}
+
+// positive class score of a binary prediction: the single output, or the second of two
+pub fn binary_score(prediction: &Mat) -> f64 {
+    let scores = prediction.as_slice();
+    match scores.len() {
+        1 => scores[0],
+        2 => scores[1] / (scores[0] + scores[1]).max(EPS),
+        _ => panic!("binary_score needs one or two outputs"),
+    }
+}
+
+// counts[actual][predicted]
+#[derive(Clone, Debug)]
+pub struct ConfusionMatrix {
+    counts: Vec<Vec<usize>>,
+}
+
+impl ConfusionMatrix {
+    pub fn new(predictions: &[Mat], targets: &[Mat], classes: usize) -> ConfusionMatrix {
+        assert_eq!(predictions.len(), targets.len());
+        let mut counts = vec![vec![0; classes]; classes];
+        for (p, t) in predictions.iter().zip(targets) {
+            counts[label_of(t)][label_of(p)] += 1;
+        }
+        ConfusionMatrix { counts }
+    }
+    pub fn classes(&self) -> usize {
+        self.counts.len()
+    }
+    pub fn count(&self, actual: usize, predicted: usize) -> usize {
+        self.counts[actual][predicted]
+    }
+    pub fn total(&self) -> usize {
+        self.counts.iter().flatten().sum()
+    }
+    pub fn true_positives(&self, class: usize) -> usize {
+        self.counts[class][class]
+    }
+    pub fn false_positives(&self, class: usize) -> usize {
+        (0..self.classes())
+            .filter(|actual| *actual != class)
+            .map(|actual| self.counts[actual][class])
+            .sum()
+    }
+    pub fn false_negatives(&self, class: usize) -> usize {
+        (0..self.classes())
+            .filter(|predicted| *predicted != class)
+            .map(|predicted| self.counts[class][predicted])
+            .sum()
+    }
+    // number of samples that actually belong to class
+    pub fn support(&self, class: usize) -> usize {
+        self.counts[class].iter().sum()
+    }
+    // undefined ratios (nothing predicted / nothing present) count as 0
+    pub fn precision(&self, class: usize) -> f64 {
+        ratio(
+            self.true_positives(class),
+            self.true_positives(class) + self.false_positives(class),
+        )
+    }
+    pub fn recall(&self, class: usize) -> f64 {
+        ratio(
+            self.true_positives(class),
+            self.true_positives(class) + self.false_negatives(class),
+        )
+    }
+    pub fn f1(&self, class: usize) -> f64 {
+        f1(self.precision(class), self.recall(class))
+    }
+    // unweighted mean over classes
+    pub fn macro_precision(&self) -> f64 {
+        self.mean_over_classes(|c| self.precision(c))
+    }
+    pub fn macro_recall(&self) -> f64 {
+        self.mean_over_classes(|c| self.recall(c))
+    }
+    pub fn macro_f1(&self) -> f64 {
+        self.mean_over_classes(|c| self.f1(c))
+    }
+    // pooled counts over classes, for single label classification all three equal accuracy
+    pub fn micro_precision(&self) -> f64 {
+        let tp: usize = (0..self.classes()).map(|c| self.true_positives(c)).sum();
+        let fp: usize = (0..self.classes()).map(|c| self.false_positives(c)).sum();
+        ratio(tp, tp + fp)
+    }
+    pub fn micro_recall(&self) -> f64 {
+        let tp: usize = (0..self.classes()).map(|c| self.true_positives(c)).sum();
+        let fn_: usize = (0..self.classes()).map(|c| self.false_negatives(c)).sum();
+        ratio(tp, tp + fn_)
+    }
+    pub fn micro_f1(&self) -> f64 {
+        f1(self.micro_precision(), self.micro_recall())
+    }
+    fn mean_over_classes<F: Fn(usize) -> f64>(&self, f: F) -> f64 {
+        (0..self.classes()).map(f).sum::<f64>() / self.classes() as f64
+    }
+}
+
+fn ratio(num: usize, den: usize) -> f64 {
+    if den == 0 {
+        return 0.0;
+    }
+    num as f64 / den as f64
+}
+
+fn f1(precision: f64, recall: f64) -> f64 {
+    if precision + recall == 0.0 {
+        return 0.0;
+    }
+    2.0 * precision * recall / (precision + recall)
+}
+
+impl fmt::Display for ConfusionMatrix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "actual\\predicted")?;
+        for predicted in 0..self.classes() {
+            write!(f, "\t{}", predicted)?;
+        }
+        writeln!(f)?;
+        for (actual, row) in self.counts.iter().enumerate() {
+            write!(f, "{}", actual)?;
+            for count in row {
+                write!(f, "\t{}", count)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ClassMetrics {
+    pub precision: f64,
+    pub recall: f64,
+    pub f1: f64,
+    pub support: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Averages {
+    pub precision: f64,
+    pub recall: f64,
+    pub f1: f64,
+}
+
+// Everything NN::evaluate measures on a dataset.
+#[derive(Clone, Debug)]
+pub struct Report {
+    pub samples: usize,
+    pub accuracy: f64,
+    pub log_loss: f64,
+    pub per_class: Vec<ClassMetrics>,
+    pub macro_avg: Averages,
+    pub micro_avg: Averages,
+    pub confusion: ConfusionMatrix,
+    // only for binary tasks
+    pub roc_auc: Option<f64>,
+}
+
+impl Report {
+    pub fn new(predictions: &[Mat], targets: &[Mat]) -> Report {
+        assert!(!predictions.is_empty(), "can't report on an empty dataset");
+        let classes = class_count(&targets[0]);
+        let confusion = ConfusionMatrix::new(predictions, targets, classes);
+        let per_class = (0..classes)
+            .map(|c| ClassMetrics {
+                precision: confusion.precision(c),
+                recall: confusion.recall(c),
+                f1: confusion.f1(c),
+                support: confusion.support(c),
+            })
+            .collect();
+
+        let roc_auc = if classes == 2 {
+            let scores: Vec<f64> = predictions.iter().map(binary_score).collect();
+            let labels: Vec<bool> = targets.iter().map(|t| label_of(t) == 1).collect();
+            // undefined when only one class is present
+            if labels.iter().any(|l| *l) && labels.iter().any(|l| !*l) {
+                Some(roc_auc(&scores, &labels))
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+
+        Report {
+            samples: predictions.len(),
+            accuracy: accuracy(predictions, targets),
+            log_loss: log_loss(predictions, targets),
+            per_class,
+            macro_avg: Averages {
+                precision: confusion.macro_precision(),
+                recall: confusion.macro_recall(),
+                f1: confusion.macro_f1(),
+            },
+            micro_avg: Averages {
+                precision: confusion.micro_precision(),
+                recall: confusion.micro_recall(),
+                f1: confusion.micro_f1(),
+            },
+            confusion,
+            roc_auc,
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "samples:{}", self.samples)?;
+        writeln!(f, "accuracy:{:.4}", self.accuracy)?;
+        writeln!(f, "log loss:{:.4}", self.log_loss)?;
+        if let Some(auc) = self.roc_auc {
+            writeln!(f, "roc auc:{:.4}", auc)?;
+        }
+        writeln!(f, "class\tprecision\trecall\tf1\tsupport")?;
+        for (class, m) in self.per_class.iter().enumerate() {
+            writeln!(
+                f,
+                "{}\t{:.4}\t\t{:.4}\t{:.4}\t{}",
+                class, m.precision, m.recall, m.f1, m.support
+            )?;
+        }
+        for (name, avg) in [("macro", &self.macro_avg), ("micro", &self.micro_avg)] {
+            writeln!(
+                f,
+                "{}\t{:.4}\t\t{:.4}\t{:.4}\t{}",
+                name, avg.precision, avg.recall, avg.f1, self.samples
+            )?;
+        }
+        write!(f, "{}", self.confusion)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics::*;
+
+    fn cols(values: &[&[f64]]) -> Vec<Mat> {
+        values
+            .iter()
+            .map(|v| Mat::from_vec(v.to_vec(), v.len(), 1))
+            .collect()
+    }
+
+    fn one_hot(labels: &[usize], classes: usize) -> Vec<Mat> {
+        labels
+            .iter()
+            .map(|l| {
+                let mut m = Mat::new(classes, 1);
+                m[(*l, 0)] = 1.0;
+                m
+            })
+            .collect()
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn accuracy_and_top_k() {
+        let p = cols(&[
+            &[0.7, 0.2, 0.1],
+            &[0.1, 0.3, 0.6],
+            &[0.5, 0.4, 0.1],
+            &[0.2, 0.2, 0.6],
+        ]);
+        let t = one_hot(&[0, 1, 1, 1], 3);
+        assert!(close(accuracy(&p, &t), 0.25));
+        assert!(close(top_k_accuracy(&p, &t, 1), 0.25));
+        // tied with class 0 on the last sample, ties count as hits
+        assert!(close(top_k_accuracy(&p, &t, 2), 1.0));
+        assert!(close(top_k_accuracy(&p, &t, 3), 1.0));
+    }
+
+    #[test]
+    fn single_output_binary_predictions() {
+        let p = cols(&[&[0.9], &[0.2], &[0.4], &[0.6]]);
+        let t = cols(&[&[1.0], &[0.0], &[1.0], &[0.0]]);
+        assert!(close(accuracy(&p, &t), 0.5));
+        assert!(close(top_k_accuracy(&p, &t, 1), 0.5));
+        assert!(close(top_k_accuracy(&p, &t, 2), 1.0));
+        let expected = -(0.9f64.ln() + 0.8f64.ln() + 0.4f64.ln() + 0.4f64.ln()) / 4.0;
+        assert!(close(log_loss(&p, &t), expected));
+    }
+
+    #[test]
+    fn log_loss_rescales_and_clips() {
+        let t = one_hot(&[0, 1], 2);
+        let p = cols(&[&[2.0, 2.0], &[0.25, 0.75]]);
+        let expected = -(0.5f64.ln() + 0.75f64.ln()) / 2.0;
+        assert!(close(log_loss(&p, &t), expected));
+        // a zero probability on the true class stays finite
+        let p = cols(&[&[0.0, 1.0], &[1.0, 0.0]]);
+        assert!(close(log_loss(&p, &t), -EPS.ln()));
+    }
+
+    #[test]
+    fn roc_auc_counts_ties_half() {
+        assert!(close(
+            roc_auc(&[0.1, 0.4, 0.35, 0.8], &[false, false, true, true]),
+            0.75
+        ));
+        assert!(close(
+            roc_auc(&[0.5, 0.5, 0.5, 0.5], &[false, true, false, true]),
+            0.5
+        ));
+        // one positive tied with one of two negatives: 1 win, 1 tie out of 2 pairs
+        assert!(close(
+            roc_auc(&[0.2, 0.6, 0.6], &[false, false, true]),
+            0.75
+        ));
+        assert!(close(roc_auc(&[0.1, 0.3, 0.9], &[false, true, true]), 1.0));
+    }
+
+    #[test]
+    #[should_panic(expected = "roc auc needs both classes present")]
+    fn roc_auc_needs_both_classes() {
+        roc_auc(&[0.1, 0.2], &[true, true]);
+    }
+
+    #[test]
+    fn confusion_matrix_and_report() {
+        let p = one_hot(&[0, 0, 1, 1, 1, 0], 2);
+        let t = one_hot(&[0, 0, 0, 1, 1, 1], 2);
+        let confusion = ConfusionMatrix::new(&p, &t, 2);
+        assert_eq!(
+            (
+                confusion.count(0, 0),
+                confusion.count(0, 1),
+                confusion.count(1, 0)
+            ),
+            (2, 1, 1)
+        );
+        assert_eq!(confusion.total(), 6);
+        assert!(close(confusion.precision(0), 2.0 / 3.0));
+        assert!(close(confusion.recall(1), 2.0 / 3.0));
+        assert!(close(confusion.micro_f1(), 4.0 / 6.0));
+        assert_eq!(
+            confusion.to_string(),
+            "actual\\predicted\t0\t1\n0\t2\t1\n1\t1\t2\n"
+        );
+
+        let report = Report::new(&p, &t);
+        assert_eq!(report.samples, 6);
+        assert!(close(report.accuracy, 4.0 / 6.0));
+        assert!(close(report.macro_avg.f1, 2.0 / 3.0));
+        assert_eq!(report.per_class[1].support, 3);
+        assert!(close(report.roc_auc.unwrap(), 6.0 / 9.0));
+        let text = report.to_string();
+        assert!(text.starts_with("samples:6\naccuracy:0.6667\n"));
+        assert!(text.contains("roc auc:0.6667\n"));
+        assert!(text.contains("macro\t0.6667\t\t0.6667\t0.6667\t6\n"));
+        assert!(text.ends_with(&confusion.to_string()));
+    }
+
+    #[test]
+    fn multiclass_report_has_no_auc() {
+        let p = one_hot(&[0, 1, 2], 3);
+        let report = Report::new(&p, &p);
+        assert_eq!(report.roc_auc, None);
+        assert_eq!(report.per_class.len(), 3);
+        assert!(close(report.accuracy, 1.0));
+    }
+}
@@ -0,0 +1,216 @@
+use rand::Rng;
+
+use crate::config::{ActivationConfig, Init, LayerConfig, ModelConfig};
+use crate::data::text::CharVocab;
+use crate::layers::activation::Activation;
+use crate::layers::dense::Dense;
+use crate::layers::graph::{Graph, NodeId};
+use crate::layers::positional::SinusoidalEncoding;
+use crate::layers::recurrent::Lstm;
+use crate::layers::time_distributed::TimeDistributed;
+use crate::layers::transformer::TransformerEncoderBlock;
+use crate::layers::Layer;
+use crate::nn::loss::Loss;
+use crate::nn::NN;
+
+// the dense mnist net: NN::new's shape with dropout after each hidden layer
+pub fn mnist_mlp(hidden_layer_size: usize, classes: usize, dropout: f64) -> NN {
+    mnist_mlp_config(hidden_layer_size, classes, dropout).build()
+}
+
+// mnist_mlp as a config, e.g. to train it through config::Config
+pub fn mnist_mlp_config(hidden_layer_size: usize, classes: usize, dropout: f64) -> ModelConfig {
+    let dense = |units| LayerConfig::Dense {
+        units,
+        init: Init::Uniform,
+    };
+    let relu = LayerConfig::Activation(ActivationConfig::Relu);
+    let dropout = LayerConfig::Dropout { p: dropout };
+    ModelConfig {
+        input: 28 * 28,
+        image: Some((1, 28, 28)),
+        layers: vec![
+            dense(hidden_layer_size),
+            relu.clone(),
+            dropout.clone(),
+            dense(hidden_layer_size),
+            relu,
+            dropout,
+            dense(classes),
+        ],
+        loss: Loss::SigmoidCrossEntropy,
+    }
+}
+
+// LeCun et al. 1998 for 28x28 single channel images: the first convolution pads by 2 so it
+// sees the 32x32 input of the paper, tanh and average pooling like the original
+pub fn lenet5(classes: usize) -> NN {
+    lenet5_config(classes).build()
+}
+
+pub fn lenet5_config(classes: usize) -> ModelConfig {
+    let conv = |filters, padding| LayerConfig::Conv2d {
+        filters,
+        kernel: 5,
+        stride: 1,
+        padding,
+    };
+    let pool = LayerConfig::AvgPool2d {
+        kernel: 2,
+        stride: None,
+    };
+    let tanh = LayerConfig::Activation(ActivationConfig::Tanh);
+    let dense = |units| LayerConfig::Dense {
+        units,
+        init: Init::Scaled,
+    };
+    ModelConfig {
+        input: 28 * 28,
+        image: Some((1, 28, 28)),
+        layers: vec![
+            conv(6, 2),
+            tanh.clone(),
+            pool.clone(),
+            conv(16, 0),
+            tanh.clone(),
+            pool,
+            LayerConfig::Flatten,
+            dense(120),
+            tanh.clone(),
+            dense(84),
+            tanh,
+            dense(classes),
+        ],
+        loss: Loss::SoftmaxCrossEntropy,
+    }
+}
+
+// one hot tokens in, a score per token and step out, e.g. for data::sequence::reversal.
+// every step is embedded on its own, gets a sinusoidal position and goes through `blocks`
+// encoder blocks, sigmoid outputs so every step is scored independently
+pub fn sequence_transformer(
+    vocab: usize,
+    steps: usize,
+    d_model: usize,
+    heads: usize,
+    blocks: usize,
+) -> NN {
+    let mut layers: Vec<Box<dyn Layer>> = vec![
+        Box::new(TimeDistributed::new(
+            Box::new(Dense::scaled(vocab, d_model)),
+            steps,
+        )),
+        Box::new(SinusoidalEncoding::new(d_model, steps)),
+    ];
+    for _ in 0..blocks {
+        layers.push(Box::new(TransformerEncoderBlock::new(
+            d_model,
+            heads,
+            2 * d_model,
+            steps,
+        )));
+    }
+    layers.push(Box::new(TimeDistributed::new(
+        Box::new(Dense::scaled(d_model, vocab)),
+        steps,
+    )));
+    NN::from_layers(vocab * steps, layers, Loss::SigmoidCrossEntropy)
+}
+
+// next character prediction over `steps` characters of context, see data::text::CharDataset.
+// all zero steps (left padding, characters outside the vocabulary) are masked out
+pub fn char_lm(vocab_size: usize, steps: usize, hidden_size: usize) -> NN {
+    NN::from_layers(
+        vocab_size * steps,
+        vec![
+            Box::new(Lstm::new(vocab_size, hidden_size, steps).mask_value(0.0)),
+            Box::new(Dense::scaled(hidden_size, vocab_size)),
+        ],
+        Loss::SoftmaxCrossEntropy,
+    )
+}
+
+// extends seed by `len` characters sampled from a char_lm, lower temperature is more greedy.
+// the model should be in eval mode
+pub fn sample_text<R: Rng>(
+    nn: &mut NN,
+    vocab: &CharVocab,
+    steps: usize,
+    seed: &str,
+    len: usize,
+    temperature: f64,
+    rng: &mut R,
+) -> String {
+    let mut text: Vec<char> = seed.chars().collect();
+    for _ in 0..len {
+        let p = nn.predict(&vocab.context(&text, steps));
+        // p^(1 / temperature), renormalized by drawing against the sum
+        let weights: Vec<f64> = p
+            .as_slice()
+            .iter()
+            .map(|p| p.powf(1.0 / temperature))
+            .collect();
+        let mut target = rng.gen::<f64>() * weights.iter().sum::<f64>();
+        let mut next = weights.len() - 1;
+        for (i, w) in weights.iter().enumerate() {
+            if target < *w {
+                next = i;
+                break;
+            }
+            target -= w;
+        }
+        text.push(vocab.char(next));
+    }
+    text.into_iter().collect()
+}
+
+// x + W2 relu(W1 x), keeps the size of x so blocks can be chained
+pub fn residual_block(graph: &mut Graph, x: NodeId, hidden: usize) -> NodeId {
+    let size = graph.size(x);
+    let h = graph.layer(Dense::scaled(size, hidden), x);
+    let h = graph.layer(Activation::relu(), h);
+    let h = graph.layer(Dense::scaled(hidden, size), h);
+    let sum = graph.add(&[x, h]);
+    graph.layer(Activation::relu(), sum)
+}
+
+// MLP with `blocks` residual blocks of width `width` between the input and output projections
+pub fn resnet_mlp(input: usize, width: usize, blocks: usize, classes: usize) -> NN {
+    let mut graph = Graph::new();
+    let x = graph.input(input);
+    let mut h = graph.layer(Dense::scaled(input, width), x);
+    h = graph.layer(Activation::relu(), h);
+    for _ in 0..blocks {
+        h = residual_block(&mut graph, h, 2 * width);
+    }
+    let out = graph.layer(Dense::scaled(width, classes), h);
+    graph.output(out);
+    NN::from_layers(input, vec![Box::new(graph)], Loss::SoftmaxCrossEntropy)
+}
+
+// two inputs fed as one column, the first `left` rows and the remaining `right` rows.
+// each goes through its own tower to an `embed` sized vector, the concatenated
+// embeddings are scored by a small head
+pub fn two_tower(left: usize, right: usize, hidden: usize, embed: usize, out: usize) -> NN {
+    let mut graph = Graph::new();
+    let x = graph.input(left + right);
+    let parts = graph.split(x, &[left, right]);
+    let towers: Vec<NodeId> = parts
+        .into_iter()
+        .map(|part| {
+            let size = graph.size(part);
+            let h = graph.layer(Dense::scaled(size, hidden), part);
+            let h = graph.layer(Activation::relu(), h);
+            graph.layer(Dense::scaled(hidden, embed), h)
+        })
+        .collect();
+    let joined = graph.concat(&towers);
+    let h = graph.layer(Activation::relu(), joined);
+    let h = graph.layer(Dense::scaled(2 * embed, out), h);
+    graph.output(h);
+    NN::from_layers(
+        left + right,
+        vec![Box::new(graph)],
+        Loss::SigmoidCrossEntropy,
+    )
+}
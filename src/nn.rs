@@ -1,157 +1,531 @@
+pub mod callbacks;
+pub mod checkpoint;
+pub mod early_stopping;
+pub mod gradcheck;
+pub mod loss;
+pub mod optim;
+pub mod regularization;
+pub mod tensorboard;
+
 use std::fs::File;
 
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
 
-use crate::tools;
+use crate::config::Config;
+use crate::data::loader::DataLoader;
+use crate::layers::activation::Activation;
+use crate::layers::dense::Dense;
+use crate::layers::Layer;
+use crate::metrics;
+use crate::nn::callbacks::Callback;
+use crate::nn::checkpoint::{Checkpoint, CheckpointConfig};
+use crate::nn::early_stopping::{EarlyStopper, EarlyStopping};
+use crate::nn::loss::Loss;
+use crate::nn::optim::Optimizer;
+use crate::nn::regularization::{GradientClipping, Regularization};
 use crate::tools::activations;
 use crate::tools::matrix::*;
-#[allow(dead_code)]
+// what train reports after every epoch, val_ entries are set when a validation set is given
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EpochMetrics {
+    pub epoch: i32,
+    pub loss: f64,
+    pub val_loss: Option<f64>,
+    pub val_accuracy: Option<f64>,
+}
+
 pub struct NN {
-    w1: Mat,
-    b1: Mat,
-    w2: Mat,
-    b2: Mat,
-    w3: Mat,
-    b3: Mat,
+    layers: Vec<Box<dyn Layer>>,
+    loss: Loss,
+    // train_mode / eval_mode, decides how layers like dropout behave in forward
+    training: bool,
     input_shape: usize,
-    hidden_layer_size: usize,
     output_shape: usize,
     lr: f64,
+    optimizer: Optimizer,
+    // seeds the batch order of train, see set_seed
+    rng: ChaCha8Rng,
+    early_stopping: Option<EarlyStopping>,
+    checkpoint: Option<CheckpointConfig>,
+    regularization: Option<Regularization>,
+    clipping: Option<GradientClipping>,
+    // global gradient norm of the last step before clipping
+    grad_norm: Option<f64>,
+    // what the model was built from, kept in checkpoints
+    config: Option<Config>,
+}
+
+// position of a run, carried between epochs and saved in checkpoints
+struct TrainState {
+    epoch: i32,
+    epochs: i32,
+    step: usize,
+    stopped: bool,
+    stopper: Option<EarlyStopper>,
+    history: Vec<EpochMetrics>,
 }
 impl NN {
+    // dense relu dense relu dense, sigmoid outputs
     pub fn new(input_shape: usize, hidden_layer_size: usize, output_shape: usize) -> NN {
-        let w1 = Mat::rand_mat(hidden_layer_size, input_shape, -1.0, 1.0);
-        let b1 = Mat::rand_mat(hidden_layer_size, 1, -1.0, 1.0);
-        //h1
-        let w2 = Mat::rand_mat(hidden_layer_size, hidden_layer_size, -1.0, 1.0);
-        let b2 = Mat::rand_mat(hidden_layer_size, 1, -1.0, 1.0);
-
-        let w3 = Mat::rand_mat(output_shape, hidden_layer_size, -1.0, 1.0);
-        let b3 = Mat::rand_mat(output_shape, 1, -1.0, 1.0);
+        NN::from_layers(
+            input_shape,
+            vec![
+                Box::new(Dense::new(input_shape, hidden_layer_size)),
+                Box::new(Activation::relu()),
+                Box::new(Dense::new(hidden_layer_size, hidden_layer_size)),
+                Box::new(Activation::relu()),
+                Box::new(Dense::new(hidden_layer_size, output_shape)),
+            ],
+            Loss::SigmoidCrossEntropy,
+        )
+    }
+    // any stack of layers, the loss applies the output non linearity (see nn::loss)
+    pub fn from_layers(input_shape: usize, layers: Vec<Box<dyn Layer>>, loss: Loss) -> NN {
+        let output_shape = layers
+            .iter()
+            .fold(input_shape, |shape, layer| layer.output_shape(shape));
         NN {
-            w1: w1,
-            b1: b1,
-            w2: w2,
-            b2: b2,
-            w3: w3,
-            b3: b3,
-            input_shape: input_shape,
-            hidden_layer_size: hidden_layer_size,
-            output_shape: output_shape,
+            layers,
+            loss,
+            training: true,
+            input_shape,
+            output_shape,
             lr: 0.1,
+            optimizer: Optimizer::sgd(),
+            rng: ChaCha8Rng::from_entropy(),
+            early_stopping: None,
+            checkpoint: None,
+            regularization: None,
+            clipping: None,
+            grad_norm: None,
+            config: None,
         }
     }
-    pub fn feed_forward(&self, x: &Mat) -> (Mat, Mat, Mat, Mat, Mat, Mat) {
-        let z1 = &(&self.w1 * &x) + &self.b1;
-        let a1 = z1.map(tools::activations::relu);
-
-        let z2 = &(&self.w2 * &a1) + &self.b2;
-        let a2 = z2.map(tools::activations::relu);
-
-        let z3 = &(&self.w3 * &a2) + &self.b3;
-        let a3 = z3.map(tools::activations::sigmoid);
-        (z1, a1, z2, a2, z3, a3)
+    // a new model starts in training mode, train switches back to it on its own
+    pub fn train_mode(&mut self) {
+        self.training = true;
     }
-    pub fn back_prop(
-        &self,
-        ins: (&Mat, &Mat, &Mat, &Mat, &Mat, &Mat, &Mat, &Mat),
-    ) -> (Mat, Mat, Mat, Mat, Mat, Mat) {
-        /*
-        //backprop
-        let (z1, a1, _z2, a2, z3, a3, x, y) = ins;
-
-        let dz2 = a2 - y;
-        // println!("diff:{}", &dz2);
-        let dw2 = &dz2 * &a1.transpose();
-        let db2 = dz2.clone();
-        let dz1 = (&z1.map(activations::drelu)).ele_mul(&(&self.w2.transpose() * &dz2));
-        let dw1 = &dz1 * &x.transpose();
-        // println!("dz1:{}", &dz1);
-        let db1 = dz1.clone();
-        (dw1, db1, dw2, db2)
-        */
-        //backprop
-        let (z1, a1, z2, a2, _z3, a3, x, y) = ins;
-
-        let dz3 = a3 - y;
-        let dw3 = &dz3 * &a2.transpose();
-        let db3 = dz3.clone();
-
-        let dz2 = (&z2.map(activations::drelu)).ele_mul(&(&self.w3.transpose() * &dz3));
-        let dw2 = &dz2 * &a1.transpose();
-        let db2 = dz2.clone();
-
-        let dz1 = (&z1.map(activations::drelu)).ele_mul(&(&self.w2.transpose() * &dz2));
-        let dw1 = &dz1 * &x.transpose();
-        let db1 = dz1.clone();
-
-        (dw1, db1, dw2, db2, dw3, db3)
+    // deterministic inference: dropout is off, normalization uses its running statistics
+    pub fn eval_mode(&mut self) {
+        self.training = false;
+    }
+    pub fn is_training(&self) -> bool {
+        self.training
+    }
+    pub fn layers(&self) -> &[Box<dyn Layer>] {
+        &self.layers
+    }
+    pub fn loss(&self) -> Loss {
+        self.loss
+    }
+    // rows of a single input column
+    pub fn input_shape(&self) -> usize {
+        self.input_shape
+    }
+    pub fn output_shape(&self) -> usize {
+        self.output_shape
+    }
+    // outputs for a batch with one sample per column, in the current mode
+    pub fn forward(&mut self, x: &Mat) -> Mat {
+        let z = self.forward_raw(x, self.training);
+        self.loss.output(&z)
+    }
+    fn forward_raw(&mut self, x: &Mat, training: bool) -> Mat {
+        let mut out = x.clone();
+        for layer in self.layers.iter_mut() {
+            out = layer.forward(&out, training);
+        }
+        out
+    }
+    // network output for a single input column
+    pub fn predict(&mut self, x: &Mat) -> Mat {
+        self.forward(x)
+    }
+    // inference mode outputs for every sample, the mode of the model is left alone
+    fn predict_all(&mut self, x: &[Mat]) -> Vec<Mat> {
+        x.iter()
+            .map(|xi| {
+                let z = self.forward_raw(xi, false);
+                self.loss.output(&z)
+            })
+            .collect()
+    }
+    // run x through the network and score the outputs against y, always in inference mode
+    pub fn evaluate(&mut self, x: &[Mat], y: &[Mat]) -> metrics::Report {
+        assert_eq!(x.len(), y.len());
+        let predictions = self.predict_all(x);
+        metrics::Report::new(&predictions, y)
     }
     pub fn set_lr(&mut self, val: f64) {
         self.lr = val;
     }
-    pub fn update_param(&mut self, dw1: &Mat, db1: &Mat, dw2: &Mat, db2: &Mat) {
-        let lr = self.lr;
-        self.w1 = &self.w1 - &dw1.scaler_mul(lr);
-        self.b1 = &self.b1 - &db1.scaler_mul(lr);
+    pub fn lr(&self) -> f64 {
+        self.lr
+    }
+    // replaces the optimizer together with its moment buffers
+    pub fn set_optimizer(&mut self, val: Optimizer) {
+        self.optimizer = val;
+    }
+    pub fn optimizer(&self) -> &Optimizer {
+        &self.optimizer
+    }
+    // makes the batch order of train and layer randomness like dropout masks reproducible
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+        for layer in self.layers.iter_mut() {
+            layer.seed(self.rng.gen());
+        }
+    }
+    // None turns early stopping off again
+    pub fn set_early_stopping(&mut self, val: Option<EarlyStopping>) {
+        self.early_stopping = val;
+    }
+    // periodically save a Checkpoint while training, None turns it off again
+    pub fn set_checkpoint(&mut self, val: Option<CheckpointConfig>) {
+        self.checkpoint = val;
+    }
+    // weight decay added to the gradients of every step, None turns it off again
+    pub fn set_regularization(&mut self, val: Option<Regularization>) {
+        self.regularization = val;
+    }
+    pub fn regularization(&self) -> Option<&Regularization> {
+        self.regularization.as_ref()
+    }
+    pub fn set_gradient_clipping(&mut self, val: Option<GradientClipping>) {
+        if let Some(clipping) = &val {
+            clipping.validate();
+        }
+        self.clipping = val;
+    }
+    // global l2 norm of the last step's gradients (penalties included) before clipping,
+    // None before the first step
+    pub fn grad_norm(&self) -> Option<f64> {
+        self.grad_norm
+    }
+    // set by config::Config::build
+    pub fn set_config(&mut self, val: Option<Config>) {
+        self.config = val;
+    }
+    pub fn config(&self) -> Option<&Config> {
+        self.config.as_ref()
+    }
+    // total weight decay penalty of the current parameters, not part of the loss train reports
+    pub fn penalty(&self) -> f64 {
+        let Some(reg) = &self.regularization else {
+            return 0.0;
+        };
+        let mut penalty = 0.0;
+        for layer in self.layers.iter() {
+            for (param, group) in layer.params().into_iter().zip(layer.param_groups()) {
+                penalty += reg.penalty(group).loss(param);
+            }
+        }
+        penalty
+    }
+    // copies of every parameter and buffer, layer by layer, in the order set_weights expects
+    pub fn weights(&self) -> Vec<Mat> {
+        self.layers
+            .iter()
+            .flat_map(|layer| layer.params().into_iter().chain(layer.buffers()))
+            .cloned()
+            .collect()
+    }
+    // weights only, the architecture has to match when loading them back
+    pub fn save_weights(&self, path: &str) {
+        let file = File::create(path).expect("couldn't create weights file ;(");
+        serde_json::to_writer(file, &self.weights()).expect("couldn't write weights file ;(");
+    }
+    pub fn load_weights(&mut self, path: &str) {
+        let file = File::open(path).expect("couldn't open weights file ;(");
+        let weights: Vec<Mat> =
+            serde_json::from_reader(file).expect("couldn't parse weights file ;(");
+        self.set_weights(weights);
+    }
+    pub fn set_weights(&mut self, weights: Vec<Mat>) {
+        let mut weights = weights.into_iter();
+        for layer in self.layers.iter_mut() {
+            for (param, _) in layer.params_and_grads() {
+                let new = weights.next().expect("not enough weights for this model");
+                assert_eq!(param.shape(), new.shape());
+                *param = new;
+            }
+            for buffer in layer.buffers_mut() {
+                let new = weights.next().expect("not enough weights for this model");
+                assert_eq!(buffer.shape(), new.shape());
+                *buffer = new;
+            }
+        }
+        assert!(weights.next().is_none(), "too many weights for this model");
+    }
+    // Layer::state of every layer (dropout rngs, ...)
+    pub fn layer_states(&self) -> Vec<Option<serde_json::Value>> {
+        self.layers.iter().map(|layer| layer.state()).collect()
+    }
+    pub fn load_layer_states(&mut self, states: Vec<Option<serde_json::Value>>) {
+        assert_eq!(states.len(), self.layers.len());
+        for (layer, state) in self.layers.iter_mut().zip(states) {
+            if let Some(state) = state {
+                layer.load_state(state);
+            }
+        }
+    }
+    // one optimizer step with the gradients of the last backward pass,
+    // weight decay is added first and clipping sees the result
+    fn update_params(&mut self) {
+        let mut grads = vec![];
+        let mut sparse = vec![];
+        for layer in self.layers.iter_mut() {
+            let groups = layer.param_groups();
+            let rows = layer.sparse_rows();
+            let params = layer.params_and_grads().into_iter().zip(groups).zip(rows);
+            for (((param, grad), group), rows) in params {
+                // sparse gradients are zero outside their rows, only those are copied
+                let mut grad = match &rows {
+                    Some(rows) => grad.select_rows(rows),
+                    None => grad.clone(),
+                };
+                if let Some(reg) = &self.regularization {
+                    let penalty = reg.penalty(group);
+                    match &rows {
+                        _ if penalty.is_none() => {}
+                        Some(rows) => penalty.add_grad_rows(param, &mut grad, rows),
+                        None => penalty.add_grad(param, &mut grad),
+                    }
+                }
+                grads.push(grad);
+                sparse.push(rows);
+            }
+        }
+        self.grad_norm = Some(regularization::global_norm(&grads));
+        if let Some(clipping) = &self.clipping {
+            clipping.clip(&mut grads);
+        }
 
-        self.w2 = &self.w2 - &dw2.scaler_mul(lr);
-        self.b2 = &self.b2 - &db2.scaler_mul(lr);
+        let lr = self.lr;
+        self.optimizer.begin_step();
+        let mut grads = grads.iter().zip(&sparse).enumerate();
+        for layer in self.layers.iter_mut() {
+            for (param, _) in layer.params_and_grads() {
+                let (index, (grad, rows)) = grads.next().unwrap();
+                self.optimizer
+                    .step_rows(index, param, grad, rows.as_deref(), lr);
+            }
+        }
     }
+    // one gradient step on a batch, returns the summed absolute error over the batch
+    pub fn train_batch(&mut self, x: &[Mat], y: &[Mat]) -> Mat {
+        assert_eq!(x.len(), y.len());
+        let (x, y) = (Mat::hstack(x), Mat::hstack(y));
+        let a = self.forward(&x);
+        let mut grad = self.loss.grad(&a, &y);
+        for layer in self.layers.iter_mut().rev() {
+            grad = layer.backward(&grad);
+        }
+        self.update_params();
+        (&a - &y).map(activations::abs).sum_columns()
+    }
+    // validation is an optional (x, y) pair scored after every epoch,
+    // early stopping (see set_early_stopping) or a callback can end training before `epochs`.
+    // every epoch runs x.len() / batch_size batches drawn without replacement
     pub fn train(
         &mut self,
-        x: &Vec<Mat>,
-        y: &Vec<Mat>,
+        x: &[Mat],
+        y: &[Mat],
+        validation: Option<(&[Mat], &[Mat])>,
         epochs: i32,
         batch_size: i32,
-        verbose: bool,
-    ) {
-        let mut epochs_done = 0;
-
-        for _ in 0..epochs {
+        callbacks: &mut [Box<dyn Callback>],
+    ) -> Vec<EpochMetrics> {
+        let mut loader = DataLoader::new(x, y, batch_size as usize)
+            .drop_last(true)
+            .seed(self.rng.gen());
+        self.train_loader(&mut loader, validation, epochs, callbacks)
+    }
+    // same as train but batches come from a data::loader::DataLoader,
+    // which handles shuffling and on the fly augmentation
+    pub fn train_loader(
+        &mut self,
+        loader: &mut DataLoader,
+        validation: Option<(&[Mat], &[Mat])>,
+        epochs: i32,
+        callbacks: &mut [Box<dyn Callback>],
+    ) -> Vec<EpochMetrics> {
+        let state = TrainState {
+            epoch: 0,
+            epochs,
+            step: 0,
+            stopped: false,
+            stopper: self.early_stopping.clone().map(EarlyStopper::new),
+            history: vec![],
+        };
+        self.run(loader, validation, state, callbacks)
+    }
+    // continue a run from a checkpoint written by set_checkpoint. loader, validation and
+    // callbacks have to be set up like in the original run, the loader's rng is restored
+    // from the checkpoint. returns the history of the whole run
+    pub fn train_resume(
+        &mut self,
+        checkpoint: Checkpoint,
+        loader: &mut DataLoader,
+        validation: Option<(&[Mat], &[Mat])>,
+        callbacks: &mut [Box<dyn Callback>],
+    ) -> Vec<EpochMetrics> {
+        assert_eq!(
+            checkpoint.callbacks.len(),
+            callbacks.len(),
+            "resume with the callbacks the checkpoint was written with"
+        );
+        self.set_weights(checkpoint.weights);
+        self.load_layer_states(checkpoint.layers);
+        self.lr = checkpoint.lr;
+        self.optimizer = checkpoint.optimizer;
+        self.rng = checkpoint.model_rng;
+        if checkpoint.config.is_some() {
+            self.config = checkpoint.config;
+        }
+        loader.set_rng(checkpoint.loader_rng);
+        for (callback, state) in callbacks.iter_mut().zip(checkpoint.callbacks) {
+            if let Some(state) = state {
+                callback.load_state(state);
+            }
+        }
+        let state = TrainState {
+            epoch: checkpoint.epoch,
+            epochs: checkpoint.epochs,
+            step: checkpoint.step,
+            stopped: checkpoint.stopped,
+            stopper: checkpoint.early_stopper,
+            history: checkpoint.history,
+        };
+        self.run(loader, validation, state, callbacks)
+    }
+    fn run(
+        &mut self,
+        loader: &mut DataLoader,
+        validation: Option<(&[Mat], &[Mat])>,
+        mut state: TrainState,
+        callbacks: &mut [Box<dyn Callback>],
+    ) -> Vec<EpochMetrics> {
+        // an epoch without batches would report a NaN loss
+        assert!(
+            !loader.is_empty(),
+            "not enough samples for a single batch ;("
+        );
+        self.train_mode();
+        for callback in callbacks.iter_mut() {
+            callback.on_train_begin(self);
+        }
+        while !state.stopped && state.epoch < state.epochs {
+            for callback in callbacks.iter_mut() {
+                callback.on_epoch_begin(self, state.epoch);
+            }
             let mut loss = Mat::new(self.output_shape, 1);
-
-            for _ in 0..x.len() / batch_size as usize {
-                let mut dw1 = Mat::zeroes_like(&self.w1);
-                let mut db1 = Mat::zeroes_like(&self.b1);
-                let mut dw2 = Mat::zeroes_like(&self.w2);
-                let mut db2 = Mat::zeroes_like(&self.b2);
-                let mut dw3 = Mat::zeroes_like(&self.w3);
-                let mut db3 = Mat::zeroes_like(&self.b3);
-                for _ in 0..batch_size {
-                    let index = rand::thread_rng().gen_range(0..x.len());
-
-                    let (z1, a1, z2, a2, z3, a3) = self.feed_forward(&x[index]);
-
-                    let (bdw1, bdb1, bdw2, bdb2, bdw3, bdb3) =
-                        self.back_prop((&z1, &a1, &z2, &a2, &z3, &a3, &x[index], &y[index]));
-                    dw1 = &bdw1 + &dw1;
-                    db1 = &bdb1 + &db1;
-
-                    dw2 = &bdw2 + &dw2;
-                    db2 = &bdb2 + &db2;
-
-                    dw3 = &bdw3 + &dw3;
-                    db3 = &bdb3 + &db3;
-                    loss = &loss + (&(&a3 - &y[index]).map(activations::abs));
+            let mut seen = 0;
+            for (bx, by) in loader.epoch() {
+                seen += bx.len();
+                let batch_loss = self.train_batch(&bx, &by);
+                state.step += 1;
+                let mean = batch_loss.sum_all() / (bx.len() * self.output_shape) as f64;
+                for callback in callbacks.iter_mut() {
+                    callback.on_batch_end(self, state.step, mean);
+                }
+                loss = &loss + &batch_loss;
+            }
+            let metrics = self.end_epoch(state.epoch, loss, seen, validation);
+            let mut stop = self.should_stop(&mut state.stopper, &metrics);
+            for callback in callbacks.iter_mut() {
+                stop |= callback.on_epoch_end(self, &metrics);
+            }
+            state.history.push(metrics);
+            state.epoch += 1;
+            state.stopped = stop;
+            if let Some(config) = &self.checkpoint {
+                if state.epoch % config.every == 0 {
+                    let path = config.path.replace("{epoch}", &state.epoch.to_string());
+                    self.snapshot(&state, loader, callbacks).save(&path);
                 }
-                dw1 = dw1.scaler_mul(1.0 / batch_size as f64);
-                db1 = db1.scaler_mul(1.0 / batch_size as f64);
-                dw2 = dw2.scaler_mul(1.0 / batch_size as f64);
-                db2 = db2.scaler_mul(1.0 / batch_size as f64);
-                self.update_param(&dw1, &db1, &dw2, &db2);
             }
-            loss = loss.scaler_mul(1.0 / x.len() as f64);
-            let avg_loss = loss.sum_all() / (loss.row() as f64 * loss.col() as f64);
-            if verbose {
-                println!("epoch:{} loss:{}", epochs_done, avg_loss);
+        }
+        self.restore_best(state.stopper);
+        for callback in callbacks.iter_mut() {
+            callback.on_train_end(self, &state.history);
+        }
+        state.history
+    }
+    fn snapshot(
+        &self,
+        state: &TrainState,
+        loader: &DataLoader,
+        callbacks: &[Box<dyn Callback>],
+    ) -> Checkpoint {
+        Checkpoint {
+            weights: self.weights(),
+            layers: self.layer_states(),
+            lr: self.lr,
+            optimizer: self.optimizer.clone(),
+            model_rng: self.rng.clone(),
+            loader_rng: loader.rng().clone(),
+            epoch: state.epoch,
+            epochs: state.epochs,
+            step: state.step,
+            stopped: state.stopped,
+            early_stopper: state.stopper.clone(),
+            history: state.history.clone(),
+            callbacks: callbacks.iter().map(|callback| callback.state()).collect(),
+            config: self.config.clone(),
+        }
+    }
+    // mean absolute error (the loss train reports) and accuracy over a dataset, in inference mode
+    pub fn loss_accuracy(&mut self, x: &[Mat], y: &[Mat]) -> (f64, f64) {
+        assert_eq!(x.len(), y.len());
+        let predictions = self.predict_all(x);
+        let mut loss = 0.0;
+        for (p, t) in predictions.iter().zip(y) {
+            let diff = (p - t).map(activations::abs);
+            loss += diff.sum_all() / (diff.row() as f64 * diff.col() as f64);
+        }
+        (loss / x.len() as f64, metrics::accuracy(&predictions, y))
+    }
+    fn end_epoch(
+        &mut self,
+        epochs_done: i32,
+        loss: Mat,
+        samples: usize,
+        validation: Option<(&[Mat], &[Mat])>,
+    ) -> EpochMetrics {
+        let loss = loss.scaler_mul(1.0 / samples as f64);
+        let avg_loss = loss.sum_all() / (loss.row() as f64 * loss.col() as f64);
+        let (val_loss, val_accuracy) = match validation {
+            Some((vx, vy)) => {
+                let (l, a) = self.loss_accuracy(vx, vy);
+                (Some(l), Some(a))
             }
-            epochs_done += 1;
+            None => (None, None),
+        };
+        EpochMetrics {
+            epoch: epochs_done,
+            loss: avg_loss,
+            val_loss,
+            val_accuracy,
+        }
+    }
+    fn should_stop(&self, stopper: &mut Option<EarlyStopper>, metrics: &EpochMetrics) -> bool {
+        match stopper {
+            Some(stopper) => stopper.update(metrics, || self.weights()),
+            None => false,
+        }
+    }
+    fn restore_best(&mut self, stopper: Option<EarlyStopper>) {
+        if let Some(weights) = stopper.and_then(EarlyStopper::into_best_weights) {
+            self.set_weights(weights);
         }
     }
 }
 
-pub fn parse_mnist(path: &String) -> (Vec<Mat>, Vec<Mat>) {
+// returns the raw pixel values (0..255), scaling is left to a fitted data::transform::Pipeline
+pub fn parse_mnist(path: &str) -> (Vec<Mat>, Vec<Mat>) {
     let file = File::open(path).expect("couldn't open file ;(");
 
     let mut rdr = csv::Reader::from_reader(file);
@@ -168,16 +542,12 @@ pub fn parse_mnist(path: &String) -> (Vec<Mat>, Vec<Mat>) {
         let index = record.iter().next().unwrap().parse::<usize>().unwrap();
         label[(index, 0)] = 1.0;
         y.push(label);
-        let mut buffer = Vec::<f64>::new();
-        buffer.reserve(row * col);
+        let mut buffer = Vec::<f64>::with_capacity(row * col);
 
         for ele in record.iter().skip(1) {
             buffer.push(ele.parse::<f64>().unwrap());
         }
-        let mut ele = Mat::from_vec(buffer, col * row, 1);
-        ele.normalize_self();
-        ele.scaler_mul(0.9);
-        x.push(ele);
+        x.push(Mat::from_vec(buffer, col * row, 1));
     }
     (x, y)
 }
@@ -0,0 +1,551 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::nn::early_stopping::Monitor;
+use crate::nn::{EpochMetrics, NN};
+
+// Hooks train calls while it runs, every method has an empty default so a callback
+// only implements what it needs. step counts batches since the start of training.
+pub trait Callback {
+    fn on_train_begin(&mut self, _model: &mut NN) {}
+    fn on_train_end(&mut self, _model: &mut NN, _history: &[EpochMetrics]) {}
+    fn on_epoch_begin(&mut self, _model: &mut NN, _epoch: i32) {}
+    // returning true stops training after this epoch
+    fn on_epoch_end(&mut self, _model: &mut NN, _metrics: &EpochMetrics) -> bool {
+        false
+    }
+    // loss is the mean absolute error of this batch
+    fn on_batch_end(&mut self, _model: &mut NN, _step: usize, _loss: f64) {}
+    // anything that has to survive a training checkpoint. when resuming, load_state
+    // is called with the saved value before on_train_begin
+    fn state(&self) -> Option<serde_json::Value> {
+        None
+    }
+    fn load_state(&mut self, _state: serde_json::Value) {}
+}
+
+// prints the epoch summary train used to print with verbose on,
+// and a running loss and pre clipping gradient norm every `every` batches when every > 0
+pub struct ProgressPrinter {
+    every: usize,
+}
+
+impl ProgressPrinter {
+    pub fn new() -> ProgressPrinter {
+        ProgressPrinter { every: 0 }
+    }
+    pub fn every(every: usize) -> ProgressPrinter {
+        ProgressPrinter { every }
+    }
+}
+
+impl Default for ProgressPrinter {
+    fn default() -> Self {
+        ProgressPrinter::new()
+    }
+}
+
+impl Callback for ProgressPrinter {
+    fn on_epoch_end(&mut self, _model: &mut NN, metrics: &EpochMetrics) -> bool {
+        match (metrics.val_loss, metrics.val_accuracy) {
+            (Some(l), Some(a)) => println!(
+                "epoch:{} loss:{} val_loss:{} val_accuracy:{}",
+                metrics.epoch, metrics.loss, l, a
+            ),
+            _ => println!("epoch:{} loss:{}", metrics.epoch, metrics.loss),
+        }
+        false
+    }
+    fn on_batch_end(&mut self, model: &mut NN, step: usize, loss: f64) {
+        if self.every > 0 && step.is_multiple_of(self.every) {
+            let grad_norm = model.grad_norm().unwrap_or(0.0);
+            println!("step:{} loss:{} grad_norm:{}", step, loss, grad_norm);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    Csv,
+    JsonLines,
+}
+
+// appends one row per epoch (epoch, loss, val_loss, val_accuracy, lr) to a file, and
+// optionally one row per `every` batches (step, epoch, loss, grad_norm, lr) to a second one.
+// the files are truncated when training starts, unless the run is resumed from a checkpoint
+pub struct MetricsLogger {
+    path: String,
+    format: LogFormat,
+    writer: Option<BufWriter<File>>,
+    steps: Option<(String, usize)>,
+    step_writer: Option<BufWriter<File>>,
+    epoch: i32,
+    append: bool,
+}
+
+impl MetricsLogger {
+    pub fn new(path: &str, format: LogFormat) -> MetricsLogger {
+        MetricsLogger {
+            path: path.to_string(),
+            format,
+            writer: None,
+            steps: None,
+            step_writer: None,
+            epoch: 0,
+            append: false,
+        }
+    }
+    // also log every `every` batches to path, in the same format
+    pub fn steps(mut self, path: &str, every: usize) -> Self {
+        assert!(every > 0, "steps are logged every 1 or more batches");
+        self.steps = Some((path.to_string(), every));
+        self
+    }
+    fn open(&self, path: &str, csv_header: &str) -> BufWriter<File> {
+        let file = if self.append {
+            OpenOptions::new().append(true).create(true).open(path)
+        } else {
+            File::create(path)
+        }
+        .expect("couldn't create metrics log ;(");
+        let mut writer = BufWriter::new(file);
+        if self.format == LogFormat::Csv && !self.append {
+            writeln!(writer, "{}", csv_header).expect("couldn't write metrics log ;(");
+        }
+        writer
+    }
+}
+
+fn optional(v: Option<f64>) -> String {
+    v.map(|v| v.to_string()).unwrap_or_default()
+}
+
+impl Callback for MetricsLogger {
+    fn on_train_begin(&mut self, _model: &mut NN) {
+        self.writer = Some(self.open(&self.path, "epoch,loss,val_loss,val_accuracy,lr"));
+        if let Some((path, _)) = &self.steps {
+            self.step_writer = Some(self.open(path, "step,epoch,loss,grad_norm,lr"));
+        }
+    }
+    fn on_epoch_begin(&mut self, _model: &mut NN, epoch: i32) {
+        self.epoch = epoch;
+    }
+    fn on_batch_end(&mut self, model: &mut NN, step: usize, loss: f64) {
+        let (Some((_, every)), Some(writer)) = (&self.steps, self.step_writer.as_mut()) else {
+            return;
+        };
+        if !step.is_multiple_of(*every) {
+            return;
+        }
+        let grad_norm = model.grad_norm();
+        match self.format {
+            LogFormat::Csv => writeln!(
+                writer,
+                "{},{},{},{},{}",
+                step,
+                self.epoch,
+                loss,
+                optional(grad_norm),
+                model.lr()
+            ),
+            LogFormat::JsonLines => {
+                let row = serde_json::json!({
+                    "step": step,
+                    "epoch": self.epoch,
+                    "loss": loss,
+                    "grad_norm": grad_norm,
+                    "lr": model.lr(),
+                });
+                writeln!(writer, "{}", row)
+            }
+        }
+        .expect("couldn't write metrics log ;(");
+    }
+    fn on_epoch_end(&mut self, model: &mut NN, metrics: &EpochMetrics) -> bool {
+        let writer = self
+            .writer
+            .as_mut()
+            .expect("metrics logger used outside of train");
+        match self.format {
+            LogFormat::Csv => writeln!(
+                writer,
+                "{},{},{},{},{}",
+                metrics.epoch,
+                metrics.loss,
+                optional(metrics.val_loss),
+                optional(metrics.val_accuracy),
+                model.lr()
+            ),
+            LogFormat::JsonLines => {
+                let row = serde_json::json!({
+                    "epoch": metrics.epoch,
+                    "loss": metrics.loss,
+                    "val_loss": metrics.val_loss,
+                    "val_accuracy": metrics.val_accuracy,
+                    "lr": model.lr(),
+                });
+                writeln!(writer, "{}", row)
+            }
+        }
+        .expect("couldn't write metrics log ;(");
+        // flushed every epoch so the logs can be followed while training runs
+        writer.flush().expect("couldn't write metrics log ;(");
+        if let Some(writer) = self.step_writer.as_mut() {
+            writer.flush().expect("couldn't write metrics log ;(");
+        }
+        false
+    }
+    fn on_train_end(&mut self, _model: &mut NN, _history: &[EpochMetrics]) {
+        self.writer = None;
+        self.step_writer = None;
+        self.append = false;
+    }
+    // only marks that the logs already have the earlier epochs in them
+    fn state(&self) -> Option<serde_json::Value> {
+        Some(serde_json::Value::Bool(true))
+    }
+    fn load_state(&mut self, _state: serde_json::Value) {
+        self.append = true;
+    }
+}
+
+// saves the weights with NN::save_weights after every epoch, or only when the monitored
+// value improved. "{epoch}" in the path is replaced with the epoch number
+pub struct ModelCheckpoint {
+    path: String,
+    monitor: Option<Monitor>,
+    best: Option<f64>,
+}
+
+impl ModelCheckpoint {
+    pub fn every_epoch(path: &str) -> ModelCheckpoint {
+        ModelCheckpoint {
+            path: path.to_string(),
+            monitor: None,
+            best: None,
+        }
+    }
+    pub fn best_only(path: &str, monitor: Monitor) -> ModelCheckpoint {
+        ModelCheckpoint {
+            path: path.to_string(),
+            monitor: Some(monitor),
+            best: None,
+        }
+    }
+}
+
+impl Callback for ModelCheckpoint {
+    fn on_epoch_end(&mut self, model: &mut NN, metrics: &EpochMetrics) -> bool {
+        if let Some(monitor) = self.monitor {
+            let score = monitor.score(metrics);
+            if self.best.is_some_and(|best| score >= best) {
+                return false;
+            }
+            self.best = Some(score);
+        }
+        model.save_weights(&self.path.replace("{epoch}", &metrics.epoch.to_string()));
+        false
+    }
+    fn on_train_end(&mut self, _model: &mut NN, _history: &[EpochMetrics]) {
+        self.best = None;
+    }
+    fn state(&self) -> Option<serde_json::Value> {
+        Some(serde_json::json!(self.best))
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        self.best = serde_json::from_value(state).expect("bad model checkpoint state");
+    }
+}
+
+// learning rate as a function of the epoch, relative to the lr the model had when training began
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Schedule {
+    // multiply by gamma every `every` epochs
+    Step { every: i32, gamma: f64 },
+    // multiply by gamma every epoch
+    Exponential { gamma: f64 },
+    // cosine anneal from the base lr down to min_lr over `epochs` epochs
+    Cosine { epochs: i32, min_lr: f64 },
+    // ramp linearly from 0 up to the base lr over `epochs` epochs
+    Warmup { epochs: i32 },
+}
+
+impl Schedule {
+    // rejects lengths that would divide by zero
+    pub fn validate(&self) {
+        match *self {
+            Schedule::Step { every, .. } => {
+                assert!(every > 0, "step schedule needs every > 0 ;(")
+            }
+            Schedule::Cosine { epochs, .. } | Schedule::Warmup { epochs } => {
+                assert!(epochs > 0, "schedule needs epochs > 0 ;(")
+            }
+            Schedule::Exponential { .. } => {}
+        }
+    }
+    pub fn lr(&self, base_lr: f64, epoch: i32) -> f64 {
+        match self {
+            Schedule::Step { every, gamma } => base_lr * libm::pow(*gamma, (epoch / every) as f64),
+            Schedule::Exponential { gamma } => base_lr * libm::pow(*gamma, epoch as f64),
+            Schedule::Cosine { epochs, min_lr } => {
+                let t = (epoch.min(*epochs) as f64) / (*epochs as f64);
+                min_lr + 0.5 * (base_lr - min_lr) * (1.0 + libm::cos(std::f64::consts::PI * t))
+            }
+            Schedule::Warmup { epochs } => {
+                base_lr * ((epoch + 1).min(*epochs) as f64) / (*epochs as f64)
+            }
+        }
+    }
+}
+
+// sets the model's lr from a Schedule at the start of every epoch
+pub struct LrScheduler {
+    schedule: Schedule,
+    base_lr: Option<f64>,
+}
+
+impl LrScheduler {
+    pub fn new(schedule: Schedule) -> LrScheduler {
+        schedule.validate();
+        LrScheduler {
+            schedule,
+            base_lr: None,
+        }
+    }
+}
+
+impl Callback for LrScheduler {
+    fn on_train_begin(&mut self, model: &mut NN) {
+        // already set when resuming, the model's lr is the scheduled one by then
+        if self.base_lr.is_none() {
+            self.base_lr = Some(model.lr());
+        }
+    }
+    fn on_epoch_begin(&mut self, model: &mut NN, epoch: i32) {
+        let base_lr = self.base_lr.expect("lr scheduler used outside of train");
+        model.set_lr(self.schedule.lr(base_lr, epoch));
+    }
+    fn on_train_end(&mut self, _model: &mut NN, _history: &[EpochMetrics]) {
+        self.base_lr = None;
+    }
+    fn state(&self) -> Option<serde_json::Value> {
+        Some(serde_json::json!(self.base_lr))
+    }
+    fn load_state(&mut self, state: serde_json::Value) {
+        self.base_lr = serde_json::from_value(state).expect("bad lr scheduler state");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::nn::callbacks::*;
+    use crate::tools::matrix::*;
+
+    fn epoch(epoch: i32, val_loss: f64) -> EpochMetrics {
+        EpochMetrics {
+            epoch,
+            loss: 1.0,
+            val_loss: Some(val_loss),
+            val_accuracy: Some(0.5),
+        }
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-12
+    }
+
+    #[test]
+    fn schedules() {
+        let step = Schedule::Step {
+            every: 2,
+            gamma: 0.5,
+        };
+        let lrs: Vec<f64> = (0..5).map(|e| step.lr(1.0, e)).collect();
+        assert_eq!(lrs, vec![1.0, 1.0, 0.5, 0.5, 0.25]);
+
+        let exponential = Schedule::Exponential { gamma: 0.9 };
+        assert!(close(exponential.lr(2.0, 2), 2.0 * 0.81));
+
+        let cosine = Schedule::Cosine {
+            epochs: 4,
+            min_lr: 0.1,
+        };
+        assert!(close(cosine.lr(1.0, 0), 1.0));
+        assert!(close(cosine.lr(1.0, 2), 0.55));
+        // stays at min_lr after the anneal
+        assert!(close(cosine.lr(1.0, 4), 0.1));
+        assert!(close(cosine.lr(1.0, 9), 0.1));
+
+        let warmup = Schedule::Warmup { epochs: 4 };
+        for (e, lr) in [0.2, 0.4, 0.6, 0.8, 0.8, 0.8].iter().enumerate() {
+            assert!(close(warmup.lr(0.8, e as i32), *lr));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "step schedule needs every > 0")]
+    fn step_every_zero_is_rejected() {
+        LrScheduler::new(Schedule::Step {
+            every: 0,
+            gamma: 0.5,
+        });
+    }
+
+    #[test]
+    fn zero_length_schedules_are_rejected() {
+        for schedule in [
+            Schedule::Cosine {
+                epochs: 0,
+                min_lr: 0.0,
+            },
+            Schedule::Warmup { epochs: 0 },
+            Schedule::Warmup { epochs: -1 },
+        ] {
+            assert!(std::panic::catch_unwind(|| schedule.validate()).is_err());
+        }
+        Schedule::Exponential { gamma: 0.5 }.validate();
+    }
+
+    #[test]
+    fn lr_scheduler_follows_the_base_lr() {
+        let mut nn = NN::new(2, 3, 2);
+        nn.set_lr(0.4);
+        let mut scheduler = LrScheduler::new(Schedule::Step {
+            every: 1,
+            gamma: 0.5,
+        });
+        scheduler.on_train_begin(&mut nn);
+        scheduler.on_epoch_begin(&mut nn, 0);
+        assert_eq!(nn.lr(), 0.4);
+        scheduler.on_epoch_begin(&mut nn, 2);
+        assert_eq!(nn.lr(), 0.1);
+
+        // a resumed scheduler keeps the original base instead of the decayed lr
+        let state = scheduler.state().unwrap();
+        let mut resumed = LrScheduler::new(Schedule::Step {
+            every: 1,
+            gamma: 0.5,
+        });
+        resumed.load_state(state);
+        resumed.on_train_begin(&mut nn);
+        resumed.on_epoch_begin(&mut nn, 3);
+        assert_eq!(nn.lr(), 0.05);
+
+        // the next train call starts from whatever lr the model has then
+        scheduler.on_train_end(&mut nn, &[]);
+        scheduler.on_train_begin(&mut nn);
+        scheduler.on_epoch_begin(&mut nn, 1);
+        assert_eq!(nn.lr(), 0.025);
+    }
+
+    #[test]
+    fn model_checkpoint_writes_every_epoch_or_the_best() {
+        let dir = std::env::temp_dir().join(format!("kek_model_checkpoint_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let every = dir.join("every_{epoch}.json");
+        let best = dir.join("best_{epoch}.json");
+        let mut nn = NN::new(2, 3, 2);
+
+        let mut every = ModelCheckpoint::every_epoch(every.to_str().unwrap());
+        let mut best = ModelCheckpoint::best_only(best.to_str().unwrap(), Monitor::ValLoss);
+        for (i, loss) in [0.5, 0.4, 0.45, 0.3].iter().enumerate() {
+            let metrics = epoch(i as i32, *loss);
+            assert!(!every.on_epoch_end(&mut nn, &metrics));
+            assert!(!best.on_epoch_end(&mut nn, &metrics));
+        }
+        assert_eq!(best.state(), Some(serde_json::json!(0.3)));
+
+        let mut files: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        let mut loaded = NN::new(2, 3, 2);
+        loaded.load_weights(dir.join("best_3.json").to_str().unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            files,
+            vec![
+                "best_0.json",
+                "best_1.json",
+                "best_3.json",
+                "every_0.json",
+                "every_1.json",
+                "every_2.json",
+                "every_3.json"
+            ]
+        );
+        assert_eq!(loaded.weights()[0].as_slice(), nn.weights()[0].as_slice());
+    }
+
+    // trains NN::new(2, 3, 2) for 2 epochs of 2 batches with the logger, steps every 2 batches
+    fn log_steps(format: LogFormat, ext: &str) -> (Vec<String>, Vec<String>, f64) {
+        let dir = std::env::temp_dir();
+        let name = format!("kek_metrics_{}_{}", ext, std::process::id());
+        let epochs = dir.join(format!("{name}.{ext}"));
+        let steps = dir.join(format!("{name}_steps.{ext}"));
+        let (epochs, steps) = (epochs.to_str().unwrap(), steps.to_str().unwrap());
+        let x: Vec<Mat> = (0..8)
+            .map(|i| Mat::from_vec(vec![i as f64 / 8.0, 1.0 - i as f64 / 8.0], 2, 1))
+            .collect();
+        let y: Vec<Mat> = (0..8)
+            .map(|i| Mat::from_vec(vec![(i < 4) as u8 as f64, (i >= 4) as u8 as f64], 2, 1))
+            .collect();
+        let mut nn = NN::new(2, 3, 2);
+        let logger = MetricsLogger::new(epochs, format).steps(steps, 2);
+        let mut callbacks: Vec<Box<dyn Callback>> = vec![Box::new(logger)];
+        nn.train(&x, &y, None, 2, 4, &mut callbacks);
+        let read = |path: &str| {
+            let text = fs::read_to_string(path).unwrap();
+            fs::remove_file(path).unwrap();
+            text.lines().map(String::from).collect()
+        };
+        (read(epochs), read(steps), nn.lr())
+    }
+
+    #[test]
+    fn metrics_logger_writes_csv_rows_per_epoch_and_step() {
+        let (epochs, steps, lr) = log_steps(LogFormat::Csv, "csv");
+        assert_eq!(epochs.len(), 3);
+        assert_eq!(epochs[0], "epoch,loss,val_loss,val_accuracy,lr");
+        assert!(epochs[2].starts_with("1,"));
+        assert!(epochs[2].ends_with(&format!(",,,{lr}")));
+
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps[0], "step,epoch,loss,grad_norm,lr");
+        for (row, (step, epoch)) in steps[1..].iter().zip([(2, 0), (4, 1)]) {
+            let fields: Vec<&str> = row.split(',').collect();
+            assert_eq!(fields.len(), 5);
+            assert_eq!(fields[0], step.to_string());
+            assert_eq!(fields[1], epoch.to_string());
+            assert!(fields[2].parse::<f64>().unwrap() >= 0.0);
+            assert!(fields[3].parse::<f64>().unwrap() > 0.0);
+            assert_eq!(fields[4], lr.to_string());
+        }
+    }
+
+    #[test]
+    fn metrics_logger_writes_json_lines_per_epoch_and_step() {
+        let (epochs, steps, lr) = log_steps(LogFormat::JsonLines, "jsonl");
+        assert_eq!(epochs.len(), 2);
+        let last: serde_json::Value = serde_json::from_str(&epochs[1]).unwrap();
+        assert_eq!(last["epoch"], 1);
+        assert!(last["val_loss"].is_null());
+        assert_eq!(last["lr"], lr);
+
+        assert_eq!(steps.len(), 2);
+        for (row, (step, epoch)) in steps.iter().zip([(2, 0), (4, 1)]) {
+            let row: serde_json::Value = serde_json::from_str(row).unwrap();
+            assert_eq!(row["step"], step);
+            assert_eq!(row["epoch"], epoch);
+            assert!(row["loss"].is_f64());
+            assert!(row["grad_norm"].as_f64().unwrap() > 0.0);
+            assert_eq!(row["lr"], lr);
+        }
+    }
+}
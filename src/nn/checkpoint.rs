@@ -0,0 +1,216 @@
+use std::fs::File;
+
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::nn::early_stopping::EarlyStopper;
+use crate::nn::optim::Optimizer;
+use crate::nn::EpochMetrics;
+use crate::tools::matrix::*;
+
+// where and how often train writes checkpoints, "{epoch}" in path is replaced
+// with the number of finished epochs
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CheckpointConfig {
+    pub path: String,
+    pub every: i32,
+}
+
+impl CheckpointConfig {
+    pub fn new(path: &str, every: i32) -> CheckpointConfig {
+        assert!(every > 0);
+        CheckpointConfig {
+            path: path.to_string(),
+            every,
+        }
+    }
+}
+
+// Everything a run needs to continue as if it was never interrupted, written at the end
+// of an epoch. feed it to NN::train_resume together with the same data and callbacks.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub weights: Vec<Mat>,
+    // Layer::state of every layer
+    pub layers: Vec<Option<serde_json::Value>>,
+    pub lr: f64,
+    pub optimizer: Optimizer,
+    pub model_rng: ChaCha8Rng,
+    pub loader_rng: ChaCha8Rng,
+    // next epoch to run and the total the run was started with
+    pub epoch: i32,
+    pub epochs: i32,
+    // batches done so far
+    pub step: usize,
+    // early stopping or a callback ended the run, resuming doesn't train any further
+    #[serde(default)]
+    pub stopped: bool,
+    pub early_stopper: Option<EarlyStopper>,
+    pub history: Vec<EpochMetrics>,
+    // Callback::state of every callback, in the order they were passed to train
+    pub callbacks: Vec<Option<serde_json::Value>>,
+    // resolved config of the model, when it was built from one
+    #[serde(default)]
+    pub config: Option<Config>,
+}
+
+impl Checkpoint {
+    pub fn save(&self, path: &str) {
+        let file = File::create(path).expect("couldn't create checkpoint file ;(");
+        serde_json::to_writer(file, self).expect("couldn't write checkpoint file ;(");
+    }
+    pub fn load(path: &str) -> Checkpoint {
+        let file = File::open(path).expect("couldn't open checkpoint file ;(");
+        serde_json::from_reader(file).expect("couldn't parse checkpoint file ;(")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::data::loader::DataLoader;
+    use crate::nn::callbacks::{Callback, LrScheduler, Schedule};
+    use crate::nn::checkpoint::{Checkpoint, CheckpointConfig};
+    use crate::nn::early_stopping::{EarlyStopping, Monitor};
+    use crate::nn::optim::Optimizer;
+    use crate::nn::{EpochMetrics, NN};
+    use crate::tools::matrix::*;
+
+    // ends the run after `epoch`, the checkpoints written before that are what a process
+    // killed at that point would leave behind
+    struct StopAfter(Option<i32>);
+
+    impl Callback for StopAfter {
+        fn on_epoch_end(&mut self, _model: &mut NN, metrics: &EpochMetrics) -> bool {
+            self.0 == Some(metrics.epoch)
+        }
+    }
+
+    fn dataset(len: usize) -> (Vec<Mat>, Vec<Mat>) {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let x: Vec<Mat> = (0..len)
+            .map(|_| Mat::from_vec((0..4).map(|_| rng.gen_range(-1.0..1.0)).collect(), 4, 1))
+            .collect();
+        let y = x
+            .iter()
+            .map(|xi| {
+                let mut label = Mat::new(2, 1);
+                label[(((xi[(0, 0)] + xi[(1, 0)]) > 0.0) as usize, 0)] = 1.0;
+                label
+            })
+            .collect();
+        (x, y)
+    }
+
+    fn model(weights: &[Mat]) -> NN {
+        let mut nn = NN::new(4, 8, 2);
+        nn.set_weights(weights.to_vec());
+        nn.set_lr(0.05);
+        nn.set_seed(11);
+        nn.set_optimizer(Optimizer::adam(0.9, 0.999, 1e-8));
+        nn.set_early_stopping(Some(EarlyStopping::new(Monitor::ValLoss, 100, 0.0)));
+        nn
+    }
+
+    fn callbacks(stop: Option<i32>) -> Vec<Box<dyn Callback>> {
+        vec![
+            Box::new(LrScheduler::new(Schedule::Step {
+                every: 2,
+                gamma: 0.5,
+            })),
+            Box::new(StopAfter(stop)),
+        ]
+    }
+
+    fn bits(weights: &[Mat]) -> Vec<u64> {
+        weights
+            .iter()
+            .flat_map(|w| w.as_slice().iter().map(|v| v.to_bits()))
+            .collect()
+    }
+
+    #[test]
+    fn resumed_run_matches_uninterrupted_run() {
+        let (x, y) = dataset(120);
+        let (vx, vy) = dataset(30);
+        let init = NN::new(4, 8, 2).weights();
+        let epochs = 6;
+
+        let mut reference = model(&init);
+        let mut loader = DataLoader::new(&x, &y, 8).seed(5);
+        let expected =
+            reference.train_loader(&mut loader, Some((&vx, &vy)), epochs, &mut callbacks(None));
+
+        let dir = std::env::temp_dir().join(format!("kek_resume_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("checkpoint_{epoch}.json");
+
+        let mut interrupted = model(&init);
+        interrupted.set_checkpoint(Some(CheckpointConfig::new(path.to_str().unwrap(), 1)));
+        let mut loader = DataLoader::new(&x, &y, 8).seed(5);
+        let partial = interrupted.train_loader(
+            &mut loader,
+            Some((&vx, &vy)),
+            epochs,
+            &mut callbacks(Some(2)),
+        );
+        assert_eq!(partial.len(), 3);
+
+        // the last checkpoint records the stop, resume from the one before it.
+        // fresh process: different initial weights and loader seed, everything comes from the file
+        assert!(Checkpoint::load(dir.join("checkpoint_3.json").to_str().unwrap()).stopped);
+        let checkpoint = Checkpoint::load(dir.join("checkpoint_2.json").to_str().unwrap());
+        assert!(!checkpoint.stopped);
+        let mut resumed = model(&NN::new(4, 8, 2).weights());
+        let mut loader = DataLoader::new(&x, &y, 8).seed(99);
+        let history = resumed.train_resume(
+            checkpoint,
+            &mut loader,
+            Some((&vx, &vy)),
+            &mut callbacks(None),
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(history, expected);
+        assert_eq!(bits(&resumed.weights()), bits(&reference.weights()));
+        assert_eq!(resumed.lr().to_bits(), reference.lr().to_bits());
+        assert_eq!(resumed.optimizer().steps(), reference.optimizer().steps());
+    }
+
+    #[test]
+    fn resuming_a_stopped_run_doesnt_train() {
+        let (x, y) = dataset(60);
+        let (vx, vy) = dataset(20);
+        let init = NN::new(4, 8, 2).weights();
+        let dir = std::env::temp_dir().join(format!("kek_stopped_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("checkpoint_{epoch}.json");
+
+        let mut stopped = model(&init);
+        stopped.set_checkpoint(Some(CheckpointConfig::new(path.to_str().unwrap(), 1)));
+        let mut loader = DataLoader::new(&x, &y, 8).seed(5);
+        let history =
+            stopped.train_loader(&mut loader, Some((&vx, &vy)), 10, &mut callbacks(Some(1)));
+        assert_eq!(history.len(), 2);
+
+        let checkpoint = Checkpoint::load(dir.join("checkpoint_2.json").to_str().unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(checkpoint.stopped);
+        assert_eq!(checkpoint.optimizer.steps(), stopped.optimizer().steps());
+
+        let mut resumed = model(&init);
+        let mut loader = DataLoader::new(&x, &y, 8).seed(5);
+        let resumed_history = resumed.train_resume(
+            checkpoint,
+            &mut loader,
+            Some((&vx, &vy)),
+            &mut callbacks(None),
+        );
+        assert_eq!(resumed_history, history);
+        assert_eq!(resumed.optimizer().steps(), stopped.optimizer().steps());
+        assert_eq!(bits(&resumed.weights()), bits(&stopped.weights()));
+    }
+}
@@ -0,0 +1,173 @@
+use serde::{Deserialize, Serialize};
+
+use crate::nn::EpochMetrics;
+use crate::tools::matrix::*;
+
+// quantity early stopping watches, the val_ ones need a validation set passed to train
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Monitor {
+    Loss,
+    ValLoss,
+    ValAccuracy,
+}
+
+impl Monitor {
+    // the monitored value of an epoch, flipped where needed so that lower is always better
+    pub fn score(&self, metrics: &EpochMetrics) -> f64 {
+        let missing = "early stopping on a validation metric needs a validation set";
+        match self {
+            Monitor::Loss => metrics.loss,
+            Monitor::ValLoss => metrics.val_loss.expect(missing),
+            Monitor::ValAccuracy => -metrics.val_accuracy.expect(missing),
+        }
+    }
+}
+
+// Stop training once the monitored value hasn't improved by more than min_delta
+// for `patience` epochs in a row, optionally rolling back to the best epoch's weights.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EarlyStopping {
+    pub monitor: Monitor,
+    pub patience: usize,
+    pub min_delta: f64,
+    pub restore_best: bool,
+}
+
+impl EarlyStopping {
+    pub fn new(monitor: Monitor, patience: usize, min_delta: f64) -> EarlyStopping {
+        EarlyStopping {
+            monitor,
+            patience,
+            min_delta,
+            restore_best: true,
+        }
+    }
+}
+
+// running state of one train call, part of a training checkpoint
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EarlyStopper {
+    config: EarlyStopping,
+    best_score: Option<f64>,
+    best_epoch: Option<i32>,
+    best_weights: Option<Vec<Mat>>,
+    bad_epochs: usize,
+}
+
+impl EarlyStopper {
+    pub fn new(config: EarlyStopping) -> EarlyStopper {
+        EarlyStopper {
+            config,
+            best_score: None,
+            best_epoch: None,
+            best_weights: None,
+            bad_epochs: 0,
+        }
+    }
+    // record an epoch, weights is only called when this epoch is the new best.
+    // returns true when training should stop
+    pub fn update<F: FnOnce() -> Vec<Mat>>(&mut self, metrics: &EpochMetrics, weights: F) -> bool {
+        let score = self.config.monitor.score(metrics);
+        let improved = match self.best_score {
+            Some(best) => score < best - self.config.min_delta,
+            None => true,
+        };
+        if improved {
+            self.best_score = Some(score);
+            self.best_epoch = Some(metrics.epoch);
+            self.bad_epochs = 0;
+            if self.config.restore_best {
+                self.best_weights = Some(weights());
+            }
+            return false;
+        }
+        self.bad_epochs += 1;
+        self.bad_epochs >= self.config.patience
+    }
+    pub fn best_epoch(&self) -> Option<i32> {
+        self.best_epoch
+    }
+    // weights to roll back to at the end of training, if restore_best is on
+    pub fn into_best_weights(self) -> Option<Vec<Mat>> {
+        self.best_weights
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nn::early_stopping::*;
+    use crate::nn::NN;
+
+    fn epoch(epoch: i32, val_loss: f64) -> EpochMetrics {
+        EpochMetrics {
+            epoch,
+            loss: 1.0,
+            val_loss: Some(val_loss),
+            val_accuracy: Some(1.0 - val_loss),
+        }
+    }
+
+    // feeds val losses until the stopper asks to stop, returns the epoch it stopped at
+    fn run(stopper: &mut EarlyStopper, losses: &[f64]) -> Option<i32> {
+        for (i, loss) in losses.iter().enumerate() {
+            let weights = || vec![Mat::from_vec(vec![i as f64], 1, 1)];
+            if stopper.update(&epoch(i as i32, *loss), weights) {
+                return Some(i as i32);
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn stops_after_patience_bad_epochs() {
+        let config = EarlyStopping::new(Monitor::ValLoss, 2, 0.0);
+        let mut stopper = EarlyStopper::new(config.clone());
+        // epoch 2 is the best, 3 and 4 are the two bad epochs
+        assert_eq!(run(&mut stopper, &[0.9, 0.8, 0.7, 0.75, 0.7, 0.1]), Some(4));
+        assert_eq!(stopper.best_epoch(), Some(2));
+
+        // an improvement resets the count
+        let mut stopper = EarlyStopper::new(config);
+        assert_eq!(run(&mut stopper, &[0.9, 1.0, 0.8, 1.0, 0.7, 1.0]), None);
+    }
+
+    #[test]
+    fn improvements_under_min_delta_dont_count() {
+        let mut stopper = EarlyStopper::new(EarlyStopping::new(Monitor::ValLoss, 2, 0.05));
+        assert_eq!(run(&mut stopper, &[1.0, 0.97, 0.96, 0.5]), Some(2));
+        assert_eq!(stopper.best_epoch(), Some(0));
+    }
+
+    #[test]
+    fn accuracy_is_maximized() {
+        let mut stopper = EarlyStopper::new(EarlyStopping::new(Monitor::ValAccuracy, 1, 0.0));
+        // val_accuracy is 1 - val_loss, so 0.2 is the best accuracy
+        assert_eq!(run(&mut stopper, &[0.5, 0.2, 0.3]), Some(2));
+        assert_eq!(stopper.best_epoch(), Some(1));
+    }
+
+    #[test]
+    fn restore_best_keeps_the_best_weights() {
+        let mut stopper = EarlyStopper::new(EarlyStopping::new(Monitor::ValLoss, 3, 0.0));
+        run(&mut stopper, &[0.9, 0.4, 0.6, 0.5, 0.7]);
+        let best = stopper.into_best_weights().unwrap();
+        assert_eq!(best[0].as_slice(), &[1.0]);
+
+        let mut config = EarlyStopping::new(Monitor::ValLoss, 3, 0.0);
+        config.restore_best = false;
+        let mut stopper = EarlyStopper::new(config);
+        run(&mut stopper, &[0.9, 0.4, 0.6]);
+        assert!(stopper.into_best_weights().is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "not enough samples for a single batch")]
+    fn training_without_a_full_batch_panics() {
+        // drop_last leaves no batch, the epoch loss would be NaN
+        let x = vec![Mat::new(2, 1); 3];
+        let y = vec![Mat::new(2, 1); 3];
+        let mut nn = NN::new(2, 3, 2);
+        nn.set_early_stopping(Some(EarlyStopping::new(Monitor::Loss, 1, 0.0)));
+        nn.train(&x, &y, None, 2, 4, &mut []);
+    }
+}
@@ -0,0 +1,332 @@
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::layers::Layer;
+use crate::nn::NN;
+use crate::tools::matrix::*;
+
+// handed to Layer::seed before every forward pass, so dropout masks and other randomness
+// are the same in the analytic and all the numeric passes
+const SEED: u64 = 7;
+
+// floor of the relative error's denominator. central differences carry around 1e-11 of
+// rounding noise, gradients that should be 0 (like the key bias of attention) would
+// otherwise come out as completely wrong
+const EPS: f64 = 1e-4;
+
+// Compares the gradients backward computes against central finite differences
+// (f(v + h) - f(v - h)) / 2h of every input element and every parameter element.
+// forward runs in training mode, batch norm only uses batch statistics with more than one sample
+#[derive(Clone, Debug)]
+pub struct GradCheck {
+    step: f64,
+    input: bool,
+}
+
+// largest relative error |numeric - analytic| / max(|numeric| + |analytic|, EPS) per tensor
+#[derive(Clone, Debug, PartialEq)]
+pub struct GradReport {
+    // None when the input wasn't checked
+    pub input: Option<f64>,
+    // same order as Layer::params, for a model every layer's params in turn (NN::weights
+    // without the batch norm buffers)
+    pub params: Vec<f64>,
+}
+
+impl GradReport {
+    pub fn worst(&self) -> f64 {
+        self.params
+            .iter()
+            .chain(&self.input)
+            .fold(0.0, |worst, &err| f64::max(worst, err))
+    }
+    pub fn assert_below(&self, tolerance: f64) {
+        if let Some(err) = self.input {
+            assert!(err < tolerance, "input gradient off by {err}");
+        }
+        for (p, err) in self.params.iter().enumerate() {
+            assert!(*err < tolerance, "gradient of param {p} off by {err}");
+        }
+    }
+}
+
+impl GradCheck {
+    pub fn new() -> GradCheck {
+        GradCheck {
+            step: 1e-5,
+            input: true,
+        }
+    }
+    pub fn step(mut self, step: f64) -> GradCheck {
+        assert!(step > 0.0);
+        self.step = step;
+        self
+    }
+    // skip the input, e.g. for embeddings whose inputs are ids
+    pub fn input(mut self, input: bool) -> GradCheck {
+        self.input = input;
+        self
+    }
+    // checks loss = sum(forward(x) .* r) for a fixed random r
+    pub fn layer(&self, layer: &mut dyn Layer, x: &Mat) -> GradReport {
+        let mut rng = ChaCha8Rng::seed_from_u64(SEED);
+        let mut r = None;
+        self.check(layer, x, &mut |out| {
+            let r = r.get_or_insert_with(|| {
+                let values = (0..out.row() * out.col()).map(|_| rng.gen_range(-1.0..1.0));
+                Mat::from_vec(values.collect(), out.row(), out.col())
+            });
+            (out.ele_mul(r).sum_all(), r.clone())
+        })
+    }
+    // checks the model's loss on a batch, regularization penalties are left out
+    pub fn model(&self, nn: &mut NN, x: &Mat, y: &Mat) -> GradReport {
+        let loss = nn.loss;
+        let mut chain = Chain(&mut nn.layers);
+        self.check(&mut chain, x, &mut |z| {
+            (loss.value(z, y), loss.grad(&loss.output(z), y))
+        })
+    }
+    // loss maps an output to the loss and d loss / d output
+    fn check(
+        &self,
+        layer: &mut dyn Layer,
+        x: &Mat,
+        loss: &mut dyn FnMut(&Mat) -> (f64, Mat),
+    ) -> GradReport {
+        let mut run = |layer: &mut dyn Layer, x: &Mat| {
+            layer.seed(SEED);
+            let out = layer.forward(x, true);
+            loss(&out)
+        };
+        let (_, grad) = run(layer, x);
+        let dx = layer.backward(&grad);
+        let grads: Vec<Mat> = layer
+            .params_and_grads()
+            .into_iter()
+            .map(|(_, grad)| grad.clone())
+            .collect();
+
+        let h = self.step;
+        let error = |numeric: f64, analytic: f64| {
+            (numeric - analytic).abs() / (numeric.abs() + analytic.abs()).max(EPS)
+        };
+        let mut input = None;
+        if self.input {
+            let mut worst: f64 = 0.0;
+            for i in 0..x.as_slice().len() {
+                let (mut plus, mut minus) = (x.clone(), x.clone());
+                plus.as_mut_slice()[i] += h;
+                minus.as_mut_slice()[i] -= h;
+                let numeric = (run(layer, &plus).0 - run(layer, &minus).0) / (2.0 * h);
+                worst = worst.max(error(numeric, dx.as_slice()[i]));
+            }
+            input = Some(worst);
+        }
+        let mut params = vec![];
+        for (p, grad) in grads.iter().enumerate() {
+            let mut worst: f64 = 0.0;
+            for i in 0..grad.as_slice().len() {
+                let nudge = |layer: &mut dyn Layer, by: f64| {
+                    layer.params_and_grads()[p].0.as_mut_slice()[i] += by;
+                };
+                nudge(layer, h);
+                let plus = run(layer, x).0;
+                nudge(layer, -2.0 * h);
+                let minus = run(layer, x).0;
+                nudge(layer, h);
+                worst = worst.max(error((plus - minus) / (2.0 * h), grad.as_slice()[i]));
+            }
+            params.push(worst);
+        }
+        GradReport { input, params }
+    }
+}
+
+impl Default for GradCheck {
+    fn default() -> Self {
+        GradCheck::new()
+    }
+}
+
+// the layers of a model as one layer, without the loss
+struct Chain<'a>(&'a mut Vec<Box<dyn Layer>>);
+
+impl Layer for Chain<'_> {
+    fn forward(&mut self, x: &Mat, training: bool) -> Mat {
+        let mut out = x.clone();
+        for layer in self.0.iter_mut() {
+            out = layer.forward(&out, training);
+        }
+        out
+    }
+    fn backward(&mut self, grad: &Mat) -> Mat {
+        let mut grad = grad.clone();
+        for layer in self.0.iter_mut().rev() {
+            grad = layer.backward(&grad);
+        }
+        grad
+    }
+    fn params_and_grads(&mut self) -> Vec<(&mut Mat, &Mat)> {
+        self.0
+            .iter_mut()
+            .flat_map(|layer| layer.params_and_grads())
+            .collect()
+    }
+    fn seed(&mut self, seed: u64) {
+        for layer in self.0.iter_mut() {
+            layer.seed(seed);
+        }
+    }
+    fn name(&self) -> String {
+        "Chain".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::layers::activation::Activation;
+    use crate::layers::dense::Dense;
+    use crate::layers::dropout::Dropout;
+    use crate::layers::testing::rand_mat;
+    use crate::layers::Layer;
+    use crate::models;
+    use crate::nn::gradcheck::GradCheck;
+    use crate::nn::loss::Loss;
+    use crate::nn::NN;
+    use crate::tools::matrix::*;
+
+    // one hot targets, they suit every loss
+    fn targets(classes: usize, n: usize) -> Mat {
+        let mut y = Mat::new(classes, n);
+        for j in 0..n {
+            y[(j % classes, j)] = 1.0;
+        }
+        y
+    }
+
+    #[test]
+    fn default_model_has_gradients_for_every_layer() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let mut nn = NN::new(4, 5, 3);
+        let report = GradCheck::new().model(&mut nn, &rand_mat(4, 3, &mut rng), &targets(3, 3));
+        // three dense layers with a weight and a bias each
+        assert_eq!(report.params.len(), 6);
+        report.assert_below(1e-6);
+    }
+
+    #[test]
+    fn every_loss_matches_its_gradient() {
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+        for loss in [
+            Loss::SigmoidCrossEntropy,
+            Loss::SoftmaxCrossEntropy,
+            Loss::MeanSquaredError,
+        ] {
+            let mut nn = NN::from_layers(
+                3,
+                vec![
+                    Box::new(Dense::new(3, 4)),
+                    Box::new(Activation::tanh()),
+                    Box::new(Dense::new(4, 2)),
+                ],
+                loss,
+            );
+            GradCheck::new()
+                .model(&mut nn, &rand_mat(3, 4, &mut rng), &targets(2, 4))
+                .assert_below(1e-6);
+        }
+    }
+
+    #[test]
+    fn dropout_masks_repeat_between_passes() {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let mut nn = NN::from_layers(
+            3,
+            vec![
+                Box::new(Dense::new(3, 6)),
+                Box::new(Dropout::new(0.5)),
+                Box::new(Dense::new(6, 2)),
+            ],
+            Loss::SoftmaxCrossEntropy,
+        );
+        GradCheck::new()
+            .model(&mut nn, &rand_mat(3, 4, &mut rng), &targets(2, 4))
+            .assert_below(1e-6);
+    }
+
+    #[test]
+    fn graph_models() {
+        let mut rng = ChaCha8Rng::seed_from_u64(4);
+        let mut resnet = models::resnet_mlp(4, 3, 2, 3);
+        GradCheck::new()
+            .model(&mut resnet, &rand_mat(4, 3, &mut rng), &targets(3, 3))
+            .assert_below(1e-6);
+        let mut towers = models::two_tower(3, 2, 4, 2, 1);
+        GradCheck::new()
+            .model(&mut towers, &rand_mat(5, 3, &mut rng), &targets(1, 3))
+            .assert_below(1e-6);
+    }
+
+    #[test]
+    fn wrong_gradients_are_reported() {
+        let mut rng = ChaCha8Rng::seed_from_u64(5);
+        let mut layer = Dense::new(2, 2);
+        let x = rand_mat(2, 2, &mut rng);
+        let good = GradCheck::new().layer(&mut layer, &x);
+        // a step this large can't be accurate for tanh
+        let mut tanh = Activation::tanh();
+        let bad = GradCheck::new()
+            .step(0.5)
+            .layer(&mut tanh, &x.scaler_mul(2.0));
+        assert!(good.worst() < 1e-8);
+        assert!(bad.worst() > 1e-3);
+    }
+
+    // x * scale with a backward that forgets the scale in dx and halves d scale
+    struct Broken {
+        scale: Mat,
+        dscale: Mat,
+        x: Option<Mat>,
+    }
+
+    impl Layer for Broken {
+        fn forward(&mut self, x: &Mat, _training: bool) -> Mat {
+            self.x = Some(x.clone());
+            x.scaler_mul(self.scale[(0, 0)])
+        }
+        fn backward(&mut self, grad: &Mat) -> Mat {
+            let x = self.x.as_ref().unwrap();
+            self.dscale[(0, 0)] = 0.5 * grad.ele_mul(x).sum_all();
+            grad.clone()
+        }
+        fn params(&self) -> Vec<&Mat> {
+            vec![&self.scale]
+        }
+        fn params_and_grads(&mut self) -> Vec<(&mut Mat, &Mat)> {
+            vec![(&mut self.scale, &self.dscale)]
+        }
+        fn name(&self) -> String {
+            "Broken".to_string()
+        }
+    }
+
+    #[test]
+    fn a_broken_backward_fails_the_check() {
+        let mut rng = ChaCha8Rng::seed_from_u64(6);
+        let mut layer = Broken {
+            scale: Mat::val_mat(1, 1, 3.0),
+            dscale: Mat::new(1, 1),
+            x: None,
+        };
+        let report = GradCheck::new().layer(&mut layer, &rand_mat(3, 2, &mut rng));
+        // 1 against 3 and 0.5 against 1
+        assert!((report.input.unwrap() - 0.5).abs() < 1e-6);
+        assert!((report.params[0] - 1.0 / 3.0).abs() < 1e-6);
+        let check = std::panic::catch_unwind(|| report.assert_below(1e-6));
+        assert!(check.is_err());
+    }
+}
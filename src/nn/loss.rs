@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+
+use crate::tools::activations;
+use crate::tools::matrix::*;
+
+// Loss the network is trained with. it also owns the output non linearity,
+// so the gradient handed to the layers is taken w.r.t. the last layer's raw output
+// and stays well behaved when the output saturates.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Loss {
+    // independent sigmoid per output with binary cross entropy, what NN::new uses
+    SigmoidCrossEntropy,
+    // softmax over every column with categorical cross entropy
+    SoftmaxCrossEntropy,
+    // identity output with 1/2 squared error
+    MeanSquaredError,
+}
+
+impl Loss {
+    // prediction for raw outputs z, one sample per column
+    pub fn output(&self, z: &Mat) -> Mat {
+        match self {
+            Loss::SigmoidCrossEntropy => z.map(activations::sigmoid),
+            Loss::SoftmaxCrossEntropy => softmax(z),
+            Loss::MeanSquaredError => z.clone(),
+        }
+    }
+    // d loss / d z for predictions a = output(z), averaged over the columns
+    pub fn grad(&self, a: &Mat, y: &Mat) -> Mat {
+        assert_eq!(a.shape(), y.shape());
+        // all three losses reduce to the same expression with their matching output
+        (a - y).scaler_mul(1.0 / a.col() as f64)
+    }
+    // loss for raw outputs z averaged over the columns, grad is its derivative w.r.t. z.
+    // softmax cross entropy expects every target column to sum to 1
+    pub fn value(&self, z: &Mat, y: &Mat) -> f64 {
+        assert_eq!(z.shape(), y.shape());
+        let mut sum = 0.0;
+        match self {
+            Loss::SigmoidCrossEntropy => {
+                // -y ln(sigmoid(z)) - (1 - y) ln(1 - sigmoid(z)) = softplus(z) - y z
+                for (&z, &y) in z.as_slice().iter().zip(y.as_slice()) {
+                    sum += z.max(0.0) + libm::log1p(libm::exp(-z.abs())) - y * z;
+                }
+            }
+            Loss::SoftmaxCrossEntropy => {
+                for j in 0..z.col() {
+                    let mut max = f64::NEG_INFINITY;
+                    for i in 0..z.row() {
+                        max = max.max(z[(i, j)]);
+                    }
+                    let mut exp_sum = 0.0;
+                    for i in 0..z.row() {
+                        exp_sum += libm::exp(z[(i, j)] - max);
+                    }
+                    let log_sum = max + libm::log(exp_sum);
+                    for i in 0..z.row() {
+                        sum += y[(i, j)] * (log_sum - z[(i, j)]);
+                    }
+                }
+            }
+            Loss::MeanSquaredError => {
+                for (&z, &y) in z.as_slice().iter().zip(y.as_slice()) {
+                    sum += 0.5 * (z - y) * (z - y);
+                }
+            }
+        }
+        sum / z.col() as f64
+    }
+}
+
+// numerically stable softmax of every column
+pub fn softmax(z: &Mat) -> Mat {
+    let mut out = z.clone();
+    for j in 0..z.col() {
+        let mut max = f64::NEG_INFINITY;
+        for i in 0..z.row() {
+            max = max.max(z[(i, j)]);
+        }
+        let mut sum = 0.0;
+        for i in 0..z.row() {
+            out[(i, j)] = libm::exp(z[(i, j)] - max);
+            sum += out[(i, j)];
+        }
+        for i in 0..z.row() {
+            out[(i, j)] /= sum;
+        }
+    }
+    out
+}
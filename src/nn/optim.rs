@@ -0,0 +1,195 @@
+use serde::{Deserialize, Serialize};
+
+use crate::tools::matrix::*;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum OptimizerKind {
+    // param -= lr * grad
+    Sgd,
+    // heavy ball momentum: v = beta * v + grad, param -= lr * v
+    Momentum { beta: f64 },
+    // Kingma & Ba with bias correction
+    Adam { beta1: f64, beta2: f64, eps: f64 },
+}
+
+// Turns gradients into parameter updates. Parameters are identified by their index
+// in Layer::params over all layers (NN::weights without the buffers), the per parameter
+// buffers are created on first use and are part of a training checkpoint.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Optimizer {
+    kind: OptimizerKind,
+    steps: u64,
+    // state[index] holds the moment buffers of parameter index
+    state: Vec<Vec<Mat>>,
+}
+
+impl Optimizer {
+    pub fn new(kind: OptimizerKind) -> Optimizer {
+        Optimizer {
+            kind,
+            steps: 0,
+            state: vec![],
+        }
+    }
+    pub fn sgd() -> Optimizer {
+        Optimizer::new(OptimizerKind::Sgd)
+    }
+    pub fn momentum(beta: f64) -> Optimizer {
+        Optimizer::new(OptimizerKind::Momentum { beta })
+    }
+    pub fn adam(beta1: f64, beta2: f64, eps: f64) -> Optimizer {
+        Optimizer::new(OptimizerKind::Adam { beta1, beta2, eps })
+    }
+    pub fn kind(&self) -> &OptimizerKind {
+        &self.kind
+    }
+    // number of updates done so far
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+    // forget all moment buffers, e.g. after the weights were replaced
+    pub fn reset(&mut self) {
+        self.steps = 0;
+        self.state.clear();
+    }
+    // call once per update, before step is called for the individual parameters
+    pub fn begin_step(&mut self) {
+        self.steps += 1;
+    }
+    pub fn step(&mut self, index: usize, param: &mut Mat, grad: &Mat, lr: f64) {
+        self.step_rows(index, param, grad, None, lr);
+    }
+    // step restricted to some rows of param for sparse gradients (see Layer::sparse_rows),
+    // grad then only holds those rows: its row i belongs to param row rows[i].
+    // the moment buffers of the other rows are left alone until their rows show up again
+    pub fn step_rows(
+        &mut self,
+        index: usize,
+        param: &mut Mat,
+        grad: &Mat,
+        rows: Option<&[usize]>,
+        lr: f64,
+    ) {
+        match rows {
+            Some(rows) => assert_eq!(grad.shape(), (rows.len(), param.col())),
+            None => assert_eq!(param.shape(), grad.shape()),
+        }
+        let buffers = match self.kind {
+            OptimizerKind::Sgd => 0,
+            OptimizerKind::Momentum { .. } => 1,
+            OptimizerKind::Adam { .. } => 2,
+        };
+        if self.state.len() <= index {
+            self.state.resize(index + 1, vec![]);
+        }
+        if self.state[index].is_empty() {
+            self.state[index] = vec![Mat::zeroes_like(param); buffers];
+        }
+        // (param element, grad element) pairs
+        let cols = param.col();
+        let elements: Box<dyn Iterator<Item = (usize, usize)>> = match rows {
+            Some(rows) => Box::new(
+                rows.iter()
+                    .enumerate()
+                    .flat_map(move |(i, r)| (0..cols).map(move |j| (r * cols + j, i * cols + j))),
+            ),
+            None => Box::new((0..param.as_slice().len()).map(|i| (i, i))),
+        };
+        let state = &mut self.state[index];
+        let p = param.as_mut_slice();
+        let grad = grad.as_slice();
+        match self.kind {
+            OptimizerKind::Sgd => {
+                for (i, g) in elements {
+                    p[i] -= lr * grad[g];
+                }
+            }
+            OptimizerKind::Momentum { beta } => {
+                let velocity = state[0].as_mut_slice();
+                for (i, g) in elements {
+                    velocity[i] = beta * velocity[i] + grad[g];
+                    p[i] -= lr * velocity[i];
+                }
+            }
+            OptimizerKind::Adam { beta1, beta2, eps } => {
+                let t = self.steps.max(1) as f64;
+                let correction1 = 1.0 - libm::pow(beta1, t);
+                let correction2 = 1.0 - libm::pow(beta2, t);
+                let (first, second) = state.split_at_mut(1);
+                let m = first[0].as_mut_slice();
+                let v = second[0].as_mut_slice();
+                for (i, g) in elements {
+                    m[i] = beta1 * m[i] + (1.0 - beta1) * grad[g];
+                    v[i] = beta2 * v[i] + (1.0 - beta2) * grad[g] * grad[g];
+                    let m_hat = m[i] / correction1;
+                    let v_hat = v[i] / correction2;
+                    p[i] -= lr * m_hat / (libm::sqrt(v_hat) + eps);
+                }
+            }
+        }
+    }
+}
+
+impl Default for Optimizer {
+    fn default() -> Self {
+        Optimizer::sgd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nn::optim::*;
+
+    fn one(value: f64) -> Mat {
+        Mat::val_mat(1, 1, value)
+    }
+
+    #[test]
+    fn sgd_and_momentum_steps() {
+        let mut sgd = Optimizer::sgd();
+        let mut p = one(1.0);
+        sgd.begin_step();
+        sgd.step(0, &mut p, &one(0.5), 0.1);
+        assert!((p[(0, 0)] - 0.95).abs() < 1e-12);
+
+        let mut momentum = Optimizer::momentum(0.9);
+        let mut p = one(1.0);
+        // v = 0.5, p = 1 - 0.05, then v = 0.9 * 0.5 + 0.5 = 0.95, p = 0.95 - 0.095
+        for expected in [0.95, 0.855] {
+            momentum.begin_step();
+            momentum.step(0, &mut p, &one(0.5), 0.1);
+            assert!((p[(0, 0)] - expected).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn adam_corrects_the_moment_bias() {
+        let mut adam = Optimizer::adam(0.9, 0.999, 1e-8);
+        let mut p = one(1.0);
+        // m = 0.05 and v = 0.00025 are 0.5 and 0.25 after correction,
+        // the first step moves by about lr whatever the gradient's scale
+        adam.begin_step();
+        adam.step(0, &mut p, &one(0.5), 0.1);
+        assert!((p[(0, 0)] - (1.0 - 0.1 * 0.5 / (0.5 + 1e-8))).abs() < 1e-12);
+        // m = 0.045 - 0.1 = -0.055, v = 0.00024975 + 0.001 = 0.00124975,
+        // m_hat = -0.055 / 0.19, v_hat = 0.00124975 / 0.001999
+        adam.begin_step();
+        adam.step(0, &mut p, &one(-1.0), 0.1);
+        let m_hat = -0.055 / 0.19;
+        let v_hat = 0.00124975 / 0.001999;
+        let expected = 1.0 - 0.1 * 0.5 / (0.5 + 1e-8) - 0.1 * m_hat / (libm::sqrt(v_hat) + 1e-8);
+        assert!((p[(0, 0)] - expected).abs() < 1e-9);
+        assert!((p[(0, 0)] - 0.93661035).abs() < 1e-8);
+    }
+
+    #[test]
+    fn sparse_steps_only_touch_their_rows() {
+        let mut momentum = Optimizer::momentum(0.9);
+        let mut p = Mat::val_mat(3, 2, 1.0);
+        momentum.begin_step();
+        // the gradient only holds row 1
+        let grad = Mat::from_vec(vec![1.0, 2.0], 1, 2);
+        momentum.step_rows(0, &mut p, &grad, Some(&[1]), 0.5);
+        assert_eq!(p.as_slice(), &[1.0, 1.0, 0.5, 0.0, 1.0, 1.0]);
+    }
+}
@@ -0,0 +1,272 @@
+use serde::{Deserialize, Serialize};
+
+use crate::layers::ParamGroup;
+use crate::tools::matrix::*;
+
+// l1 * sum |w| + l2 / 2 * sum w^2 added to the loss of a parameter
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Penalty {
+    pub l1: f64,
+    pub l2: f64,
+}
+
+impl Penalty {
+    pub fn none() -> Penalty {
+        Penalty::default()
+    }
+    pub fn is_none(&self) -> bool {
+        self.l1 == 0.0 && self.l2 == 0.0
+    }
+    pub fn loss(&self, param: &Mat) -> f64 {
+        param
+            .as_slice()
+            .iter()
+            .map(|w| self.l1 * w.abs() + 0.5 * self.l2 * w * w)
+            .sum()
+    }
+    // adds d penalty / d param to grad, the l1 subgradient at 0 is 0
+    pub fn add_grad(&self, param: &Mat, grad: &mut Mat) {
+        assert_eq!(param.shape(), grad.shape());
+        for (g, w) in grad.as_mut_slice().iter_mut().zip(param.as_slice()) {
+            *g += self.grad(*w);
+        }
+    }
+    // add_grad for some rows only, sparse parameters decay when they are used.
+    // grad holds just those rows, its row i belongs to param row rows[i]
+    pub fn add_grad_rows(&self, param: &Mat, grad: &mut Mat, rows: &[usize]) {
+        assert_eq!(grad.shape(), (rows.len(), param.col()));
+        for (i, &r) in rows.iter().enumerate() {
+            for j in 0..param.col() {
+                grad[(i, j)] += self.grad(param[(r, j)]);
+            }
+        }
+    }
+    fn grad(&self, w: f64) -> f64 {
+        let sign = if w > 0.0 {
+            1.0
+        } else if w < 0.0 {
+            -1.0
+        } else {
+            0.0
+        };
+        self.l1 * sign + self.l2 * w
+    }
+}
+
+// Weight decay per parameter group (see layers::ParamGroup). the constructors only
+// penalize weights, biases, normalization parameters and slopes are left alone unless set
+// with `group`
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Regularization {
+    pub weights: Penalty,
+    pub biases: Penalty,
+    pub norm: Penalty,
+    pub slopes: Penalty,
+}
+
+impl Regularization {
+    pub fn l1(l1: f64) -> Regularization {
+        Regularization::elastic(l1, 0.0)
+    }
+    pub fn l2(l2: f64) -> Regularization {
+        Regularization::elastic(0.0, l2)
+    }
+    pub fn elastic(l1: f64, l2: f64) -> Regularization {
+        Regularization {
+            weights: Penalty { l1, l2 },
+            ..Regularization::default()
+        }
+    }
+    pub fn group(mut self, group: ParamGroup, penalty: Penalty) -> Regularization {
+        match group {
+            ParamGroup::Weight => self.weights = penalty,
+            ParamGroup::Bias => self.biases = penalty,
+            ParamGroup::Norm => self.norm = penalty,
+            ParamGroup::Slope => self.slopes = penalty,
+        }
+        self
+    }
+    pub fn penalty(&self, group: ParamGroup) -> Penalty {
+        match group {
+            ParamGroup::Weight => self.weights,
+            ParamGroup::Bias => self.biases,
+            ParamGroup::Norm => self.norm,
+            ParamGroup::Slope => self.slopes,
+        }
+    }
+}
+
+// applied to the gradients (penalties included) right before the optimizer step
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum GradientClipping {
+    // clamp every element to [-max, max]
+    Value(f64),
+    // scale all gradients together so their global l2 norm is at most max
+    Norm(f64),
+}
+
+impl GradientClipping {
+    // clamp panics on a negative bound and a norm can't be scaled down to 0
+    pub fn validate(&self) {
+        match *self {
+            GradientClipping::Value(max) => {
+                assert!(max >= 0.0, "clipping by value needs max >= 0 ;(")
+            }
+            GradientClipping::Norm(max) => assert!(max > 0.0, "clipping by norm needs max > 0 ;("),
+        }
+    }
+    pub fn clip(&self, grads: &mut [Mat]) {
+        match *self {
+            GradientClipping::Value(max) => {
+                for grad in grads.iter_mut() {
+                    for g in grad.as_mut_slice().iter_mut() {
+                        *g = g.clamp(-max, max);
+                    }
+                }
+            }
+            GradientClipping::Norm(max) => {
+                let norm = global_norm(grads);
+                if norm > max {
+                    let scale = max / norm;
+                    for grad in grads.iter_mut() {
+                        *grad = grad.scaler_mul(scale);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// l2 norm of all gradients taken as one vector
+pub fn global_norm(grads: &[Mat]) -> f64 {
+    grads
+        .iter()
+        .flat_map(|grad| grad.as_slice())
+        .map(|g| g * g)
+        .sum::<f64>()
+        .sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layers::batch_norm::BatchNorm1d;
+    use crate::layers::dense::Dense;
+    use crate::layers::ParamGroup;
+    use crate::nn::loss::Loss;
+    use crate::nn::regularization::*;
+    use crate::nn::NN;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-12
+    }
+
+    #[test]
+    fn l1_and_l2_loss_and_gradient() {
+        let w = Mat::from_vec(vec![2.0, -1.0, 0.0], 3, 1);
+        let penalty = Penalty { l1: 0.5, l2: 0.0 };
+        assert!(close(penalty.loss(&w), 1.5));
+        let mut grad = Mat::val_mat(3, 1, 1.0);
+        penalty.add_grad(&w, &mut grad);
+        // the subgradient at 0 is 0
+        assert_eq!(grad.as_slice(), &[1.5, 0.5, 1.0]);
+
+        let penalty = Penalty { l1: 0.0, l2: 0.1 };
+        assert!(close(penalty.loss(&w), 0.25));
+        let mut grad = Mat::new(3, 1);
+        penalty.add_grad(&w, &mut grad);
+        assert_eq!(grad.as_slice(), &[0.2, -0.1, 0.0]);
+
+        let penalty = Penalty { l1: 0.5, l2: 0.1 };
+        assert!(close(penalty.loss(&w), 1.75));
+        assert!(Penalty::none().is_none() && !penalty.is_none());
+    }
+
+    #[test]
+    fn add_grad_rows_decays_the_given_rows() {
+        let w = Mat::from_vec(vec![1.0, -2.0, 3.0, 4.0, -5.0, 6.0], 3, 2);
+        let penalty = Penalty { l1: 1.0, l2: 0.5 };
+        let mut sparse = Mat::new(2, 2);
+        penalty.add_grad_rows(&w, &mut sparse, &[2, 0]);
+        let mut dense = Mat::new(3, 2);
+        penalty.add_grad(&w, &mut dense);
+        for j in 0..2 {
+            assert_eq!(sparse[(0, j)], dense[(2, j)]);
+            assert_eq!(sparse[(1, j)], dense[(0, j)]);
+        }
+    }
+
+    #[test]
+    fn biases_and_norm_are_excluded_by_default() {
+        for reg in [
+            Regularization::l1(0.1),
+            Regularization::l2(0.1),
+            Regularization::elastic(0.1, 0.2),
+        ] {
+            assert!(!reg.penalty(ParamGroup::Weight).is_none());
+            assert!(reg.penalty(ParamGroup::Bias).is_none());
+            assert!(reg.penalty(ParamGroup::Norm).is_none());
+            assert!(reg.penalty(ParamGroup::Slope).is_none());
+        }
+        let reg = Regularization::l2(0.1).group(ParamGroup::Bias, Penalty { l1: 0.0, l2: 1.0 });
+        assert_eq!(reg.penalty(ParamGroup::Bias).l2, 1.0);
+
+        let w = Mat::from_vec(vec![1.0, -2.0], 1, 2);
+        let b = Mat::from_vec(vec![3.0], 1, 1);
+        let mut nn = NN::from_layers(
+            2,
+            vec![
+                Box::new(Dense::from_weights(w, b)),
+                Box::new(BatchNorm1d::new(1)),
+            ],
+            Loss::MeanSquaredError,
+        );
+        nn.set_regularization(Some(Regularization::l2(0.1)));
+        // only 0.05 * (1 + 4), the bias and the batch norm gamma of 1 aren't penalized
+        assert!(close(nn.penalty(), 0.25));
+        nn.set_regularization(Some(reg));
+        assert!(close(nn.penalty(), 0.25 + 4.5));
+    }
+
+    #[test]
+    fn clipping_by_value_and_by_norm() {
+        let grads = || {
+            vec![
+                Mat::from_vec(vec![3.0, -4.0], 2, 1),
+                Mat::from_vec(vec![0.5], 1, 1),
+            ]
+        };
+        let mut clipped = grads();
+        GradientClipping::Value(1.0).clip(&mut clipped);
+        assert_eq!(clipped[0].as_slice(), &[1.0, -1.0]);
+        assert_eq!(clipped[1].as_slice(), &[0.5]);
+
+        let mut clipped = grads();
+        assert!(close(global_norm(&clipped), 5.0 * 1.01f64.sqrt()));
+        GradientClipping::Norm(2.0).clip(&mut clipped);
+        assert!(close(global_norm(&clipped), 2.0));
+        // the direction is kept
+        assert!(close(clipped[0][(0, 0)] / clipped[1][(0, 0)], 6.0));
+
+        // already small enough
+        let mut clipped = grads();
+        GradientClipping::Norm(10.0).clip(&mut clipped);
+        assert_eq!(clipped[0].as_slice(), grads()[0].as_slice());
+    }
+
+    #[test]
+    fn invalid_clipping_bounds_are_rejected() {
+        for clipping in [
+            GradientClipping::Value(-1.0),
+            GradientClipping::Norm(0.0),
+            GradientClipping::Norm(-2.0),
+            GradientClipping::Norm(f64::NAN),
+        ] {
+            let mut nn = NN::new(2, 3, 1);
+            let set = std::panic::AssertUnwindSafe(|| nn.set_gradient_clipping(Some(clipping)));
+            assert!(std::panic::catch_unwind(set).is_err());
+        }
+        GradientClipping::Value(0.0).validate();
+    }
+}
@@ -0,0 +1,401 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::nn::callbacks::Callback;
+use crate::nn::{EpochMetrics, NN};
+use crate::tools::matrix::*;
+
+// buckets of equal width between min and max of a histogram
+const BUCKETS: usize = 30;
+
+// Writes a TensorBoard event file: TFRecords (length, masked crc32c of the length, data,
+// masked crc32c of the data) each holding an Event protobuf. the protobufs are encoded
+// by hand, only the fields needed for scalars and histograms:
+//   Event { wall_time: double = 1, step: int64 = 2, file_version: string = 3, summary = 5 }
+//   Summary { value: repeated Value = 1 }
+//   Value { tag: string = 1, simple_value: float = 2, histo: HistogramProto = 5 }
+//   HistogramProto { min = 1, max = 2, num = 3, sum = 4, sum_squares = 5,
+//                    bucket_limit: packed double = 6, bucket: packed double = 7 }
+pub struct EventWriter {
+    writer: BufWriter<File>,
+}
+
+impl EventWriter {
+    // creates dir and a new events.out.tfevents.* file in it
+    pub fn new(dir: &str) -> EventWriter {
+        fs::create_dir_all(dir).expect("couldn't create log dir ;(");
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("clock is before 1970 ;(");
+        // the pid keeps runs started in the same second apart
+        let name = format!(
+            "events.out.tfevents.{}.kek.{}",
+            now.as_secs(),
+            std::process::id()
+        );
+        let file = File::create(Path::new(dir).join(name)).expect("couldn't create event file ;(");
+        let mut writer = EventWriter {
+            writer: BufWriter::new(file),
+        };
+        let mut event = event_header(0);
+        string_field(&mut event, 3, "brain.Event:2");
+        writer.write_record(&event);
+        writer
+    }
+    pub fn scalar(&mut self, tag: &str, step: usize, value: f64) {
+        let mut value_proto = vec![];
+        string_field(&mut value_proto, 1, tag);
+        key(&mut value_proto, 2, 5);
+        value_proto.extend((value as f32).to_le_bytes());
+        self.write_summary(step, &value_proto);
+    }
+    // every element of the given matrices in one histogram
+    pub fn histogram(&mut self, tag: &str, step: usize, values: &[&Mat]) {
+        let all: Vec<f64> = values
+            .iter()
+            .flat_map(|m| m.as_slice().iter().copied())
+            .collect();
+        if all.is_empty() {
+            return;
+        }
+        let mut histo = vec![];
+        for (field, value) in histogram_stats(&all).into_iter().enumerate() {
+            double_field(&mut histo, field as u32 + 1, value);
+        }
+        let (limits, counts) = buckets(&all);
+        packed_doubles(&mut histo, 6, &limits);
+        packed_doubles(&mut histo, 7, &counts);
+
+        let mut value_proto = vec![];
+        string_field(&mut value_proto, 1, tag);
+        bytes_field(&mut value_proto, 5, &histo);
+        self.write_summary(step, &value_proto);
+    }
+    pub fn flush(&mut self) {
+        self.writer.flush().expect("couldn't write event file ;(");
+    }
+    fn write_summary(&mut self, step: usize, value_proto: &[u8]) {
+        let mut summary = vec![];
+        bytes_field(&mut summary, 1, value_proto);
+        let mut event = event_header(step);
+        bytes_field(&mut event, 5, &summary);
+        self.write_record(&event);
+    }
+    fn write_record(&mut self, data: &[u8]) {
+        let length = (data.len() as u64).to_le_bytes();
+        let mut record = Vec::with_capacity(data.len() + 16);
+        record.extend(length);
+        record.extend(masked_crc(&length).to_le_bytes());
+        record.extend(data);
+        record.extend(masked_crc(data).to_le_bytes());
+        self.writer
+            .write_all(&record)
+            .expect("couldn't write event file ;(");
+    }
+}
+
+// wall_time and step of an Event
+fn event_header(step: usize) -> Vec<u8> {
+    let wall_time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock is before 1970 ;(")
+        .as_secs_f64();
+    let mut event = vec![];
+    double_field(&mut event, 1, wall_time);
+    key(&mut event, 2, 0);
+    varint(&mut event, step as u64);
+    event
+}
+
+// min, max, num, sum, sum_squares
+fn histogram_stats(values: &[f64]) -> [f64; 5] {
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let sum = values.iter().sum();
+    let sum_squares = values.iter().map(|v| v * v).sum();
+    [min, max, values.len() as f64, sum, sum_squares]
+}
+
+// right edges and counts, the last edge is max so every value lands in a bucket
+fn buckets(values: &[f64]) -> (Vec<f64>, Vec<f64>) {
+    let [min, max, ..] = histogram_stats(values);
+    if min == max {
+        return (vec![max], vec![values.len() as f64]);
+    }
+    let width = (max - min) / BUCKETS as f64;
+    let mut limits: Vec<f64> = (1..=BUCKETS).map(|i| min + i as f64 * width).collect();
+    limits[BUCKETS - 1] = max;
+    let mut counts = vec![0.0; BUCKETS];
+    for v in values {
+        let i = (((v - min) / width) as usize).min(BUCKETS - 1);
+        counts[i] += 1.0;
+    }
+    (limits, counts)
+}
+
+fn varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+// field number and wire type: 0 varint, 1 64 bit, 2 length delimited, 5 32 bit
+fn key(out: &mut Vec<u8>, field: u32, wire_type: u8) {
+    varint(out, ((field as u64) << 3) | wire_type as u64);
+}
+
+fn double_field(out: &mut Vec<u8>, field: u32, value: f64) {
+    key(out, field, 1);
+    out.extend(value.to_le_bytes());
+}
+
+fn bytes_field(out: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    key(out, field, 2);
+    varint(out, bytes.len() as u64);
+    out.extend(bytes);
+}
+
+fn string_field(out: &mut Vec<u8>, field: u32, s: &str) {
+    bytes_field(out, field, s.as_bytes());
+}
+
+fn packed_doubles(out: &mut Vec<u8>, field: u32, values: &[f64]) {
+    let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+    bytes_field(out, field, &bytes);
+}
+
+// crc32c (Castagnoli), bitwise since records are small
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0x82f6_3b78
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn masked_crc(data: &[u8]) -> u32 {
+    let crc = crc32c(data);
+    crc.rotate_right(15).wrapping_add(0xa282_ead8)
+}
+
+// Logs training to a TensorBoard event file in dir: train/loss, train/grad_norm and
+// train/lr every `every` batches (by step), epoch/loss, epoch/val_loss,
+// epoch/val_accuracy and epoch/lr every epoch, plus a histogram of every parameter
+// tagged "{layer index}_{layer kind}/param{index}" at the end of every epoch.
+// a resumed run starts a new file in the same dir, the viewer merges them by step
+pub struct TensorBoardLogger {
+    dir: String,
+    every: usize,
+    histograms: bool,
+    writer: Option<EventWriter>,
+    step: usize,
+}
+
+impl TensorBoardLogger {
+    pub fn new(dir: &str) -> TensorBoardLogger {
+        TensorBoardLogger {
+            dir: dir.to_string(),
+            every: 1,
+            histograms: true,
+            writer: None,
+            step: 0,
+        }
+    }
+    // batches between step scalars, 0 turns them off
+    pub fn every(mut self, every: usize) -> Self {
+        self.every = every;
+        self
+    }
+    pub fn histograms(mut self, histograms: bool) -> Self {
+        self.histograms = histograms;
+        self
+    }
+    fn writer(&mut self) -> &mut EventWriter {
+        self.writer
+            .as_mut()
+            .expect("tensorboard logger used outside of train")
+    }
+}
+
+impl Callback for TensorBoardLogger {
+    fn on_train_begin(&mut self, _model: &mut NN) {
+        self.writer = Some(EventWriter::new(&self.dir));
+    }
+    fn on_batch_end(&mut self, model: &mut NN, step: usize, loss: f64) {
+        self.step = step;
+        if self.every == 0 || !step.is_multiple_of(self.every) {
+            return;
+        }
+        let grad_norm = model.grad_norm();
+        let lr = model.lr();
+        let writer = self.writer();
+        writer.scalar("train/loss", step, loss);
+        if let Some(grad_norm) = grad_norm {
+            writer.scalar("train/grad_norm", step, grad_norm);
+        }
+        writer.scalar("train/lr", step, lr);
+    }
+    // epoch summaries are logged at the step they ended on so both line up in the viewer
+    fn on_epoch_end(&mut self, model: &mut NN, metrics: &EpochMetrics) -> bool {
+        let step = self.step;
+        let lr = model.lr();
+        let histograms = self.histograms;
+        let writer = self.writer();
+        writer.scalar("epoch/loss", step, metrics.loss);
+        if let Some(val_loss) = metrics.val_loss {
+            writer.scalar("epoch/val_loss", step, val_loss);
+        }
+        if let Some(val_accuracy) = metrics.val_accuracy {
+            writer.scalar("epoch/val_accuracy", step, val_accuracy);
+        }
+        writer.scalar("epoch/lr", step, lr);
+        if histograms {
+            for (i, layer) in model.layers().iter().enumerate() {
+                // "Dense(784, 32)" -> "Dense", tags read better without the shapes
+                let name = layer.name();
+                let kind = name.split('(').next().unwrap_or_default().trim();
+                for (j, param) in layer.params().into_iter().enumerate() {
+                    let tag = format!("{}_{}/param{}", i, kind, j);
+                    writer.histogram(&tag, step, &[param]);
+                }
+            }
+        }
+        writer.flush();
+        false
+    }
+    fn on_train_end(&mut self, _model: &mut NN, _history: &[EpochMetrics]) {
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32c_matches_the_check_value() {
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+        assert_eq!(crc32c(b""), 0);
+    }
+
+    #[test]
+    fn varints_use_seven_bits_per_byte() {
+        let mut out = vec![];
+        varint(&mut out, 1);
+        varint(&mut out, 300);
+        assert_eq!(out, vec![0x01, 0xac, 0x02]);
+    }
+
+    #[test]
+    fn buckets_hold_every_value() {
+        let values: Vec<f64> = (0..100).map(|i| (i as f64 * 0.37).sin()).collect();
+        let (limits, counts) = buckets(&values);
+        assert_eq!(limits.len(), counts.len());
+        assert_eq!(counts.iter().sum::<f64>(), values.len() as f64);
+        assert_eq!(*limits.last().unwrap(), histogram_stats(&values)[1]);
+        assert!(limits.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(buckets(&[2.0, 2.0]), (vec![2.0], vec![2.0]));
+    }
+
+    // the data of every record in the single event file in dir, with both crcs checked
+    fn records(dir: &str) -> Vec<Vec<u8>> {
+        let mut files = fs::read_dir(dir).unwrap();
+        let file = files.next().unwrap().unwrap().path();
+        assert!(files.next().is_none());
+        let bytes = fs::read(&file).unwrap();
+        let mut records = vec![];
+        let mut at = 0;
+        while at < bytes.len() {
+            let length = &bytes[at..at + 8];
+            let crc = u32::from_le_bytes(bytes[at + 8..at + 12].try_into().unwrap());
+            assert_eq!(crc, masked_crc(length));
+            let n = u64::from_le_bytes(length.try_into().unwrap()) as usize;
+            let data = &bytes[at + 12..at + 12 + n];
+            let crc = u32::from_le_bytes(bytes[at + 12 + n..at + 16 + n].try_into().unwrap());
+            assert_eq!(crc, masked_crc(data));
+            records.push(data.to_vec());
+            at += 16 + n;
+        }
+        records
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|w| w == needle)
+    }
+
+    #[test]
+    fn records_are_framed_with_checked_lengths() {
+        let dir = std::env::temp_dir().join(format!("kek_tb_test_{}", std::process::id()));
+        let dir = dir.to_str().unwrap();
+        let mut writer = EventWriter::new(dir);
+        writer.scalar("loss", 3, 0.5);
+        writer.histogram("w", 3, &[&Mat::from_vec(vec![1.0, -2.0, 3.0], 3, 1)]);
+        writer.flush();
+        let records = records(dir);
+        fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(records.len(), 3);
+        assert!(contains(&records[0], b"brain.Event:2"));
+        // step 3 as a varint after the wall time
+        assert_eq!(records[1][9..11], [0x10, 3]);
+        assert!(contains(&records[1], &0.5f32.to_le_bytes()));
+        assert!(contains(&records[2], &3.0f64.to_le_bytes()));
+    }
+
+    #[test]
+    fn logger_writes_scalars_and_histograms_while_training() {
+        let dir = std::env::temp_dir().join(format!("kek_tb_logger_{}", std::process::id()));
+        let dir = dir.to_str().unwrap();
+        let x: Vec<Mat> = (0..8)
+            .map(|i| Mat::from_vec(vec![i as f64 / 8.0, 1.0 - i as f64 / 8.0], 2, 1))
+            .collect();
+        let y: Vec<Mat> = (0..8)
+            .map(|i| Mat::from_vec(vec![(i < 4) as u8 as f64, (i >= 4) as u8 as f64], 2, 1))
+            .collect();
+        let mut nn = NN::new(2, 3, 2);
+        let mut callbacks: Vec<Box<dyn Callback>> = vec![Box::new(TensorBoardLogger::new(dir))];
+        nn.train(&x, &y, Some((&x, &y)), 2, 4, &mut callbacks);
+        let records = records(dir);
+        fs::remove_dir_all(dir).unwrap();
+
+        let count = |tag: &str| {
+            records
+                .iter()
+                .filter(|r| contains(r, tag.as_bytes()))
+                .count()
+        };
+        // 2 batches in each of the 2 epochs
+        for tag in ["train/loss", "train/grad_norm", "train/lr"] {
+            assert_eq!(count(tag), 4, "{tag}");
+        }
+        for tag in [
+            "epoch/loss",
+            "epoch/val_loss",
+            "epoch/val_accuracy",
+            "epoch/lr",
+        ] {
+            assert_eq!(count(tag), 2, "{tag}");
+        }
+        // the dense layers sit at 0, 2 and 4, activations have no params
+        for i in [0, 2, 4] {
+            for j in 0..2 {
+                assert_eq!(count(&format!("{i}_Dense/param{j}")), 2);
+            }
+        }
+        assert_eq!(count("_Activation/"), 0);
+        assert_eq!(records.len(), 1 + 4 * 3 + 2 * (4 + 6));
+    }
+}
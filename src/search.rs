@@ -0,0 +1,671 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::config::Config;
+use crate::nn::early_stopping::Monitor;
+use crate::tools::matrix::*;
+
+// values one hyperparameter is searched over
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Domain {
+    // any config value, e.g. whole optimizer or clipping settings
+    Choice(Vec<Value>),
+    Uniform { low: f64, high: f64 },
+    // uniform in log space, for learning rates and decays
+    LogUniform { low: f64, high: f64 },
+    // low..=high
+    Int { low: i64, high: i64 },
+}
+
+impl Domain {
+    fn sample<R: Rng>(&self, rng: &mut R) -> Value {
+        match self {
+            Domain::Choice(values) => values[rng.gen_range(0..values.len())].clone(),
+            Domain::Uniform { low, high } => Value::from(rng.gen_range(*low..=*high)),
+            Domain::LogUniform { low, high } => {
+                let log = rng.gen_range(libm::log(*low)..=libm::log(*high));
+                Value::from(libm::exp(log))
+            }
+            Domain::Int { low, high } => Value::from(rng.gen_range(*low..=*high)),
+        }
+    }
+    // every value for grid search, continuous domains have none
+    fn values(&self) -> Vec<Value> {
+        match self {
+            Domain::Choice(values) => values.clone(),
+            Domain::Int { low, high } => (*low..=*high).map(Value::from).collect(),
+            _ => panic!("grid search needs Choice or Int domains ;("),
+        }
+    }
+}
+
+// Hyperparameters by their dotted path into Config (as serialized), e.g.
+// "training.lr", "training.batch_size" or "model.layers.0.Dense.units". the value at
+// the path is replaced as a whole so it has to exist in the base config, optional
+// sections can be searched by giving the whole section: "training.clipping". toml has
+// no null, so switching a section off takes a json search file
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SearchSpace {
+    params: BTreeMap<String, Domain>,
+}
+
+impl SearchSpace {
+    pub fn new() -> SearchSpace {
+        SearchSpace::default()
+    }
+    pub fn param(mut self, path: &str, domain: Domain) -> Self {
+        self.params.insert(path.to_string(), domain);
+        self
+    }
+    pub fn choice<V: Into<Value>>(self, path: &str, values: Vec<V>) -> Self {
+        let values = values.into_iter().map(Into::into).collect();
+        self.param(path, Domain::Choice(values))
+    }
+    pub fn uniform(self, path: &str, low: f64, high: f64) -> Self {
+        self.param(path, Domain::Uniform { low, high })
+    }
+    pub fn log_uniform(self, path: &str, low: f64, high: f64) -> Self {
+        self.param(path, Domain::LogUniform { low, high })
+    }
+    pub fn int(self, path: &str, low: i64, high: i64) -> Self {
+        self.param(path, Domain::Int { low, high })
+    }
+    pub fn paths(&self) -> Vec<&String> {
+        self.params.keys().collect()
+    }
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> Params {
+        self.params
+            .iter()
+            .map(|(path, domain)| (path.clone(), domain.sample(rng)))
+            .collect()
+    }
+    // cartesian product of every domain, the last path varies fastest
+    pub fn grid(&self) -> Vec<Params> {
+        let mut grid = vec![Params::new()];
+        for (path, domain) in &self.params {
+            let values = domain.values();
+            grid = grid
+                .into_iter()
+                .flat_map(|params| {
+                    values.iter().map(move |value| {
+                        let mut params = params.clone();
+                        params.insert(path.clone(), value.clone());
+                        params
+                    })
+                })
+                .collect();
+        }
+        grid
+    }
+}
+
+// one point of a search space
+pub type Params = BTreeMap<String, Value>;
+
+// base with params written over it, panics on paths that don't exist
+pub fn apply(base: &Config, params: &Params) -> Config {
+    let mut json = serde_json::to_value(base).expect("couldn't serialize config ;(");
+    for (path, value) in params {
+        let pointer = format!("/{}", path.replace('.', "/"));
+        let slot = json
+            .pointer_mut(&pointer)
+            .unwrap_or_else(|| panic!("config has nothing at {path} ;("));
+        *slot = value.clone();
+    }
+    serde_json::from_value(json).unwrap_or_else(|e| panic!("params don't fit the config ;( {e}"))
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Strategy {
+    // every combination, each trained for the full training.epochs
+    Grid,
+    // independent samples, each trained for the full training.epochs
+    Random {
+        trials: usize,
+    },
+    // Jamieson & Talwalkar: train `trials` samples for min_epochs, keep the best 1/eta,
+    // give them eta times the epochs and repeat until one is left
+    SuccessiveHalving {
+        trials: usize,
+        min_epochs: i32,
+        eta: usize,
+    },
+    // Li et al.: successive halving brackets trading the number of trials against
+    // their starting budget, max_epochs is what the final survivors get
+    Hyperband {
+        max_epochs: i32,
+        eta: usize,
+    },
+}
+
+// a search as read from a .toml or .json file next to the base config:
+//   strategy = { SuccessiveHalving = { trials = 27, min_epochs = 1, eta = 3 } }
+//   objective = "ValAccuracy"
+//   [space]
+//   "training.lr" = { LogUniform = { low = 1e-4, high = 0.5 } }
+//   "training.batch_size" = { Choice = [16, 32, 64] }
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SearchConfig {
+    pub strategy: Strategy,
+    #[serde(default = "default_objective")]
+    pub objective: Monitor,
+    // for sampling, trials train with the base config's seed
+    #[serde(default)]
+    pub seed: u64,
+    pub space: SearchSpace,
+}
+
+fn default_objective() -> Monitor {
+    Monitor::ValLoss
+}
+
+fn is_toml(path: &str) -> bool {
+    Path::new(path).extension().is_some_and(|ext| ext == "toml")
+}
+
+impl SearchConfig {
+    // .toml files are read as toml, anything else as json
+    pub fn load(path: &str) -> SearchConfig {
+        let text = fs::read_to_string(path).expect("couldn't read search file ;(");
+        if is_toml(path) {
+            toml::from_str(&text).unwrap_or_else(|e| panic!("couldn't parse {path} ;( {e}"))
+        } else {
+            serde_json::from_str(&text).unwrap_or_else(|e| panic!("couldn't parse {path} ;( {e}"))
+        }
+    }
+}
+
+// one training run of a search
+#[derive(Clone, Debug)]
+pub struct Trial {
+    pub id: usize,
+    pub params: Params,
+    // successive halving round, 0 for grid and random search
+    pub rung: usize,
+    // hyperband bracket, 0 otherwise
+    pub bracket: usize,
+    pub epochs: i32,
+    // objective of the best epoch, lower is better (see Monitor::score)
+    pub score: f64,
+    // metrics of that epoch
+    pub best_epoch: i32,
+    pub loss: f64,
+    pub val_loss: Option<f64>,
+    pub val_accuracy: Option<f64>,
+}
+
+// Runs the trials of a strategy on already loaded samples, in parallel on rayon's pool.
+// the base config needs a data section for val_split and the transform, the objective
+// usually needs val_split > 0. every trial starts from scratch with the base seed,
+// successive halving retrains survivors with the bigger budget rather than resuming them
+pub struct Search {
+    base: Config,
+    config: SearchConfig,
+    verbose: bool,
+}
+
+impl Search {
+    pub fn new(base: Config, config: SearchConfig) -> Search {
+        // caught here rather than after the search file is half run
+        if config.strategy == Strategy::Grid {
+            for (path, domain) in &config.space.params {
+                assert!(
+                    matches!(domain, Domain::Choice(_) | Domain::Int { .. }),
+                    "grid search needs Choice or Int domains, {path} is continuous ;("
+                );
+            }
+        }
+        Search {
+            base,
+            config,
+            verbose: false,
+        }
+    }
+    // print a line per finished trial
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+    // every trial run, best first: trials trained for more epochs (the ones that made it
+    // further in successive halving) rank above the rest, then by score
+    pub fn run(&self, x: &[Mat], y: &[Mat]) -> Vec<Trial> {
+        let mut rng = ChaCha8Rng::seed_from_u64(self.config.seed);
+        let space = &self.config.space;
+        let epochs = self.base.training.epochs;
+        let mut trials = match self.config.strategy {
+            Strategy::Grid => {
+                let params = space.grid().into_iter().map(|p| (p, 0, 0, epochs));
+                self.run_all(params.collect(), 0, x, y)
+            }
+            Strategy::Random { trials } => {
+                let params = (0..trials).map(|_| (space.sample(&mut rng), 0, 0, epochs));
+                self.run_all(params.collect(), 0, x, y)
+            }
+            Strategy::SuccessiveHalving {
+                trials,
+                min_epochs,
+                eta,
+            } => {
+                assert!(eta >= 2, "eta has to be at least 2 ;(");
+                assert!(trials >= 1, "successive halving needs at least 1 trial ;(");
+                let params = (0..trials).map(|_| space.sample(&mut rng)).collect();
+                let rungs = rungs(trials, eta);
+                self.halving(params, min_epochs, eta, rungs, 0, 0, x, y)
+            }
+            Strategy::Hyperband { max_epochs, eta } => {
+                assert!(eta >= 2, "eta has to be at least 2 ;(");
+                let eta_f = eta as f64;
+                // s_max + 1 brackets, bracket s starts eta^s times as many trials as the
+                // last one with 1/eta^s of the epochs
+                let s_max = (libm::log(max_epochs as f64) / libm::log(eta_f) + 1e-9) as i32;
+                let mut trials = vec![];
+                for s in (0..=s_max).rev() {
+                    let n = libm::ceil((s_max + 1) as f64 / (s + 1) as f64 * eta_f.powi(s));
+                    let min_epochs = ((max_epochs as f64 / eta_f.powi(s)) as i32).max(1);
+                    let params = (0..n as usize).map(|_| space.sample(&mut rng)).collect();
+                    let bracket = (s_max - s) as usize;
+                    let first_id = trials.len();
+                    let rungs = s as usize + 1;
+                    trials.extend(
+                        self.halving(params, min_epochs, eta, rungs, bracket, first_id, x, y),
+                    );
+                }
+                trials
+            }
+        };
+        trials.sort_by(|a, b| b.epochs.cmp(&a.epochs).then(a.score.total_cmp(&b.score)));
+        trials
+    }
+    #[allow(clippy::too_many_arguments)]
+    fn halving(
+        &self,
+        params: Vec<Params>,
+        min_epochs: i32,
+        eta: usize,
+        rungs: usize,
+        bracket: usize,
+        first_id: usize,
+        x: &[Mat],
+        y: &[Mat],
+    ) -> Vec<Trial> {
+        let mut all = vec![];
+        let mut survivors = params;
+        let mut epochs = min_epochs;
+        for rung in 0..rungs {
+            let runs = survivors
+                .iter()
+                .map(|p| (p.clone(), rung, bracket, epochs))
+                .collect();
+            let mut results = self.run_all(runs, first_id + all.len(), x, y);
+            results.sort_by(|a, b| a.score.total_cmp(&b.score));
+            let keep = (results.len() / eta).max(1);
+            survivors = results[..keep].iter().map(|t| t.params.clone()).collect();
+            all.extend(results);
+            epochs *= eta as i32;
+        }
+        all
+    }
+    // (params, rung, bracket, epochs) of every trial, ids count up from first_id
+    fn run_all(
+        &self,
+        runs: Vec<(Params, usize, usize, i32)>,
+        first_id: usize,
+        x: &[Mat],
+        y: &[Mat],
+    ) -> Vec<Trial> {
+        runs.into_par_iter()
+            .enumerate()
+            .map(|(i, (params, rung, bracket, epochs))| {
+                let trial = self.trial(first_id + i, params, rung, bracket, epochs, x, y);
+                if self.verbose {
+                    println!(
+                        "trial:{} rung:{} epochs:{} score:{:.4} {}",
+                        trial.id,
+                        trial.rung,
+                        trial.epochs,
+                        trial.score,
+                        serde_json::to_string(&trial.params).unwrap()
+                    );
+                }
+                trial
+            })
+            .collect()
+    }
+    #[allow(clippy::too_many_arguments)]
+    fn trial(
+        &self,
+        id: usize,
+        params: Params,
+        rung: usize,
+        bracket: usize,
+        epochs: i32,
+        x: &[Mat],
+        y: &[Mat],
+    ) -> Trial {
+        let mut config = apply(&self.base, &params);
+        config.training.epochs = epochs;
+        let history = config.train_on(x, y, vec![]).history;
+        let objective = self.config.objective;
+        let best = history
+            .iter()
+            .min_by(|a, b| objective.score(a).total_cmp(&objective.score(b)))
+            .expect("a trial trained for no epochs ;(");
+        Trial {
+            id,
+            params,
+            rung,
+            bracket,
+            epochs,
+            score: objective.score(best),
+            best_epoch: best.epoch,
+            loss: best.loss,
+            val_loss: best.val_loss,
+            val_accuracy: best.val_accuracy,
+        }
+    }
+}
+
+// rounds successive halving needs to get from n trials down to one
+fn rungs(n: usize, eta: usize) -> usize {
+    let mut rungs = 1;
+    let mut left = n;
+    while left > 1 {
+        left = (left / eta).max(1);
+        rungs += 1;
+    }
+    rungs
+}
+
+// one row per trial in the given order: rank, id, bracket, rung, epochs, one column per
+// searched path (values as json), then score and the metrics of the best epoch
+pub fn write_leaderboard(path: &str, trials: &[Trial]) {
+    let mut writer = csv::Writer::from_path(path).expect("couldn't create leaderboard ;(");
+    let paths: Vec<String> = trials
+        .first()
+        .map(|t| t.params.keys().cloned().collect())
+        .unwrap_or_default();
+    let mut header: Vec<String> = ["rank", "trial", "bracket", "rung", "epochs"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    header.extend(paths.iter().cloned());
+    header.extend(
+        ["score", "best_epoch", "loss", "val_loss", "val_accuracy"]
+            .iter()
+            .map(|s| s.to_string()),
+    );
+    writer
+        .write_record(&header)
+        .expect("couldn't write leaderboard ;(");
+    let optional = |v: Option<f64>| v.map(|v| v.to_string()).unwrap_or_default();
+    for (rank, trial) in trials.iter().enumerate() {
+        let mut record = vec![
+            (rank + 1).to_string(),
+            trial.id.to_string(),
+            trial.bracket.to_string(),
+            trial.rung.to_string(),
+            trial.epochs.to_string(),
+        ];
+        record.extend(paths.iter().map(|p| trial.params[p].to_string()));
+        record.extend([
+            trial.score.to_string(),
+            trial.best_epoch.to_string(),
+            trial.loss.to_string(),
+            optional(trial.val_loss),
+            optional(trial.val_accuracy),
+        ]);
+        writer
+            .write_record(&record)
+            .expect("couldn't write leaderboard ;(");
+    }
+    writer.flush().expect("couldn't write leaderboard ;(");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DataConfig, LayerConfig, ModelConfig};
+    use crate::nn::loss::Loss;
+
+    fn base() -> Config {
+        Config {
+            model: ModelConfig {
+                input: 4,
+                image: None,
+                layers: vec![LayerConfig::Dense {
+                    units: 3,
+                    init: Default::default(),
+                }],
+                loss: Loss::MeanSquaredError,
+            },
+            training: Default::default(),
+            data: None,
+        }
+    }
+
+    #[test]
+    fn grid_is_the_cartesian_product() {
+        let space = SearchSpace::new()
+            .choice("training.lr", vec![0.1, 0.01])
+            .int("training.batch_size", 1, 3);
+        let grid = space.grid();
+        assert_eq!(grid.len(), 6);
+        for (i, params) in grid.iter().enumerate() {
+            assert_eq!(params["training.batch_size"], Value::from(i as i64 / 2 + 1));
+            assert_eq!(params["training.lr"], Value::from([0.1, 0.01][i % 2]));
+        }
+    }
+
+    #[test]
+    fn samples_stay_in_their_domain() {
+        let space = SearchSpace::new()
+            .log_uniform("training.lr", 1e-4, 1e-1)
+            .uniform("a", -1.0, 1.0)
+            .int("b", 2, 4);
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        for _ in 0..100 {
+            let params = space.sample(&mut rng);
+            let lr = params["training.lr"].as_f64().unwrap();
+            assert!((1e-4..=1e-1).contains(&lr));
+            assert!((-1.0..=1.0).contains(&params["a"].as_f64().unwrap()));
+            assert!((2..=4).contains(&params["b"].as_i64().unwrap()));
+        }
+    }
+
+    #[test]
+    fn apply_writes_dotted_paths() {
+        let params = Params::from([
+            ("training.lr".to_string(), Value::from(0.5)),
+            ("model.layers.0.Dense.units".to_string(), Value::from(7)),
+        ]);
+        let config = apply(&base(), &params);
+        assert_eq!(config.training.lr, 0.5);
+        assert!(matches!(
+            config.model.layers[0],
+            LayerConfig::Dense { units: 7, .. }
+        ));
+    }
+
+    #[test]
+    #[should_panic]
+    fn apply_rejects_missing_paths() {
+        let params = Params::from([("training.nope".to_string(), Value::from(1))]);
+        apply(&base(), &params);
+    }
+
+    #[test]
+    fn halving_rounds_reach_one_trial() {
+        assert_eq!(rungs(1, 3), 1);
+        assert_eq!(rungs(27, 3), 4);
+        assert_eq!(rungs(10, 3), 3);
+    }
+
+    // 3 classes, the feature of the class is shifted up by 1
+    fn dataset() -> (Vec<Mat>, Vec<Mat>) {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let mut x = vec![];
+        let mut y = vec![];
+        for i in 0..24 {
+            let mut xi = Mat::from_vec((0..4).map(|_| rng.gen_range(0.0..1.0)).collect(), 4, 1);
+            xi[(i % 3, 0)] += 1.0;
+            let mut yi = Mat::new(3, 1);
+            yi[(i % 3, 0)] = 1.0;
+            x.push(xi);
+            y.push(yi);
+        }
+        (x, y)
+    }
+
+    // trials train for 2 epochs unless the strategy sets them
+    fn search(strategy: Strategy, space: SearchSpace) -> Search {
+        let mut base = base();
+        base.training.epochs = 2;
+        base.data = Some(DataConfig {
+            train: String::new(),
+            test: None,
+            val_split: 0.25,
+            augment: false,
+            transform: vec![],
+        });
+        let config = SearchConfig {
+            strategy,
+            objective: Monitor::ValLoss,
+            seed: 3,
+            space,
+        };
+        Search::new(base, config)
+    }
+
+    fn halving(trials: usize) -> Search {
+        let strategy = Strategy::SuccessiveHalving {
+            trials,
+            min_epochs: 1,
+            eta: 2,
+        };
+        search(
+            strategy,
+            SearchSpace::new().log_uniform("training.lr", 1e-3, 1.0),
+        )
+    }
+
+    fn sorted_ids(trials: &[Trial]) -> Vec<usize> {
+        let mut ids: Vec<usize> = trials.iter().map(|t| t.id).collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn successive_halving_ranks_and_writes_the_leaderboard() {
+        let (x, y) = dataset();
+        let trials = halving(4).run(&x, &y);
+        // 4 trials for 1 epoch, the best 2 for 2 and the best one for 4
+        assert_eq!(trials.len(), 7);
+        let epochs: Vec<i32> = trials.iter().map(|t| t.epochs).collect();
+        assert_eq!(epochs, [4, 2, 2, 1, 1, 1, 1]);
+        for pair in trials.windows(2) {
+            if pair[0].epochs == pair[1].epochs {
+                assert!(pair[0].score <= pair[1].score);
+            }
+        }
+        assert_eq!(sorted_ids(&trials), (0..7).collect::<Vec<_>>());
+
+        let path = std::env::temp_dir().join(format!("kek_leaderboard_{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        write_leaderboard(path, &trials);
+        let text = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines[0],
+            "rank,trial,bracket,rung,epochs,training.lr,score,best_epoch,loss,val_loss,val_accuracy"
+        );
+        assert_eq!(lines.len(), 8);
+        for (rank, (line, trial)) in lines[1..].iter().zip(&trials).enumerate() {
+            let fields: Vec<&str> = line.split(',').collect();
+            assert_eq!(fields.len(), 11);
+            assert_eq!(fields[0], (rank + 1).to_string());
+            assert_eq!(fields[1], trial.id.to_string());
+            assert_eq!(fields[3], trial.rung.to_string());
+            assert_eq!(fields[4], trial.epochs.to_string());
+            assert_eq!(fields[6], trial.score.to_string());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "successive halving needs at least 1 trial")]
+    fn successive_halving_needs_a_trial() {
+        let (x, y) = dataset();
+        halving(0).run(&x, &y);
+    }
+
+    #[test]
+    fn grid_and_random_train_every_trial_for_the_full_epochs() {
+        let (x, y) = dataset();
+        let space = SearchSpace::new()
+            .choice("training.lr", vec![0.1, 0.01])
+            .int("training.batch_size", 4, 5);
+        let trials = search(Strategy::Grid, space.clone()).run(&x, &y);
+        assert_eq!(trials.len(), 4);
+        // ids follow the grid order
+        let mut by_id = trials.clone();
+        by_id.sort_by_key(|t| t.id);
+        let params: Vec<Params> = by_id.into_iter().map(|t| t.params).collect();
+        assert_eq!(params, space.grid());
+
+        let space = SearchSpace::new().log_uniform("training.lr", 1e-3, 1.0);
+        let random = search(Strategy::Random { trials: 3 }, space).run(&x, &y);
+        assert_eq!(random.len(), 3);
+        for trials in [&trials, &random] {
+            assert!(trials
+                .iter()
+                .all(|t| t.epochs == 2 && t.rung == 0 && t.bracket == 0));
+            assert!(trials.windows(2).all(|pair| pair[0].score <= pair[1].score));
+        }
+        assert_eq!(sorted_ids(&random), [0, 1, 2]);
+    }
+
+    #[test]
+    fn hyperband_brackets() {
+        let (x, y) = dataset();
+        let strategy = Strategy::Hyperband {
+            max_epochs: 4,
+            eta: 2,
+        };
+        let space = SearchSpace::new().log_uniform("training.lr", 1e-3, 1.0);
+        let trials = search(strategy, space).run(&x, &y);
+        // bracket 0: 4 trials for 1 epoch, 2 for 2 and 1 for 4
+        // bracket 1: 3 trials for 2 epochs and 1 for 4, bracket 2: 3 trials for 4 epochs
+        assert_eq!(trials.len(), 14);
+        assert_eq!(sorted_ids(&trials), (0..14).collect::<Vec<_>>());
+        let epochs = |bracket: usize| {
+            let mut epochs: Vec<i32> = trials
+                .iter()
+                .filter(|t| t.bracket == bracket)
+                .map(|t| t.epochs)
+                .collect();
+            epochs.sort();
+            epochs
+        };
+        assert_eq!(epochs(0), [1, 1, 1, 1, 2, 2, 4]);
+        assert_eq!(epochs(1), [2, 2, 2, 4]);
+        assert_eq!(epochs(2), [4, 4, 4]);
+    }
+
+    #[test]
+    #[should_panic(expected = "grid search needs Choice or Int domains, training.lr is continuous")]
+    fn grid_rejects_continuous_domains() {
+        let space =
+            SearchSpace::new()
+                .int("training.batch_size", 4, 5)
+                .uniform("training.lr", 0.01, 0.1);
+        search(Strategy::Grid, space);
+    }
+}
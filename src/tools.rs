@@ -1,3 +1,2 @@
-#[allow(dead_code)]
 pub mod activations;
 pub mod matrix;
@@ -1,34 +1,224 @@
+use std::fmt::Debug;
+
 use libm::*;
 
-pub fn sigmoid(x: f64) -> f64 {
-    return exp(x) / (1.0 + exp(x));
+// An element wise non linearity paired with its derivative. both take the pre activation x,
+// so df(x) is d f / d x at the same point f was evaluated
+pub trait ActivationFn: Debug {
+    fn f(&self, x: f64) -> f64;
+    fn df(&self, x: f64) -> f64;
 }
-pub fn dsigmoid(x: f64) -> f64 {
-    return x * (1.0 - x);
+
+#[derive(Clone, Copy, Debug)]
+pub struct Sigmoid;
+
+impl ActivationFn for Sigmoid {
+    // only ever exponentiates non positive numbers, so large inputs don't overflow to NaN
+    fn f(&self, x: f64) -> f64 {
+        if x >= 0.0 {
+            1.0 / (1.0 + exp(-x))
+        } else {
+            let e = exp(x);
+            e / (1.0 + e)
+        }
+    }
+    fn df(&self, x: f64) -> f64 {
+        let s = self.f(x);
+        s * (1.0 - s)
+    }
 }
-pub fn relu(x: f64) -> f64 {
-    if x < 0.0 {
-        return 0.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Tanh;
+
+impl ActivationFn for Tanh {
+    fn f(&self, x: f64) -> f64 {
+        libm::tanh(x)
+    }
+    fn df(&self, x: f64) -> f64 {
+        let t = libm::tanh(x);
+        1.0 - t * t
     }
-    return x;
 }
-pub fn drelu(x: f64) -> f64 {
-    if x < 0.0 {
-        return 0.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Relu;
+
+impl ActivationFn for Relu {
+    fn f(&self, x: f64) -> f64 {
+        x.max(0.0)
+    }
+    fn df(&self, x: f64) -> f64 {
+        if x < 0.0 {
+            0.0
+        } else {
+            1.0
+        }
     }
-    return 1.0;
 }
 
-pub fn tanh(x: f64) -> f64 {
-    return libm::tanh(x);
+// slope * x for negative x, layers::activation::Prelu learns the slope
+#[derive(Clone, Copy, Debug)]
+pub struct LeakyRelu {
+    pub slope: f64,
+}
+
+impl ActivationFn for LeakyRelu {
+    fn f(&self, x: f64) -> f64 {
+        if x < 0.0 {
+            self.slope * x
+        } else {
+            x
+        }
+    }
+    fn df(&self, x: f64) -> f64 {
+        if x < 0.0 {
+            self.slope
+        } else {
+            1.0
+        }
+    }
 }
-pub fn dtanh(x: f64) -> f64 {
-    return 1.0 - (x * x);
+
+// Clevert et al. 2015, alpha * (e^x - 1) for negative x
+#[derive(Clone, Copy, Debug)]
+pub struct Elu {
+    pub alpha: f64,
 }
 
-pub fn abs(x: f64) -> f64 {
-    if x < 0.0 {
-        return -1.0 * x;
+impl ActivationFn for Elu {
+    fn f(&self, x: f64) -> f64 {
+        if x > 0.0 {
+            x
+        } else {
+            self.alpha * expm1(x)
+        }
+    }
+    fn df(&self, x: f64) -> f64 {
+        if x > 0.0 {
+            1.0
+        } else {
+            self.alpha * exp(x)
+        }
+    }
+}
+
+// Klambauer et al. 2017, a scaled elu with the self normalizing constants of the paper
+#[derive(Clone, Copy, Debug)]
+pub struct Selu;
+
+const SELU_SCALE: f64 = 1.050_700_987_355_480_5;
+const SELU_ALPHA: f64 = 1.673_263_242_354_377_2;
+
+impl ActivationFn for Selu {
+    fn f(&self, x: f64) -> f64 {
+        SELU_SCALE * Elu { alpha: SELU_ALPHA }.f(x)
+    }
+    fn df(&self, x: f64) -> f64 {
+        SELU_SCALE * Elu { alpha: SELU_ALPHA }.df(x)
+    }
+}
+
+// exact form x * Phi(x) with the standard normal cdf, not the tanh approximation
+#[derive(Clone, Copy, Debug)]
+pub struct Gelu;
+
+impl ActivationFn for Gelu {
+    fn f(&self, x: f64) -> f64 {
+        x * normal_cdf(x)
+    }
+    fn df(&self, x: f64) -> f64 {
+        let pdf = exp(-0.5 * x * x) / sqrt(2.0 * std::f64::consts::PI);
+        normal_cdf(x) + x * pdf
+    }
+}
+
+fn normal_cdf(x: f64) -> f64 {
+    0.5 * (1.0 + erf(x / std::f64::consts::SQRT_2))
+}
+
+// x * sigmoid(x), also known as SiLU
+#[derive(Clone, Copy, Debug)]
+pub struct Swish;
+
+impl ActivationFn for Swish {
+    fn f(&self, x: f64) -> f64 {
+        x * Sigmoid.f(x)
+    }
+    fn df(&self, x: f64) -> f64 {
+        let s = Sigmoid.f(x);
+        s + x * s * (1.0 - s)
+    }
+}
+
+// x * tanh(softplus(x))
+#[derive(Clone, Copy, Debug)]
+pub struct Mish;
+
+impl ActivationFn for Mish {
+    fn f(&self, x: f64) -> f64 {
+        x * libm::tanh(Softplus.f(x))
+    }
+    fn df(&self, x: f64) -> f64 {
+        let t = libm::tanh(Softplus.f(x));
+        t + x * (1.0 - t * t) * Sigmoid.f(x)
+    }
+}
+
+// ln(1 + e^x), written so it neither overflows for large x nor loses precision for small
+#[derive(Clone, Copy, Debug)]
+pub struct Softplus;
+
+impl ActivationFn for Softplus {
+    fn f(&self, x: f64) -> f64 {
+        x.max(0.0) + log1p(exp(-fabs(x)))
+    }
+    fn df(&self, x: f64) -> f64 {
+        Sigmoid.f(x)
+    }
+}
+
+// x / (1 + |x|)
+#[derive(Clone, Copy, Debug)]
+pub struct Softsign;
+
+impl ActivationFn for Softsign {
+    fn f(&self, x: f64) -> f64 {
+        x / (1.0 + fabs(x))
+    }
+    fn df(&self, x: f64) -> f64 {
+        let d = 1.0 + fabs(x);
+        1.0 / (d * d)
     }
-    return x;
+}
+
+// piecewise linear sigmoid, clamp(x / 6 + 1 / 2, 0, 1) like pytorch
+#[derive(Clone, Copy, Debug)]
+pub struct HardSigmoid;
+
+impl ActivationFn for HardSigmoid {
+    fn f(&self, x: f64) -> f64 {
+        (x / 6.0 + 0.5).clamp(0.0, 1.0)
+    }
+    fn df(&self, x: f64) -> f64 {
+        if x > -3.0 && x < 3.0 {
+            1.0 / 6.0
+        } else {
+            0.0
+        }
+    }
+}
+
+// plain functions for Mat::map
+pub fn sigmoid(x: f64) -> f64 {
+    Sigmoid.f(x)
+}
+pub fn relu(x: f64) -> f64 {
+    Relu.f(x)
+}
+pub fn tanh(x: f64) -> f64 {
+    Tanh.f(x)
+}
+pub fn abs(x: f64) -> f64 {
+    fabs(x)
 }
@@ -1,10 +1,18 @@
 use core::fmt;
 
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use std::ops::{Add, Index, IndexMut, Mul, Sub};
 
+thread_local! {
+    // set inside Mat::seeded, rand_mat uses thread_rng otherwise
+    static INIT_RNG: RefCell<Option<ChaCha8Rng>> = const { RefCell::new(None) };
+}
+
 // Define your struct
-#[derive(Clone)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Mat {
     buffer: Vec<f64>,
     size: usize,
@@ -13,7 +21,6 @@ pub struct Mat {
 }
 
 // Implement methods for the struct
-#[allow(dead_code)]
 impl Mat {
     // Constructor for a new matrix
     pub fn new(row: usize, col: usize) -> Mat {
@@ -27,10 +34,7 @@ impl Mat {
         }
     }
     pub fn val_mat(row: usize, col: usize, val: f64) -> Mat {
-        let mut buffer: Vec<f64> = vec![0.0; row * col];
-        for i in 0..row * col {
-            buffer[i] = val
-        }
+        let buffer: Vec<f64> = vec![val; row * col];
         Mat {
             buffer,
             size: row * col,
@@ -41,9 +45,14 @@ impl Mat {
     // Constructor for a matrix with random values
     pub fn rand_mat(row: usize, col: usize, min: f64, max: f64) -> Mat {
         let mut buffer: Vec<f64> = vec![0.0; row * col];
-        for i in 0..row * col {
-            buffer[i] = rand::thread_rng().gen_range(min..max);
-        }
+        INIT_RNG.with_borrow_mut(|seeded| {
+            for ele in buffer.iter_mut() {
+                *ele = match seeded {
+                    Some(rng) => rng.gen_range(min..max),
+                    None => rand::thread_rng().gen_range(min..max),
+                };
+            }
+        });
         Mat {
             buffer,
             size: row * col,
@@ -51,6 +60,14 @@ impl Mat {
             col,
         }
     }
+    // runs f with every rand_mat on this thread drawing from a rng seeded with seed,
+    // so e.g. a model's initial weights can be reproduced
+    pub fn seeded<T>(seed: u64, f: impl FnOnce() -> T) -> T {
+        let outer = INIT_RNG.replace(Some(ChaCha8Rng::seed_from_u64(seed)));
+        let out = f();
+        INIT_RNG.set(outer);
+        out
+    }
     pub fn zeroes_like(other: &Mat) -> Mat {
         let row = other.row();
         let col = other.col();
@@ -77,6 +94,13 @@ impl Mat {
     pub fn col(&self) -> usize {
         self.col
     }
+    // raw row major view of the elements
+    pub fn as_slice(&self) -> &[f64] {
+        &self.buffer
+    }
+    pub fn as_mut_slice(&mut self) -> &mut [f64] {
+        &mut self.buffer
+    }
     // Function to map 2D index to 1D index
     pub fn map_2_to_1(x: usize, y: usize, width: usize) -> usize {
         width * y + x
@@ -92,12 +116,6 @@ impl Mat {
         }
     }
 
-    // Getter function to get the index in the buffer for given coordinates
-    fn get(&self, x: usize, y: usize) -> usize {
-        assert!(y < self.row && x < self.col);
-        Mat::map_2_to_1(x, y, self.col)
-    }
-
     // Setter function to set value at given coordinates
     pub fn set(&mut self, x: usize, y: usize, val: f64) {
         assert!(y < self.row && x < self.col);
@@ -135,8 +153,8 @@ impl Mat {
         assert_eq!(self.col, other.col);
         assert_eq!(self.row, other.row);
         let mut buffer = self.buffer.clone();
-        for i in 0..self.size {
-            buffer[i] *= other.buffer[i];
+        for (ele, o) in buffer.iter_mut().zip(&other.buffer) {
+            *ele *= o;
         }
         Mat {
             buffer,
@@ -148,8 +166,8 @@ impl Mat {
     pub fn scaler_mul(&self, val: f64) -> Mat {
         let mut buffer = self.buffer.clone();
 
-        for i in 0..self.size {
-            buffer[i] *= val;
+        for ele in buffer.iter_mut() {
+            *ele *= val;
         }
         Mat {
             buffer,
@@ -163,28 +181,145 @@ impl Mat {
             (self.row, self.col)
         }
     }
+    // scale so the largest magnitude becomes 1, an all zero matrix is left untouched
     pub fn normalize_self(&mut self) {
-        let mut max = libm::fabs(self[(0, 0)]);
+        let mut max = 0.0;
         for ele in &self.buffer {
             if max < libm::fabs(*ele) {
-                max = *ele;
+                max = libm::fabs(*ele);
             }
         }
-        for i in 0..self.buffer.len() {
-            self.buffer[i] /= max;
+        if max == 0.0 {
+            return;
+        }
+        for ele in self.buffer.iter_mut() {
+            *ele /= max;
         }
     }
+    // (column, row) of the largest element, the first one on ties
     pub fn get_max(&self) -> (usize, usize) {
-        let mut max = libm::fabs(self[(0, 0)]);
+        assert!(!self.buffer.is_empty());
         let mut max_index = 0;
-        for ele in self.buffer.iter().enumerate() {
-            if max < libm::fabs(*ele.1) {
-                max = *ele.1;
-                max_index = ele.0;
+        for (i, ele) in self.buffer.iter().enumerate() {
+            if *ele > self.buffer[max_index] {
+                max_index = i;
             }
         }
         (max_index % self.col, max_index / self.col)
     }
+    // columns side by side, every column needs the same number of rows
+    pub fn hstack(cols: &[Mat]) -> Mat {
+        assert!(!cols.is_empty());
+        let row = cols[0].row;
+        let col: usize = cols.iter().map(|c| c.col).sum();
+        let mut mat = Mat::new(row, col);
+        let mut offset = 0;
+        for c in cols {
+            assert_eq!(c.row, row);
+            for i in 0..row {
+                for j in 0..c.col {
+                    mat[(i, offset + j)] = c[(i, j)];
+                }
+            }
+            offset += c.col;
+        }
+        mat
+    }
+    // copy of column j as a (row, 1) matrix
+    pub fn column(&self, j: usize) -> Mat {
+        assert!(j < self.col);
+        let mut mat = Mat::new(self.row, 1);
+        for i in 0..self.row {
+            mat[(i, 0)] = self[(i, j)];
+        }
+        mat
+    }
+    // columns start..end as a new matrix
+    pub fn cols(&self, start: usize, end: usize) -> Mat {
+        assert!(start <= end && end <= self.col);
+        let mut mat = Mat::new(self.row, end - start);
+        for i in 0..self.row {
+            for j in start..end {
+                mat[(i, j - start)] = self[(i, j)];
+            }
+        }
+        mat
+    }
+    pub fn columns(&self) -> Vec<Mat> {
+        (0..self.col).map(|j| self.column(j)).collect()
+    }
+    // rows start..end as a new matrix
+    pub fn rows(&self, start: usize, end: usize) -> Mat {
+        assert!(start <= end && end <= self.row);
+        Mat::from_vec(
+            self.buffer[start * self.col..end * self.col].to_vec(),
+            end - start,
+            self.col,
+        )
+    }
+    // the given rows in that order
+    pub fn select_rows(&self, rows: &[usize]) -> Mat {
+        let mut buffer = Vec::with_capacity(rows.len() * self.col);
+        for &r in rows {
+            assert!(r < self.row);
+            buffer.extend_from_slice(&self.buffer[r * self.col..(r + 1) * self.col]);
+        }
+        Mat::from_vec(buffer, rows.len(), self.col)
+    }
+    // matrices on top of each other, every one needs the same number of columns
+    pub fn vstack(rows: &[Mat]) -> Mat {
+        assert!(!rows.is_empty());
+        let col = rows[0].col;
+        let mut buffer = Vec::with_capacity(rows.iter().map(|r| r.size).sum());
+        for r in rows {
+            assert_eq!(r.col, col);
+            buffer.extend_from_slice(&r.buffer);
+        }
+        let row = rows.iter().map(|r| r.row).sum();
+        Mat::from_vec(buffer, row, col)
+    }
+    // add a (row, 1) column to every column
+    pub fn add_column(&self, other: &Mat) -> Mat {
+        assert_eq!(other.shape(), (self.row, 1));
+        let mut mat = self.clone();
+        for i in 0..self.row {
+            for j in 0..self.col {
+                mat[(i, j)] += other[(i, 0)];
+            }
+        }
+        mat
+    }
+    // sum every row, giving a (row, 1) column
+    pub fn sum_columns(&self) -> Mat {
+        let mut mat = Mat::new(self.row, 1);
+        for i in 0..self.row {
+            mat[(i, 0)] = self.sum(i);
+        }
+        mat
+    }
+    // matrix product computed in block_size sized tiles, what `&a * &b` does with BLOCK_SIZE.
+    // public so benches/hot_paths.rs can compare tile sizes
+    pub fn mul_blocked(&self, other: &Mat, block_size: usize) -> Mat {
+        assert!(block_size > 0);
+        assert_eq!(self.col, other.row);
+        let other_transposed = other.transpose();
+        let mut mat_result = Mat::new(self.row, other.col);
+
+        for i in (0..self.row).step_by(block_size) {
+            for j in (0..other_transposed.row).step_by(block_size) {
+                for k in (0..self.col).step_by(block_size) {
+                    for ii in i..(i + block_size).min(self.row) {
+                        for jj in j..(j + block_size).min(other_transposed.row) {
+                            for kk in k..(k + block_size).min(self.col) {
+                                mat_result[(ii, jj)] += self[(ii, kk)] * other_transposed[(jj, kk)];
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        mat_result
+    }
     pub fn sum_all(&self) -> f64 {
         let mut sum = 0.0;
         for ele in &self.buffer {
@@ -200,6 +335,8 @@ impl Index<(usize, usize)> for Mat {
 
     fn index(&self, index: (usize, usize)) -> &Self::Output {
         let (i, j) = index;
+        assert!(i < self.row);
+        assert!(j < self.col);
         &self.buffer[Mat::map_2_to_1(j, i, self.col)]
     }
 }
@@ -222,8 +359,8 @@ impl Add for &Mat {
         assert_eq!(self.col, other.col);
         assert_eq!(self.row, other.row);
         let mut buffer = self.buffer.clone();
-        for i in 0..self.size {
-            buffer[i] += other.buffer[i];
+        for (ele, o) in buffer.iter_mut().zip(&other.buffer) {
+            *ele += o;
         }
         Mat {
             buffer,
@@ -242,8 +379,8 @@ impl Sub for &Mat {
         assert_eq!(self.col, other.col);
         assert_eq!(self.row, other.row);
         let mut buffer = self.buffer.clone();
-        for i in 0..self.size {
-            buffer[i] -= other.buffer[i];
+        for (ele, o) in buffer.iter_mut().zip(&other.buffer) {
+            *ele -= o;
         }
         Mat {
             buffer,
@@ -254,6 +391,9 @@ impl Sub for &Mat {
     }
 }
 
+// tile size of the blocked matmul, `cargo bench -- matmul_block_size` compares other sizes
+pub const BLOCK_SIZE: usize = 32;
+
 // Implement multiplication for the struct
 impl Mul for &Mat {
     type Output = Mat;
@@ -299,26 +439,7 @@ impl Mul for &Mat {
         mat_result
         */
 
-        assert_eq!(self.col, other.row);
-        let other_transposed = other.transpose();
-        let mut mat_result = Mat::new(self.row, other.col);
-
-        let block_size = 32; // Adjust the block size based on your cache size and architecture
-
-        for i in (0..self.row).step_by(block_size) {
-            for j in (0..other_transposed.row).step_by(block_size) {
-                for k in (0..self.col).step_by(block_size) {
-                    for ii in i..(i + block_size).min(self.row) {
-                        for jj in j..(j + block_size).min(other_transposed.row) {
-                            for kk in k..(k + block_size).min(self.col) {
-                                mat_result[(ii, jj)] += self[(ii, kk)] * other_transposed[(jj, kk)];
-                            }
-                        }
-                    }
-                }
-            }
-        }
-        mat_result
+        self.mul_blocked(other, BLOCK_SIZE)
     }
 }
 
@@ -330,8 +451,187 @@ impl fmt::Display for &Mat {
                 write!(f, "{},", self.buffer[Mat::map_2_to_1(j, i, self.col)])
                     .expect("idk dawg this printin error");
             }
-            write!(f, "\n").expect("idk dawg this printin error");
+            writeln!(f).expect("idk dawg this printin error");
+        }
+        writeln!(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use crate::tools::matrix::*;
+
+    fn mat(row: usize, col: usize) -> impl Strategy<Value = Mat> {
+        prop::collection::vec(-10.0..10.0f64, row * col)
+            .prop_map(move |buffer| Mat::from_vec(buffer, row, col))
+    }
+
+    // (a, b) with a's columns matching b's rows
+    fn mul_pair(max: usize) -> impl Strategy<Value = (Mat, Mat)> {
+        (1..max, 1..max, 1..max).prop_flat_map(|(n, k, m)| (mat(n, k), mat(k, m)))
+    }
+
+    fn naive_mul(a: &Mat, b: &Mat) -> Mat {
+        let mut out = Mat::new(a.row(), b.col());
+        for i in 0..a.row() {
+            for j in 0..b.col() {
+                for k in 0..a.col() {
+                    out[(i, j)] += a[(i, k)] * b[(k, j)];
+                }
+            }
         }
-        write!(f, "\n")
+        out
+    }
+
+    fn same(a: &Mat, b: &Mat) -> bool {
+        a.shape() == b.shape() && a.as_slice() == b.as_slice()
+    }
+
+    fn assert_close(a: &Mat, b: &Mat) {
+        assert_eq!(a.shape(), b.shape());
+        for (x, y) in a.as_slice().iter().zip(b.as_slice()) {
+            assert!(
+                (x - y).abs() <= 1e-9 * (1.0 + x.abs().max(y.abs())),
+                "{x} != {y}"
+            );
+        }
+    }
+
+    proptest! {
+        // sizes go past the block size of 32 so partial blocks are covered
+        #[test]
+        fn blocked_mul_matches_naive((a, b) in mul_pair(70)) {
+            assert_close(&(&a * &b), &naive_mul(&a, &b));
+        }
+
+        #[test]
+        fn mul_is_associative(
+            (a, b, c) in (1..8usize, 1..8usize, 1..8usize, 1..8usize)
+                .prop_flat_map(|(n, k, l, m)| (mat(n, k), mat(k, l), mat(l, m)))
+        ) {
+            assert_close(&(&(&a * &b) * &c), &(&a * &(&b * &c)));
+        }
+
+        #[test]
+        fn transpose_of_product((a, b) in mul_pair(12)) {
+            assert_close(&(&a * &b).transpose(), &(&b.transpose() * &a.transpose()));
+        }
+
+        #[test]
+        fn transpose_is_an_involution(a in (1..12usize, 1..12usize).prop_flat_map(|(r, c)| mat(r, c))) {
+            let back = a.transpose().transpose();
+            prop_assert!(same(&back, &a));
+        }
+
+        #[test]
+        fn mul_distributes_over_add(
+            (a, b, c) in (1..8usize, 1..8usize, 1..8usize)
+                .prop_flat_map(|(n, k, m)| (mat(n, k), mat(k, m), mat(k, m)))
+        ) {
+            assert_close(&(&a * &(&b + &c)), &(&(&a * &b) + &(&a * &c)));
+            assert_close(&(&(&b + &c).transpose() * &a.transpose()), &(&(&a * &b) + &(&a * &c)).transpose());
+        }
+
+        #[test]
+        fn add_and_sub_are_inverse(
+            (a, b) in (1..10usize, 1..10usize).prop_flat_map(|(r, c)| (mat(r, c), mat(r, c)))
+        ) {
+            assert_close(&(&(&a + &b) - &b), &a);
+            prop_assert!(same(&(&a + &b), &(&b + &a)));
+        }
+
+        #[test]
+        fn stacking_round_trips(
+            (a, b) in (1..6usize, 1..6usize, 1..6usize).prop_flat_map(|(r, c, d)| (mat(r, c), mat(r, d)))
+        ) {
+            let wide = Mat::hstack(&[a.clone(), b.clone()]);
+            prop_assert!(same(&wide.cols(0, a.col()), &a));
+            prop_assert!(same(&wide.cols(a.col(), wide.col()), &b));
+            let tall = Mat::vstack(&[a.transpose(), b.transpose()]);
+            prop_assert!(same(&tall.transpose(), &wide));
+            prop_assert!(same(&tall.rows(a.col(), tall.row()), &b.transpose()));
+        }
+
+        #[test]
+        fn get_max_finds_the_largest_element(a in (1..8usize, 1..8usize).prop_flat_map(|(r, c)| mat(r, c))) {
+            let (j, i) = a.get_max();
+            for &v in a.as_slice() {
+                prop_assert!(v <= a[(i, j)]);
+            }
+        }
+
+        #[test]
+        fn normalize_keeps_signs(a in (1..8usize, 1..8usize).prop_flat_map(|(r, c)| mat(r, c))) {
+            let mut n = a.clone();
+            n.normalize_self();
+            let max = n.as_slice().iter().fold(0.0f64, |m, v| m.max(v.abs()));
+            prop_assert!(max == 0.0 || (max - 1.0).abs() < 1e-12);
+            for (x, y) in a.as_slice().iter().zip(n.as_slice()) {
+                prop_assert!(x * y >= 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn get_max_with_only_negative_values() {
+        let a = Mat::from_vec(vec![-3.0, -0.5, -2.0, -7.0], 2, 2);
+        assert_eq!(a.get_max(), (1, 0));
+    }
+
+    #[test]
+    fn normalize_by_a_negative_extreme() {
+        // the largest magnitude comes after a smaller positive value, which used to
+        // leave the signed value as the divisor
+        let mut a = Mat::from_vec(vec![1.0, -4.0, 2.0], 1, 3);
+        a.normalize_self();
+        assert_eq!(a.as_slice(), &[0.25, -1.0, 0.5]);
+    }
+
+    #[test]
+    fn normalize_leaves_zeros_alone() {
+        let mut a = Mat::new(2, 2);
+        a.normalize_self();
+        assert_eq!(a.as_slice(), &[0.0; 4]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn index_past_the_last_column() {
+        // would otherwise read (1, 0)
+        let a = Mat::new(2, 2);
+        let _ = a[(0, 2)];
+    }
+
+    #[test]
+    #[should_panic]
+    fn index_past_the_last_row() {
+        let mut a = Mat::new(2, 2);
+        a[(2, 0)] = 1.0;
+    }
+
+    #[test]
+    #[should_panic]
+    fn mul_needs_matching_inner_sizes() {
+        let _ = &Mat::new(2, 3) * &Mat::new(2, 3);
+    }
+
+    #[test]
+    #[should_panic]
+    fn add_needs_equal_shapes() {
+        let _ = &Mat::new(2, 3) + &Mat::new(3, 2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn ele_mul_needs_equal_shapes() {
+        let _ = Mat::new(2, 3).ele_mul(&Mat::new(2, 2));
+    }
+
+    #[test]
+    #[should_panic]
+    fn vstack_needs_equal_columns() {
+        let _ = Mat::vstack(&[Mat::new(1, 2), Mat::new(1, 3)]);
     }
 }